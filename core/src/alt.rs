@@ -0,0 +1,324 @@
+use std::collections::BinaryHeap;
+
+use crate::builder::haversine_distance;
+use crate::graph::{BasicState, Graph};
+use crate::query::QueryGraph;
+
+/// Landmarks for ALT (A*, landmarks, triangle inequality).
+///
+/// Stores the road distance from a few landmark nodes to every node. For any
+/// landmark l the triangle inequality gives |d(l,u) - d(l,t)| <= d(u,t), which
+/// is usually a much tighter lower bound than the straight line distance.
+/// Every road is stored in both directions, so one table per landmark is enough.
+pub struct Landmarks {
+    nodes: Vec<usize>,
+    dist: Vec<Vec<f64>>, // dist[i][v] = distance from landmark i to node v
+}
+
+impl Landmarks {
+    /// Picks `count` landmarks by farthest point selection: each new landmark
+    /// is the node farthest away from all landmarks picked so far. Nodes no
+    /// landmark can reach count as infinitely far, so every component gets one
+    /// before a component gets a second.
+    pub fn select(graph: &Graph, count: usize) -> Landmarks {
+        let n = graph.nodes().len();
+        let query = QueryGraph::new(graph);
+        let mut landmarks = Landmarks { nodes: Vec::new(), dist: Vec::new() };
+        if n == 0 {
+            return landmarks;
+        }
+
+        // start from the node farthest away from node 0 instead of node 0 itself
+        let (from_first, _) = query.dijkstra(0, None);
+        let mut closest = from_first;
+        while landmarks.nodes.len() < count.min(n) {
+            let next = (0..n)
+                .filter(|v| !landmarks.nodes.contains(v))
+                .max_by(|&a, &b| closest[a].total_cmp(&closest[b]))
+                .unwrap();
+            let (dist, _) = query.dijkstra(next, None);
+            if landmarks.nodes.is_empty() {
+                closest = dist.clone();
+            } else {
+                for (c, d) in closest.iter_mut().zip(&dist) {
+                    *c = c.min(*d);
+                }
+            }
+            landmarks.nodes.push(next);
+            landmarks.dist.push(dist);
+        }
+        landmarks
+    }
+
+    pub fn nodes(&self) -> &[usize] {
+        &self.nodes
+    }
+
+    /// Distance from landmark `i` to the real node `v`
+    pub fn distance(&self, i: usize, v: usize) -> f64 {
+        self.dist[i][v]
+    }
+
+    // lower bound from the landmark distances of two nodes
+    fn bound(from: impl Iterator<Item = f64>, to: &[f64]) -> f64 {
+        let mut best: f64 = 0.0;
+        for (a, &b) in from.zip(to) {
+            match (a.is_finite(), b.is_finite()) {
+                (true, true) => best = best.max((a - b).abs()),
+                // one landmark reaches only one of them, so they are not connected
+                (true, false) | (false, true) => return f64::INFINITY,
+                (false, false) => {}
+            }
+        }
+        best
+    }
+}
+
+impl Graph {
+    /// Selects `count` landmarks and keeps their distance tables with the graph.
+    /// The tables refer to node indices, so do this after any reordering.
+    pub fn with_landmarks(mut self, count: usize) -> Self {
+        self.landmarks = Some(Landmarks::select(&self, count));
+        self
+    }
+
+    pub fn landmarks(&self) -> Option<&Landmarks> {
+        self.landmarks.as_ref()
+    }
+}
+
+/// Lower bounds on the road distance to one goal, the larger of the
+/// haversine distance and the landmark bound.
+pub struct LowerBound<'q, 'a> {
+    query: &'q QueryGraph<'a>,
+    goal: usize,
+    goal_dist: Vec<f64>,
+}
+
+impl LowerBound<'_, '_> {
+    pub fn estimate(&self, u: usize) -> f64 {
+        let (a, b) = (self.query.node(u), self.query.node(self.goal));
+        let straight = haversine_distance(a.lat(), a.lon(), b.lat(), b.lon());
+        let Some(landmarks) = self.query.graph().landmarks() else {
+            return straight;
+        };
+        let alt = if self.query.is_virtual(u) {
+            Landmarks::bound(self.query.landmark_distances(u).into_iter(), &self.goal_dist)
+        } else {
+            Landmarks::bound(landmarks.dist.iter().map(|d| d[u]), &self.goal_dist)
+        };
+        straight.max(alt)
+    }
+}
+
+impl<'a> QueryGraph<'a> {
+    /// Lower bounds towards `goal` for pruning and as A* heuristic
+    pub fn lower_bound(&self, goal: usize) -> LowerBound<'_, 'a> {
+        LowerBound { query: self, goal, goal_dist: self.landmark_distances(goal) }
+    }
+
+    // Distance from every landmark to `v`. Virtual nodes are not in the tables,
+    // their distances come from the real nodes they are wired to.
+    fn landmark_distances(&self, v: usize) -> Vec<f64> {
+        let Some(landmarks) = self.graph().landmarks() else {
+            return Vec::new();
+        };
+        if !self.is_virtual(v) {
+            return landmarks.dist.iter().map(|d| d[v]).collect();
+        }
+
+        let mut result = vec![f64::INFINITY; landmarks.nodes.len()];
+        let (reach, _) = self.virtual_reach(v);
+        for (&u, &du) in reach.iter().filter(|&(&u, _)| !self.is_virtual(u)) {
+            for (r, d) in result.iter_mut().zip(&landmarks.dist) {
+                *r = r.min(du + d[u]);
+            }
+        }
+        result
+    }
+
+    /// Dijkstra from both ends at once, stops as soon as the two searches
+    /// can't improve the best meeting point anymore. Roads are stored in both
+    /// directions, so the backward search walks the same adjacency.
+    pub fn bidirectional_dijkstra(&self, start: usize, goal: usize) -> Option<(Vec<usize>, f64)> {
+        if start == goal {
+            return Some((vec![start], 0.0));
+        }
+        let n = self.node_count();
+        let mut dist = [vec![f64::INFINITY; n], vec![f64::INFINITY; n]];
+        let mut parent = [vec![None; n], vec![None; n]];
+        dist[0][start] = 0.0;
+        dist[1][goal] = 0.0;
+        let mut heaps = [
+            BinaryHeap::from([BasicState { node: start, dist: 0.0 }]),
+            BinaryHeap::from([BasicState { node: goal, dist: 0.0 }]),
+        ];
+        let mut best = f64::INFINITY;
+        let mut meeting = None;
+
+        loop {
+            let top = |heap: &BinaryHeap<BasicState>| heap.peek().map_or(f64::INFINITY, |s| s.dist);
+            let (top_f, top_b) = (top(&heaps[0]), top(&heaps[1]));
+            if top_f + top_b >= best || (top_f.is_infinite() && top_b.is_infinite()) {
+                break;
+            }
+            // expand the side with the smaller frontier
+            let side = if top_f <= top_b { 0 } else { 1 };
+            let BasicState { node: u, dist: du } = heaps[side].pop().unwrap();
+            if du > dist[side][u] {
+                continue;
+            }
+            for edge in self.neighbors(u) {
+                let v = edge.node_index;
+                let dv = du + edge.edge_data.length_m;
+                if dv < dist[side][v] {
+                    dist[side][v] = dv;
+                    parent[side][v] = Some(u);
+                    heaps[side].push(BasicState { node: v, dist: dv });
+                }
+                if dist[side][v] + dist[1 - side][v] < best {
+                    best = dist[side][v] + dist[1 - side][v];
+                    meeting = Some(v);
+                }
+            }
+        }
+
+        let meeting = meeting?;
+        let mut path = vec![meeting];
+        let mut cur = meeting;
+        while let Some(prev) = parent[0][cur] {
+            path.push(prev);
+            cur = prev;
+        }
+        path.reverse();
+        cur = meeting;
+        while let Some(next) = parent[1][cur] {
+            path.push(next);
+            cur = next;
+        }
+        Some((path, best))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EdgeData, EdgeSnap, Neighbor, Node};
+    use crate::test_util::Grid;
+
+    /// n × n grid, 0.001° apart with haversine lengths, plus a few missing
+    /// edges so shortest paths have to detour
+    fn build_grid(n: usize) -> Graph {
+        Grid::new(n, n).build(|u, v, length_m| (!(v == u + 1 && u % n == n / 2 && u >= n)).then_some(EdgeData { way_id: 1, length_m }))
+    }
+
+    #[test]
+    fn landmark_tables_match_dijkstra() {
+        let graph = build_grid(6).with_landmarks(3);
+        let landmarks = graph.landmarks().unwrap();
+        assert_eq!(landmarks.nodes().len(), 3);
+        let query = QueryGraph::new(&graph);
+        for (i, &l) in landmarks.nodes().iter().enumerate() {
+            let (dist, _) = query.dijkstra(l, None);
+            for (v, d) in dist.iter().enumerate() {
+                assert_eq!(landmarks.distance(i, v), *d);
+            }
+        }
+    }
+
+    #[test]
+    fn landmarks_are_spread_out() {
+        let graph = build_grid(6).with_landmarks(2);
+        let landmarks = graph.landmarks().unwrap().nodes();
+        // the farthest pair of the grid are opposite corners
+        let query = QueryGraph::new(&graph);
+        let (dist, _) = query.dijkstra(landmarks[0], None);
+        let farthest = dist.iter().copied().fold(0.0, f64::max);
+        assert_eq!(dist[landmarks[1]], farthest);
+    }
+
+    #[test]
+    fn bound_never_overestimates() {
+        let graph = build_grid(6).with_landmarks(4);
+        let query = QueryGraph::new(&graph);
+        let mut tighter = 0;
+        for goal in 0..graph.nodes().len() {
+            let (dist, _) = query.dijkstra(goal, None);
+            let bound = query.lower_bound(goal);
+            for (u, d) in dist.iter().enumerate() {
+                let estimate = bound.estimate(u);
+                assert!(estimate <= d + 1e-6, "{} -> {}: {} > {}", u, goal, estimate, d);
+                let (a, b) = (query.node(u), query.node(goal));
+                if estimate > haversine_distance(a.lat(), a.lon(), b.lat(), b.lon()) + 1.0 {
+                    tighter += 1;
+                }
+            }
+        }
+        // the wall in the middle of the grid makes the landmarks beat the straight line
+        assert!(tighter > 0);
+    }
+
+    #[test]
+    fn bound_for_virtual_nodes() {
+        let graph = build_grid(4).with_landmarks(2);
+        let mut query = QueryGraph::new(&graph);
+        // halfway between node 0 and node 4 (one row up)
+        let edge_length = query.edge_length(0, 4).unwrap();
+        let snap = EdgeSnap { from: 0, to: 4, way_id: 1, fraction: 0.5, lat: 0.0005, lon: 0.0, distance: 0.0, edge_length };
+        let v = query.split_edges(&[snap])[0];
+        assert!(query.is_virtual(v));
+
+        let (dist, _) = query.dijkstra(v, None);
+        let bound = query.lower_bound(v);
+        for (u, d) in dist.iter().enumerate() {
+            assert!(bound.estimate(u) <= d + 1e-6);
+            assert!(query.lower_bound(u).estimate(v) <= d + 1e-6);
+        }
+    }
+
+    #[test]
+    fn bound_is_infinite_across_components() {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001), Node::new(3, 0.0, 0.002)];
+        let edge = EdgeData { way_id: 1, length_m: 111.0 };
+        let adj = vec![
+            vec![Neighbor { osm_id: 2, node_index: 1, edge_data: edge }],
+            vec![Neighbor { osm_id: 1, node_index: 0, edge_data: edge }],
+            vec![],
+        ];
+        let graph = Graph::new(nodes, adj).with_landmarks(2);
+        // one landmark per component
+        assert!(graph.landmarks().unwrap().nodes().contains(&2));
+
+        let query = QueryGraph::new(&graph);
+        assert!(query.lower_bound(2).estimate(0).is_infinite());
+        assert!(query.lower_bound(1).estimate(0).is_finite());
+    }
+
+    #[test]
+    fn bidirectional_matches_dijkstra() {
+        let graph = build_grid(6);
+        let query = QueryGraph::new(&graph);
+        for start in [0, 5, 17, 30, 35] {
+            let (dist, _) = query.dijkstra(start, None);
+            for (goal, expected) in dist.iter().enumerate() {
+                let (path, d) = query.bidirectional_dijkstra(start, goal).unwrap();
+                assert!((d - expected).abs() < 1e-6);
+                assert_eq!(path.first(), Some(&start));
+                assert_eq!(path.last(), Some(&goal));
+                // the path really has that length
+                let walked: f64 = path.windows(2).map(|w| query.edge_length(w[0], w[1]).unwrap()).sum();
+                assert!((walked - d).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn bidirectional_unreachable() {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001)];
+        let graph = Graph::new(nodes, vec![vec![], vec![]]);
+        let query = QueryGraph::new(&graph);
+        assert!(query.bidirectional_dijkstra(0, 1).is_none());
+    }
+}
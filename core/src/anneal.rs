@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng, rng};
+
+use crate::diversity::DiversityFilter;
+use crate::graph::{fresh_seed, Graph};
+use crate::budget::{Budget, Meter};
+use crate::model::{Neighbor, Objective, PathQuery, PathsResponse, RouteError, SearchOutcome, StopReason};
+use crate::query::QueryGraph;
+
+// temperature at the start and at the end of the budget, costs are fractions
+// of the route length so a worse route by 0.1 is still taken early on
+const START_TEMPERATURE: f64 = 0.1;
+const END_TEMPERATURE: f64 = 0.001;
+// a moved or inserted waypoint wanders at most this many edges
+const MAX_WALK: usize = 20;
+const MAX_WAYPOINTS: usize = 8;
+// the first waypoint is picked among nodes at most this much worse than the best one
+const INITIAL_SLACK_M: f64 = 50.0;
+
+/// Settings of the simulated annealing route optimizer
+#[derive(Debug, Clone)]
+pub struct AnnealingOptions {
+    pub time_budget: Duration,
+    pub max_iterations: usize,
+    pub objective: Objective,
+    pub seed: Option<u64>, // fixed seed for reproducible runs
+}
+
+impl Default for AnnealingOptions {
+    fn default() -> Self {
+        AnnealingOptions {
+            time_budget: Duration::from_secs(2),
+            max_iterations: 20_000,
+            objective: Objective::default(),
+            seed: None,
+        }
+    }
+}
+
+/// A route found by the optimizer and what the objective made of it
+#[derive(Debug, Clone)]
+pub struct Annealed {
+    pub path: Vec<usize>,
+    pub length: f64,
+    pub cost: f64,
+}
+
+impl Graph {
+    pub fn get_paths_annealing(&self, req: &PathQuery, options: &AnnealingOptions) -> Result<PathsResponse, RouteError> {
+        let seed = options.seed.unwrap_or_else(fresh_seed);
+        let options = &AnnealingOptions { seed: Some(seed), ..options.clone() };
+        let mut res = self.get_paths_with(req, |query, start, goal, target_distance, tol, search| {
+            let mut diversity = DiversityFilter::new(req.max_overlap);
+            // via legs split the run between them like they split the distance
+            let share = if req.target_distance > 0.0 { (target_distance / req.target_distance).min(1.0) } else { 1.0 };
+            let leg_options = AnnealingOptions {
+                time_budget: options.time_budget.mul_f64(share),
+                max_iterations: ((options.max_iterations as f64 * share).ceil() as usize).max(1),
+                ..options.clone()
+            };
+            let (routes, stopped) = query.anneal(start, goal, target_distance, tol, &leg_options, &req.budget);
+            let paths = routes
+                .into_iter()
+                // stitched shortest paths often come back the way they went
+                .filter(|route| search.repeats.permits(&route.path))
+                .filter(|route| search.ascent.is_none_or(|range| range.contains(query.ascent(&route.path))))
+                .filter(|route| diversity.accept(query, &route.path))
+                .take(req.amount)
+                .map(|route| (route.path, route.length))
+                .collect();
+            SearchOutcome { paths, stopped }
+        })?;
+        res.seed = Some(seed);
+        Ok(res)
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Simulated annealing over waypoints between `start` and `goal`.
+    ///
+    /// A route is the chain of shortest paths start -> w1 -> ... -> goal. Each
+    /// step moves, adds or drops one waypoint and keeps the change if it lowers
+    /// the objective, or with a probability that shrinks as the temperature
+    /// cools down over the budget. Returns every distinct route within `tol`
+    /// of `target_distance` it came across, best first, and the reason if
+    /// `budget` cut the run short.
+    pub fn anneal(&self, start: usize, goal: usize, target_distance: f64, tol: f64, options: &AnnealingOptions, budget: &Budget) -> (Vec<Annealed>, Option<StopReason>) {
+        let rng = options.seed.map_or_else(|| StdRng::from_rng(&mut rng()), StdRng::seed_from_u64);
+        let mut annealer = Annealer { query: self, start, goal, target_distance, options, rng, legs: HashMap::new(), cached_nodes: 0 };
+        let mut meter = budget.meter();
+        let routes = annealer.run(tol, &mut meter);
+        (routes, meter.stopped())
+    }
+}
+
+// shortest path between two stops and its length
+type Leg = (Vec<usize>, f64);
+
+struct Annealer<'q, 'a> {
+    query: &'q QueryGraph<'a>,
+    start: usize,
+    goal: usize,
+    target_distance: f64,
+    options: &'q AnnealingOptions,
+    rng: StdRng,
+    legs: HashMap<(usize, usize), Option<Leg>>,
+    cached_nodes: usize, // in all of `legs`, for the memory estimate
+}
+
+struct Route {
+    waypoints: Vec<usize>,
+    path: Vec<usize>,
+    length: f64,
+    cost: f64,
+}
+
+impl Annealer<'_, '_> {
+    fn run(&mut self, tol: f64, meter: &mut Meter) -> Vec<Annealed> {
+        let Some(mut current) = self.initial_route() else {
+            return Vec::new();
+        };
+        let mut found: HashMap<Vec<usize>, (f64, f64)> = HashMap::new();
+        let target_distance = self.target_distance;
+        let mut record = |route: &Route| {
+            if (route.length - target_distance).abs() <= tol {
+                found.insert(route.path.clone(), (route.length, route.cost));
+            }
+        };
+        record(&current);
+
+        let started = Instant::now();
+        let budget = self.options.time_budget.as_secs_f64();
+        for iteration in 0..self.options.max_iterations {
+            let elapsed = started.elapsed().as_secs_f64();
+            if elapsed >= budget || meter.exhausted(iteration, self.cached_nodes * size_of::<usize>()) {
+                break;
+            }
+            // cool down with whichever runs out first, time or iterations
+            let progress = (elapsed / budget).max(iteration as f64 / self.options.max_iterations as f64);
+            let temperature = START_TEMPERATURE * (END_TEMPERATURE / START_TEMPERATURE).powf(progress);
+
+            let waypoints = self.neighbor(&current.waypoints);
+            let Some(candidate) = self.route(waypoints) else {
+                continue;
+            };
+            let delta = candidate.cost - current.cost;
+            if delta <= 0.0 || self.rng.random::<f64>() < (-delta / temperature).exp() {
+                record(&candidate);
+                current = candidate;
+            }
+        }
+
+        let mut routes: Vec<Annealed> = found
+            .into_iter()
+            .map(|(path, (length, cost))| Annealed { path, length, cost })
+            .collect();
+        routes.sort_by(|a, b| a.cost.total_cmp(&b.cost).then_with(|| a.path.cmp(&b.path)));
+        routes
+    }
+
+    // one waypoint whose detour comes closest to the target distance
+    fn initial_route(&mut self) -> Option<Route> {
+        let cutoff = Some(self.target_distance * 2.0);
+        let (from_start, _) = self.query.dijkstra(self.start, cutoff);
+        let (to_goal, _) = self.query.dijkstra(self.goal, cutoff);
+        let deviation = |w: usize| (from_start[w] + to_goal[w] - self.target_distance).abs();
+
+        let best = (0..self.query.node_count())
+            .filter(|&w| from_start[w].is_finite() && to_goal[w].is_finite())
+            .min_by(|&a, &b| deviation(a).total_cmp(&deviation(b)))?;
+        // start somewhere random among the nodes about as good as the best one
+        let close: Vec<usize> = (0..self.query.node_count())
+            .filter(|&w| from_start[w].is_finite() && to_goal[w].is_finite() && deviation(w) <= deviation(best) + INITIAL_SLACK_M)
+            .collect();
+        let waypoint = close[self.rng.random_range(0..close.len())];
+        self.route(vec![waypoint])
+    }
+
+    // a random change to the waypoints
+    fn neighbor(&mut self, waypoints: &[usize]) -> Vec<usize> {
+        let mut next = waypoints.to_vec();
+        let roll = self.rng.random_range(0..10);
+        if (roll < 6 && !next.is_empty()) || (roll < 8 && next.len() >= MAX_WAYPOINTS) {
+            let i = self.rng.random_range(0..next.len());
+            next[i] = self.walk(next[i]);
+        } else if roll < 8 || next.is_empty() {
+            // new waypoint somewhere around one of the route's anchors
+            let i = self.rng.random_range(0..=next.len());
+            let anchor = if i == 0 { self.start } else { next[i - 1] };
+            let waypoint = self.walk(anchor);
+            next.insert(i, waypoint);
+        } else {
+            let i = self.rng.random_range(0..next.len());
+            next.remove(i);
+        }
+        next
+    }
+
+    fn walk(&mut self, mut node: usize) -> usize {
+        for _ in 0..self.rng.random_range(1..=MAX_WALK) {
+            let neighbors: Vec<usize> = self.query.neighbors(node).map(|n| n.node_index).collect();
+            if neighbors.is_empty() {
+                break;
+            }
+            node = neighbors[self.rng.random_range(0..neighbors.len())];
+        }
+        node
+    }
+
+    fn leg(&mut self, from: usize, to: usize) -> Option<Leg> {
+        if let Some(leg) = self.legs.get(&(from, to)) {
+            return leg.clone();
+        }
+        let leg = self.query.shortest_path(from, to);
+        self.cached_nodes += leg.as_ref().map_or(0, |(path, _)| path.len());
+        self.legs.insert((from, to), leg.clone());
+        leg
+    }
+
+    fn route(&mut self, waypoints: Vec<usize>) -> Option<Route> {
+        let mut stops = vec![self.start];
+        stops.extend_from_slice(&waypoints);
+        stops.push(self.goal);
+
+        let mut path = vec![self.start];
+        let mut length = 0.0;
+        for pair in stops.windows(2) {
+            let (leg, leg_length) = self.leg(pair[0], pair[1])?;
+            path.extend_from_slice(&leg[1..]);
+            length += leg_length;
+        }
+        let cost = self.cost(&path, length);
+        Some(Route { waypoints, path, length, cost })
+    }
+
+    fn cost(&self, path: &[usize], length: f64) -> f64 {
+        let objective = &self.options.objective;
+        let mut cost = objective.distance_weight * (length - self.target_distance).abs() / self.target_distance;
+        if length <= 0.0 {
+            return cost;
+        }
+
+        let mut driven: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut reused = 0.0;
+        let mut penalized = 0.0;
+        for pair in path.windows(2) {
+            let edge = self.edge(pair[0], pair[1]);
+            let times = driven.entry((pair[0].min(pair[1]), pair[0].max(pair[1]))).or_default();
+            *times += 1;
+            if *times > 1 {
+                reused += edge.edge_data.length_m;
+            }
+            let highway = self.query.graph().tag(edge.edge_data.way_id, "highway");
+            if let Some(weight) = highway.and_then(|h| objective.highway_penalties.get(h)) {
+                penalized += weight * edge.edge_data.length_m;
+            }
+        }
+        cost += (objective.reuse_weight * reused + penalized) / length;
+        cost
+    }
+
+    // the shortest edge between two adjacent nodes
+    fn edge(&self, u: usize, v: usize) -> &Neighbor {
+        self.query
+            .neighbors(u)
+            .filter(|n| n.node_index == v)
+            .min_by(|a, b| a.edge_data.length_m.total_cmp(&b.edge_data.length_m))
+            .expect("consecutive path nodes are adjacent")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::haversine_distance;
+    use crate::model::{EdgeData, Node, RepeatPolicy};
+    use crate::test_util::Grid;
+
+    /// n × n grid 0.001° apart with haversine lengths, node `row * n + col`,
+    /// every block its own way and the middle column a primary road
+    fn build_grid(n: usize) -> Graph {
+        let mut way_tags = HashMap::new();
+        let mut way_id = 0;
+        let graph = Grid::new(n, n).build(|u, v, length_m| {
+            way_id += 1;
+            let highway = if v == u + n && u % n == n / 2 { "primary" } else { "residential" };
+            way_tags.insert(way_id, HashMap::from([("highway".to_string(), highway.to_string())]));
+            Some(EdgeData { way_id, length_m })
+        });
+        graph.with_way_tags(way_tags)
+    }
+
+    fn options(seed: u64) -> AnnealingOptions {
+        AnnealingOptions { time_budget: Duration::from_secs(10), max_iterations: 2_000, seed: Some(seed), ..Default::default() }
+    }
+
+    #[test]
+    fn hits_the_target_distance() {
+        let graph = build_grid(9);
+        let query = QueryGraph::new(&graph);
+        // corner to corner is 16 blocks of 111 m, 28 blocks come to 3116 m
+        let direct = haversine_distance(0.0, 0.0, 0.008, 0.008);
+        let routes = query.anneal(0, 80, 3100.0, 100.0, &options(1), &Budget::default()).0;
+        assert!(!routes.is_empty());
+        for route in &routes {
+            assert!((route.length - 3100.0).abs() <= 100.0);
+            assert_eq!(route.path.first(), Some(&0));
+            assert_eq!(route.path.last(), Some(&80));
+            assert!(route.length > direct);
+        }
+        // best first
+        assert!(routes.windows(2).all(|w| w[0].cost <= w[1].cost));
+    }
+
+    #[test]
+    fn same_seed_same_routes() {
+        let graph = build_grid(7);
+        let query = QueryGraph::new(&graph);
+        let a = query.anneal(0, 48, 2000.0, 100.0, &options(7), &Budget::default()).0;
+        let b = query.anneal(0, 48, 2000.0, 100.0, &options(7), &Budget::default()).0;
+        assert_eq!(a.iter().map(|r| &r.path).collect::<Vec<_>>(), b.iter().map(|r| &r.path).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn penalties_steer_the_route() {
+        let graph = build_grid(9);
+        let query = QueryGraph::new(&graph);
+        let primary_share = |route: &Annealed| {
+            let on_primary: f64 = route
+                .path
+                .windows(2)
+                .filter(|w| (w[0] % 9 == 4) && (w[1] % 9 == 4))
+                .map(|w| query.edge_length(w[0], w[1]).unwrap())
+                .sum();
+            on_primary / route.length
+        };
+
+        // left alone the detour stays off the primary road
+        let plain = &query.anneal(0, 8, 2500.0, 100.0, &options(3), &Budget::default()).0[0];
+        assert_eq!(primary_share(plain), 0.0);
+        let mut avoid = options(3);
+        avoid.objective.highway_penalties.insert("residential".to_string(), 1.0);
+        let best = &query.anneal(0, 8, 2500.0, 100.0, &avoid, &Budget::default()).0[0];
+        assert!(primary_share(best) > 0.5);
+
+        let mut reuse = options(3);
+        reuse.objective.reuse_weight = 10.0;
+        let best = &query.anneal(0, 8, 2500.0, 100.0, &reuse, &Budget::default()).0[0];
+        let mut segments: Vec<(usize, usize)> = best.path.windows(2).map(|w| (w[0].min(w[1]), w[0].max(w[1]))).collect();
+        let total = segments.len();
+        segments.sort();
+        segments.dedup();
+        assert_eq!(segments.len(), total);
+    }
+
+    #[test]
+    fn requests_keep_to_the_repeat_policy() {
+        let graph = build_grid(9);
+        let req = PathQuery {
+            start_lat: 0.0,
+            start_lon: 0.0,
+            goal_lat: 0.0,
+            goal_lon: 0.008,
+            amount: 20,
+            target_distance: 2500.0,
+            tol: 100.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via: Vec::new(),
+            via_order: Default::default(),
+            avoid: Default::default(),
+            repeats: RepeatPolicy::NoNodeRepeats,
+            budget: Default::default(),
+            seed: None,
+            ascent: None,
+            novelty: None,
+        };
+        let res = graph.get_paths_annealing(&req, &options(5)).unwrap();
+        assert!(!res.paths.is_empty());
+        for path in &res.paths {
+            let mut ids: Vec<u64> = path.nodes.iter().map(|n| n.id()).collect();
+            ids.sort();
+            ids.dedup();
+            assert_eq!(ids.len(), path.nodes.len());
+        }
+    }
+
+    #[test]
+    fn unreachable_goal_gives_nothing() {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001)];
+        let graph = Graph::new(nodes, vec![vec![], vec![]]);
+        let query = QueryGraph::new(&graph);
+        assert!(query.anneal(0, 1, 500.0, 50.0, &options(1), &Budget::default()).0.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use crate::graph::Graph;
+use crate::model::{Avoid, Neighbor, Node};
+use crate::query::QueryGraph;
+
+/// Edges one query must not use. The shared graph stays as it is, the
+/// query graph leaves masked edges out of `neighbors`.
+pub(crate) struct Mask {
+    ways: HashSet<u64>,
+    nodes: HashSet<usize>,
+    edges: HashSet<(usize, usize)>, // real edges in or across a polygon, lower index first
+    polygons: Vec<Vec<(f64, f64)>>, // still needed for the edges of virtual nodes
+}
+
+impl Mask {
+    pub(crate) fn new(graph: &Graph, avoid: &Avoid) -> Self {
+        let ids: HashSet<u64> = avoid.node_ids.iter().copied().collect();
+        let nodes = graph.nodes().iter().enumerate().filter(|(_, node)| ids.contains(&node.id())).map(|(i, _)| i).collect();
+
+        let mut edges = HashSet::new();
+        for polygon in avoid.polygons.iter().filter(|polygon| polygon.len() >= 3) {
+            for (u, neighbors) in graph.adj().iter().enumerate() {
+                for neighbor in neighbors {
+                    let v = neighbor.node_index;
+                    if u < v && crosses(polygon, &graph.nodes()[u], &graph.nodes()[v]) {
+                        edges.insert((u, v));
+                    }
+                }
+            }
+        }
+
+        Mask {
+            ways: avoid.way_ids.iter().copied().collect(),
+            nodes,
+            edges,
+            polygons: avoid.polygons.iter().filter(|polygon| polygon.len() >= 3).cloned().collect(),
+        }
+    }
+
+    pub(crate) fn allows(&self, query: &QueryGraph, u: usize, neighbor: &Neighbor) -> bool {
+        let v = neighbor.node_index;
+        if self.ways.contains(&neighbor.edge_data.way_id) || self.nodes.contains(&u) || self.nodes.contains(&v) {
+            return false;
+        }
+        if query.is_virtual(u) || query.is_virtual(v) {
+            let (a, b) = (query.node(u), query.node(v));
+            !self.polygons.iter().any(|polygon| crosses(polygon, a, b))
+        } else {
+            !self.edges.contains(&(u.min(v), u.max(v)))
+        }
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Closes the roads `avoid` names for this query only
+    pub fn avoiding(mut self, avoid: &Avoid) -> Self {
+        self.mask = if avoid.is_empty() { None } else { Some(Mask::new(self.graph(), avoid)) };
+        self
+    }
+}
+
+// whether the segment a-b has an end inside `polygon` or crosses its outline,
+// lat/lon taken as plane coordinates which is fine for city sized polygons
+fn crosses(polygon: &[(f64, f64)], a: &Node, b: &Node) -> bool {
+    let (p, q) = ((a.lat(), a.lon()), (b.lat(), b.lon()));
+    if !bounding_boxes_overlap(polygon, p, q) {
+        return false;
+    }
+    if inside(polygon, p) || inside(polygon, q) {
+        return true;
+    }
+    let n = polygon.len();
+    (0..n).any(|k| segments_intersect(p, q, polygon[k], polygon[(k + 1) % n]))
+}
+
+fn bounding_boxes_overlap(polygon: &[(f64, f64)], p: (f64, f64), q: (f64, f64)) -> bool {
+    let (mut min_lat, mut min_lon, mut max_lat, mut max_lon) = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &(lat, lon) in polygon {
+        (min_lat, min_lon, max_lat, max_lon) = (min_lat.min(lat), min_lon.min(lon), max_lat.max(lat), max_lon.max(lon));
+    }
+    p.0.max(q.0) >= min_lat && p.0.min(q.0) <= max_lat && p.1.max(q.1) >= min_lon && p.1.min(q.1) <= max_lon
+}
+
+// even-odd ray casting
+fn inside(polygon: &[(f64, f64)], (lat, lon): (f64, f64)) -> bool {
+    let n = polygon.len();
+    let mut inside = false;
+    for k in 0..n {
+        let ((lat1, lon1), (lat2, lon2)) = (polygon[k], polygon[(k + 1) % n]);
+        if (lat1 > lat) != (lat2 > lat) && lon < lon1 + (lat - lat1) / (lat2 - lat1) * (lon2 - lon1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn segments_intersect(p1: (f64, f64), p2: (f64, f64), q1: (f64, f64), q2: (f64, f64)) -> bool {
+    let orientation = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).signum();
+    let (d1, d2) = (orientation(q1, q2, p1), orientation(q1, q2, p2));
+    let (d3, d4) = (orientation(p1, p2, q1), orientation(p1, p2, q2));
+    d1 != d2 && d3 != d4
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::haversine_distance;
+    use crate::ch::ContractionHierarchy;
+    use crate::model::{EdgeData, PathQuery, ViaOrder};
+    use crate::test_util::Grid;
+
+    /// 3 × 3 grid 0.001° apart with haversine lengths, node `row * 3 + col`,
+    /// the way id of an edge is `10 * u + v`
+    fn build_grid() -> Graph {
+        Grid::new(3, 3).with_first_id(100).build(|u, v, length_m| Some(EdgeData { way_id: (10 * u + v) as u64, length_m }))
+    }
+
+    // around the middle of the edge between node 0 and node 1
+    fn small_square() -> Vec<(f64, f64)> {
+        vec![(-0.0002, 0.0004), (-0.0002, 0.0006), (0.0002, 0.0006), (0.0002, 0.0004)]
+    }
+
+    #[test]
+    fn polygons_close_the_roads_they_touch() {
+        let graph = build_grid();
+        let avoid = Avoid { polygons: vec![small_square()], ..Default::default() };
+        let query = QueryGraph::new(&graph).avoiding(&avoid);
+        assert!(query.edge_length(0, 1).is_none());
+        assert!(query.edge_length(1, 0).is_none());
+        assert!(query.edge_length(0, 3).is_some());
+
+        // the middle node and all four roads leading to it
+        let around_center = vec![(0.0009, 0.0009), (0.0009, 0.0011), (0.0011, 0.0011), (0.0011, 0.0009)];
+        let avoid = Avoid { polygons: vec![around_center], ..Default::default() };
+        let query = QueryGraph::new(&graph).avoiding(&avoid);
+        assert_eq!(query.neighbors(4).count(), 0);
+        assert_eq!(query.neighbors(1).count(), 2);
+    }
+
+    #[test]
+    fn ways_and_nodes_are_closed_by_id() {
+        let graph = build_grid();
+        let avoid = Avoid { way_ids: vec![1], node_ids: vec![108], ..Default::default() };
+        let query = QueryGraph::new(&graph).avoiding(&avoid);
+        assert!(query.edge_length(0, 1).is_none());
+        assert!(query.edge_length(5, 8).is_none());
+        assert!(query.edge_length(7, 8).is_none());
+        assert!(query.edge_length(0, 3).is_some());
+        // the graph itself is untouched
+        assert!(QueryGraph::new(&graph).edge_length(0, 1).is_some());
+    }
+
+    fn request(avoid: Avoid) -> PathQuery {
+        PathQuery {
+            start_lat: 0.0,
+            start_lon: 0.0,
+            goal_lat: 0.0,
+            goal_lon: 0.002,
+            amount: 5,
+            target_distance: 450.0,
+            tol: 20.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via: Vec::new(),
+            via_order: ViaOrder::Ordered,
+            avoid,
+            repeats: Default::default(),
+            budget: Default::default(),
+            seed: None,
+            ascent: None,
+            novelty: None,
+        }
+    }
+
+    #[test]
+    fn searches_go_around_closed_roads() {
+        let graph = build_grid();
+        let block = haversine_distance(0.0, 0.0, 0.001, 0.0);
+        let uses_closed = |nodes: &[Node]| nodes.windows(2).any(|w| (w[0].id(), w[1].id()) == (100, 101) || (w[0].id(), w[1].id()) == (101, 100));
+
+        let open = graph.get_paths_bfs(&request(Avoid::default())).unwrap();
+        assert!(open.paths.iter().any(|path| uses_closed(&path.nodes)));
+
+        let avoid = Avoid { polygons: vec![small_square()], ..Default::default() };
+        for res in [
+            graph.get_paths_bfs(&request(avoid.clone())).unwrap(),
+            graph.get_paths_special_dijkstra(&request(avoid.clone())).unwrap(),
+            graph.get_paths_dfs(&request(avoid.clone())).unwrap(),
+        ] {
+            assert!(!res.paths.is_empty());
+            for path in &res.paths {
+                assert!(!uses_closed(&path.nodes));
+                assert!((path.distance - 4.0 * block).abs() < 20.0);
+            }
+        }
+    }
+
+    #[test]
+    fn masked_queries_do_not_use_the_contraction_hierarchy() {
+        let graph = build_grid();
+        let ch = ContractionHierarchy::build(&graph);
+        let graph = graph.with_contraction_hierarchy(ch);
+        let avoid = Avoid { way_ids: vec![1], ..Default::default() };
+        let query = QueryGraph::new(&graph).avoiding(&avoid);
+        let (path, _) = query.shortest_path(0, 1).unwrap();
+        assert_eq!(path, vec![0, 3, 4, 1]);
+    }
+}
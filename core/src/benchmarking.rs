@@ -1,5 +1,12 @@
+use std::time::{Duration, Instant};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use crate::builder::haversine_distance;
 use crate::graph::Graph;
 use crate::model::{Node, Neighbor, EdgeData};
+use crate::query::QueryGraph;
 
 /// Build an N×N grid graph (total N*N nodes), laid out row-major:
 /// node index = row*N + col, coords unused here (0,0), all edges length = 1.0
@@ -39,6 +46,194 @@ pub fn make_grid_graph(n: usize) -> Graph {
     Graph::new(nodes, adj)
 }
 
+/// Build an N×N grid graph with real coordinates (about 111 m apart) whose
+/// node indices are shuffled, like a graph built from ways in file order
+/// where neighbors in space end up far apart in memory.
+pub fn make_shuffled_grid_graph(n: usize, seed: u64) -> Graph {
+    let mut rng = StdRng::seed_from_u64(seed);
+    // position[i] = index of grid cell i in the shuffled graph
+    let mut position: Vec<usize> = (0..n * n).collect();
+    position.shuffle(&mut rng);
+
+    let mut nodes = vec![Node::new(0, 0.0, 0.0); n * n];
+    for cell in 0..(n * n) {
+        let (row, col) = (cell / n, cell % n);
+        nodes[position[cell]] = Node::new(cell as u64, row as f64 * 0.001, col as f64 * 0.001);
+    }
+
+    let mut adj = vec![Vec::new(); n * n];
+    let mut link = |a: usize, b: usize| {
+        let (u, v) = (position[a], position[b]);
+        let (from, to) = (nodes[u], nodes[v]);
+        let edge = EdgeData { way_id: 0, length_m: haversine_distance(from.lat(), from.lon(), to.lat(), to.lon()) };
+        adj[u].push(Neighbor { osm_id: b as u64, node_index: v, edge_data: edge });
+        adj[v].push(Neighbor { osm_id: a as u64, node_index: u, edge_data: edge });
+    };
+    for row in 0..n {
+        for col in 0..n {
+            let cell = row * n + col;
+            if col + 1 < n {
+                link(cell, cell + 1);
+            }
+            if row + 1 < n {
+                link(cell, cell + n);
+            }
+        }
+    }
+
+    Graph::new(nodes, adj)
+}
+
+/// Build an N×N grid of streets with `shape_nodes` extra nodes along every
+/// block, like OSM ways where most nodes only shape the road. Blocks are
+/// about 111 m long, lengths are haversine distances.
+pub fn make_street_grid_graph(n: usize, shape_nodes: usize) -> Graph {
+    let step = 0.001 / (shape_nodes + 1) as f64;
+    let mut nodes: Vec<Node> = Vec::new();
+    for row in 0..n {
+        for col in 0..n {
+            nodes.push(Node::new(nodes.len() as u64, row as f64 * 0.001, col as f64 * 0.001));
+        }
+    }
+
+    let mut adj = vec![Vec::new(); n * n];
+    // chain of shape nodes from intersection a towards b
+    let street = |a: usize, b: usize, nodes: &mut Vec<Node>, adj: &mut Vec<Vec<Neighbor>>| {
+        let (from, to) = (nodes[a], nodes[b]);
+        let (dlat, dlon) = ((to.lat() - from.lat()) / 0.001 * step, (to.lon() - from.lon()) / 0.001 * step);
+        let mut prev = a;
+        for i in 1..=shape_nodes + 1 {
+            let next = if i == shape_nodes + 1 {
+                b
+            } else {
+                nodes.push(Node::new(nodes.len() as u64, from.lat() + dlat * i as f64, from.lon() + dlon * i as f64));
+                adj.push(Vec::new());
+                nodes.len() - 1
+            };
+            let (p, q) = (nodes[prev], nodes[next]);
+            let edge = EdgeData { way_id: 0, length_m: haversine_distance(p.lat(), p.lon(), q.lat(), q.lon()) };
+            adj[prev].push(Neighbor { osm_id: q.id(), node_index: next, edge_data: edge });
+            adj[next].push(Neighbor { osm_id: p.id(), node_index: prev, edge_data: edge });
+            prev = next;
+        }
+    };
+    for row in 0..n {
+        for col in 0..n {
+            let idx = row * n + col;
+            if col + 1 < n {
+                street(idx, idx + 1, &mut nodes, &mut adj);
+            }
+            if row + 1 < n {
+                street(idx, idx + n, &mut nodes, &mut adj);
+            }
+        }
+    }
+
+    Graph::new(nodes, adj)
+}
+
+/// Runs a full Dijkstra from every node with an OSM id in `sources` and
+/// returns the total time spent searching.
+pub fn time_dijkstra(graph: &Graph, sources: &[u64]) -> Duration {
+    let indices: Vec<usize> = sources
+        .iter()
+        .map(|id| graph.nodes().iter().position(|n| n.id() == *id).expect("source not in graph"))
+        .collect();
+    let query = QueryGraph::new(graph);
+    let start = Instant::now();
+    for idx in indices {
+        let _ = query.dijkstra(idx, None);
+    }
+    start.elapsed()
+}
+
+#[cfg(test)]
+mod ordering_benchmarks {
+    use super::*;
+
+    // cargo test --release -- --ignored --nocapture hilbert
+    #[test]
+    #[ignore]
+    fn hilbert_ordering_speeds_up_dijkstra() {
+        let n = 700;
+        let sources: Vec<u64> = (0..20).map(|i| (i * 24_989) as u64 % (n * n) as u64).collect();
+
+        let shuffled = make_shuffled_grid_graph(n, 42);
+        let before = time_dijkstra(&shuffled, &sources);
+        let reordered = shuffled.reorder_hilbert();
+        let after = time_dijkstra(&reordered, &sources);
+
+        println!("{} nodes, {} searches", n * n, sources.len());
+        println!("file order:    {:?}", before);
+        println!("hilbert order: {:?}", after);
+    }
+}
+
+#[cfg(test)]
+mod point_to_point_benchmarks {
+    use super::*;
+
+    fn time<F: FnMut(usize, usize)>(pairs: &[(usize, usize)], mut search: F) -> Duration {
+        let start = Instant::now();
+        for &(s, t) in pairs {
+            search(s, t);
+        }
+        start.elapsed()
+    }
+
+    // cargo test --release -- --ignored --nocapture point_to_point
+    #[test]
+    #[ignore]
+    fn point_to_point_searches() {
+        let n = 500;
+        let graph = make_shuffled_grid_graph(n, 7).reorder_hilbert();
+        let pairs: Vec<(usize, usize)> = (0..50).map(|i| ((i * 24_989) % (n * n), (i * 7_919 + 3_331) % (n * n))).collect();
+
+        let plain = QueryGraph::new(&graph);
+        println!("{} nodes, {} queries", n * n, pairs.len());
+        println!("dijkstra:       {:?}", time(&pairs, |s, _| { plain.dijkstra(s, None); }));
+        println!("bidirectional:  {:?}", time(&pairs, |s, t| { plain.bidirectional_dijkstra(s, t); }));
+        println!("A* (haversine): {:?}", time(&pairs, |s, t| { plain.astar(s, t); }));
+
+        let start = Instant::now();
+        let graph = graph.with_landmarks(8);
+        println!("landmarks:      {:?} to preprocess", start.elapsed());
+        let alt = QueryGraph::new(&graph);
+        println!("A* (ALT):       {:?}", time(&pairs, |s, t| { alt.astar(s, t); }));
+    }
+}
+
+#[cfg(test)]
+mod ch_benchmarks {
+    use super::*;
+    use crate::ch::ContractionHierarchy;
+
+    // cargo test --release -- --ignored --nocapture contraction
+    #[test]
+    #[ignore]
+    fn contraction_hierarchy_queries() {
+        let graph = make_street_grid_graph(100, 4).reorder_hilbert();
+        let n = graph.nodes().len();
+        let pairs: Vec<(usize, usize)> = (0..200).map(|i| ((i * 24_989) % n, (i * 7_919 + 3_331) % n)).collect();
+
+        let start = Instant::now();
+        let ch = ContractionHierarchy::build(&graph);
+        println!("{} nodes, {} shortcuts, built in {:?}", n, ch.shortcut_count(), start.elapsed());
+
+        let query = QueryGraph::new(&graph);
+        let start = Instant::now();
+        for &(s, _) in &pairs {
+            let _ = query.dijkstra(s, None);
+        }
+        println!("dijkstra: {:?}", start.elapsed());
+        let start = Instant::now();
+        for &(s, t) in &pairs {
+            let _ = ch.shortest_path(s, t);
+        }
+        println!("CH:       {:?}", start.elapsed());
+    }
+}
+
 /*
 #[cfg(test)]
 mod heap_tests_bfs {
@@ -67,7 +262,7 @@ mod heap_tests_bfs {
         let accepted_road_types: &[&str] = &["residential","unclassified","track","service","tertiary","road","secondary","primary","trunk","primary_link","trunk_link","tertiary_link","secondary_link","highway",
         ];
         let graph = create_graph(file_path, accepted_road_types).expect("Failed to create graph from OSM data");
-        print!("Graph created with {} nodes and {} edges\n", graph.nodes().len(), graph.adj().len());
+        print!("Graph created with {} nodes and {} edges\n", graph.nodes().len(), graph.stats().undirected_edges);
 
         let _ = graph.bfs(1838, 1816, 1, 3000.0, 100.0);
         // on test teardown you’ll get the same dhat summary
@@ -76,7 +271,7 @@ mod heap_tests_bfs {
 */
 
 
-#[cfg(test)]
+#[cfg(all(test, feature = "dhat-heap"))]
 mod heap_tests_dijkstra {
     #[cfg(feature = "dhat-heap")]
     #[global_allocator]
@@ -103,7 +298,7 @@ mod heap_tests_dijkstra {
         let accepted_road_types: &[&str] = &["residential","unclassified","track","service","tertiary","road","secondary","primary","trunk","primary_link","trunk_link","tertiary_link","secondary_link","highway",
         ];
         let graph = create_graph(file_path, accepted_road_types).expect("Failed to create graph from OSM data");
-        print!("Graph created with {} nodes and {} edges\n", graph.nodes().len(), graph.adj().len());
+        print!("Graph created with {} nodes and {} edges\n", graph.nodes().len(), graph.stats().undirected_edges);
 
         let _ = graph.special_dijkstra(1838, 1816, 1, 10000.0, 100.0);
         // on test teardown you’ll get the same dhat summary
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::model::StopReason;
+
+// the clock is only read every this many checks
+const CLOCK_INTERVAL: usize = 256;
+
+/// Limits on how much work one search may do. A search that runs out stops
+/// and returns what it found so far together with the reason.
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub max_states: Option<usize>, // partial paths created
+    pub deadline: Option<Instant>,
+    pub max_memory_bytes: Option<usize>, // estimated from the search's own bookkeeping
+    pub cancel: Option<CancelToken>, // library only, the server never sets one
+}
+
+/// Stops a search from another thread. For callers that embed the crate, the
+/// HTTP server has no way to cancel a request and relies on the deadline.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Budget {
+    pub(crate) fn meter(&self) -> Meter<'_> {
+        Meter { budget: self, checks: 0, clock_interval: CLOCK_INTERVAL, stopped: None }
+    }
+
+    /// Meter for searches that check it rarely, e.g. once per whole
+    /// sub-search, so it reads the clock every time
+    pub(crate) fn coarse_meter(&self) -> Meter<'_> {
+        Meter { budget: self, checks: 0, clock_interval: 1, stopped: None }
+    }
+}
+
+/// Keeps track of one search against its budget. Once a limit is hit it
+/// stays hit.
+pub(crate) struct Meter<'b> {
+    budget: &'b Budget,
+    checks: usize,
+    clock_interval: usize,
+    stopped: Option<StopReason>,
+}
+
+impl Meter<'_> {
+    /// Whether the search has to stop now, given how many states it has
+    /// and roughly how many bytes they take
+    pub(crate) fn exhausted(&mut self, states: usize, bytes: usize) -> bool {
+        if self.stopped.is_none() {
+            self.stopped = self.check(states, bytes);
+        }
+        self.stopped.is_some()
+    }
+
+    fn check(&mut self, states: usize, bytes: usize) -> Option<StopReason> {
+        let budget = self.budget;
+        if budget.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Some(StopReason::Cancelled);
+        }
+        if budget.max_states.is_some_and(|max| states > max) {
+            return Some(StopReason::StateLimit);
+        }
+        if budget.max_memory_bytes.is_some_and(|max| bytes > max) {
+            return Some(StopReason::MemoryLimit);
+        }
+        self.checks += 1;
+        if self.checks.is_multiple_of(self.clock_interval) && budget.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(StopReason::Deadline);
+        }
+        None
+    }
+
+    pub(crate) fn stopped(&self) -> Option<StopReason> {
+        self.stopped
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_stop_the_meter_for_good() {
+        let budget = Budget { max_states: Some(10), ..Default::default() };
+        let mut meter = budget.meter();
+        assert!(!meter.exhausted(10, 0));
+        assert!(meter.exhausted(11, 0));
+        assert!(meter.exhausted(0, 0));
+        assert_eq!(meter.stopped(), Some(StopReason::StateLimit));
+
+        let budget = Budget { max_memory_bytes: Some(1 << 20), ..Default::default() };
+        let mut meter = budget.meter();
+        assert!(meter.exhausted(1, 2 << 20));
+        assert_eq!(meter.stopped(), Some(StopReason::MemoryLimit));
+    }
+
+    #[test]
+    fn deadline_and_cancellation() {
+        let budget = Budget { deadline: Some(Instant::now() - Duration::from_millis(1)), ..Default::default() };
+        let mut meter = budget.meter();
+        // the clock is only looked at every so often
+        let checks = (0..CLOCK_INTERVAL).take_while(|_| !meter.exhausted(0, 0)).count();
+        assert_eq!(checks, CLOCK_INTERVAL - 1);
+        assert_eq!(meter.stopped(), Some(StopReason::Deadline));
+        let mut meter = budget.coarse_meter();
+        assert!(meter.exhausted(0, 0));
+
+        let token = CancelToken::new();
+        let budget = Budget { cancel: Some(token.clone()), ..Default::default() };
+        let mut meter = budget.meter();
+        assert!(!meter.exhausted(0, 0));
+        token.cancel();
+        assert!(meter.exhausted(0, 0));
+        assert_eq!(meter.stopped(), Some(StopReason::Cancelled));
+    }
+}
@@ -27,6 +27,13 @@ pub struct GraphBuilder {
 
     pub nodes: Vec<Node>,
     pub adj: Vec<Vec<Neighbor>>,
+    pub way_tags: HashMap<u64, HashMap<String, String>>,
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GraphBuilder {
@@ -35,6 +42,7 @@ impl GraphBuilder {
             id_to_idx: HashMap::new(),
             nodes:     Vec::new(),
             adj:       Vec::new(),
+            way_tags:  HashMap::new(),
         }
     }
     
@@ -58,11 +66,14 @@ impl GraphBuilder {
     }
 
     pub fn add_way(&mut self, way: &Way) {
+        if !way.tags.is_empty() {
+            self.way_tags.insert(way.id(), way.tags.clone());
+        }
         // assume order of nodes in way means they are connected
         for i in 0..way.nodes().len() - 1 {
             // add nodes to the graph
-            let from = way.nodes()[i].clone();
-            let to = way.nodes()[i + 1].clone();
+            let from = way.nodes()[i];
+            let to = way.nodes()[i + 1];
             self.add_node(from);
             self.add_node(to);
             let edge_data = EdgeData {
@@ -74,7 +85,7 @@ impl GraphBuilder {
     }
 
     pub fn build(self) -> Graph {
-        Graph::new(self.nodes, self.adj)
+        Graph::new(self.nodes, self.adj).with_way_tags(self.way_tags)
     }
 
 }
@@ -127,7 +138,7 @@ fn add_way() {
     let node2 = Node::new(2, 52.1, 13.1);
     let node3 = Node::new(3, 52.2, 13.2);
     
-    let way = Way::new(1, vec![1,2,3],vec![node1.clone(), node2.clone(), node3.clone()]);
+    let way = Way::new(1, vec![1,2,3],vec![node1, node2, node3]);
     
     builder.add_way(&way);
     
@@ -151,10 +162,10 @@ fn add_two_ways() {
     let node2 = Node::new(2, 52.1, 13.1);
     let node3 = Node::new(3, 52.2, 13.2);
     
-    let way = Way::new(1, vec![1,2,3],vec![node1.clone(), node2.clone(), node3.clone()]);
+    let way = Way::new(1, vec![1,2,3],vec![node1, node2, node3]);
     
     let node4 = Node::new(4, 52.3, 13.3);
-    let way2 = Way::new(2, vec![3,4],vec![node3.clone(), node4.clone()]);
+    let way2 = Way::new(2, vec![3,4],vec![node3, node4]);
 
     builder.add_way(&way);
     builder.add_way(&way2);
@@ -177,14 +188,14 @@ fn add_multiple_ways() {
     let node2 = Node::new(2, 52.1, 13.1);
     let node3 = Node::new(3, 52.2, 13.2);
     
-    let way = Way::new(1, vec![1,2,3],vec![node1.clone(), node2.clone(), node3.clone()]);
+    let way = Way::new(1, vec![1,2,3],vec![node1, node2, node3]);
     
     let node4 = Node::new(4, 52.3, 13.3);
-    let way2 = Way::new(2, vec![3,4],vec![node3.clone(), node4.clone()]);
+    let way2 = Way::new(2, vec![3,4],vec![node3, node4]);
 
     let node5 = Node::new(5, 52.4, 13.4);
     let node6 = Node::new(6, 52.5, 13.5);
-    let way3 = Way::new(3, vec![3,5,6],vec![node3.clone(), node5.clone(), node6.clone()]);
+    let way3 = Way::new(3, vec![3,5,6],vec![node3, node5, node6]);
 
     builder.add_way(&way);
     builder.add_way(&way2);
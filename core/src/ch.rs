@@ -0,0 +1,611 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, Read, Write};
+
+use crate::graph::{BasicState, Graph};
+use crate::query::QueryGraph;
+
+// witness searches give up after settling this many nodes, which may add a
+// few unnecessary shortcuts but keeps preprocessing fast
+const WITNESS_SETTLE_LIMIT: usize = 100;
+const FILE_MAGIC: &[u8; 8] = b"RPCH\0\0\0\x01";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChEdge {
+    to: usize,
+    length_m: f64,
+    middle: Option<usize>, // contracted node a shortcut skips, None for road segments
+}
+
+/// Contraction hierarchy over a `Graph`.
+///
+/// Nodes are contracted one by one from least to most important. Whenever a
+/// contracted node lies on the only shortest path between two of its
+/// neighbors, a shortcut edge replaces it. A query then only ever moves up
+/// in the hierarchy from both ends and meets in the middle, touching a tiny
+/// part of the graph. Roads go both ways, so a single upward graph serves
+/// the forward and the backward search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractionHierarchy {
+    rank: Vec<usize>,
+    up: Vec<Vec<ChEdge>>, // edges to higher ranked nodes
+    fingerprint: u64,
+}
+
+impl ContractionHierarchy {
+    pub fn build(graph: &Graph) -> ContractionHierarchy {
+        let n = graph.nodes().len();
+        // remaining graph, parallel edges collapsed to the shortest one
+        let mut adj: Vec<HashMap<usize, (f64, Option<usize>)>> = vec![HashMap::new(); n];
+        for (u, neighbors) in graph.adj().iter().enumerate() {
+            for neighbor in neighbors {
+                let v = neighbor.node_index;
+                let length = neighbor.edge_data.length_m;
+                if u != v && adj[u].get(&v).is_none_or(|&(l, _)| length < l) {
+                    adj[u].insert(v, (length, None));
+                }
+            }
+        }
+
+        let mut rank = vec![usize::MAX; n];
+        let mut up = vec![Vec::new(); n];
+        let mut depth = vec![0i64; n];
+        let mut contracted_neighbors = vec![0i64; n];
+        let priority = |shortcuts: &[(usize, usize, f64)], adj: &[HashMap<usize, (f64, Option<usize>)>], depth: &[i64], contracted_neighbors: &[i64], v: usize| {
+            // edge difference, plus terms that spread contraction evenly over the graph
+            let edge_difference = shortcuts.len() as i64 - adj[v].len() as i64;
+            2 * edge_difference + contracted_neighbors[v] + depth[v]
+        };
+
+        let mut heap: BinaryHeap<Reverse<(i64, usize)>> = (0..n)
+            .map(|v| Reverse((priority(&shortcuts_for(&adj, v), &adj, &depth, &contracted_neighbors, v), v)))
+            .collect();
+        let mut next_rank = 0;
+        while let Some(Reverse((_, v))) = heap.pop() {
+            if rank[v] != usize::MAX {
+                continue;
+            }
+            // lazy update: priorities go stale as neighbors get contracted
+            let shortcuts = shortcuts_for(&adj, v);
+            let current = priority(&shortcuts, &adj, &depth, &contracted_neighbors, v);
+            if let Some(Reverse((top, _))) = heap.peek() && current > *top {
+                heap.push(Reverse((current, v)));
+                continue;
+            }
+
+            rank[v] = next_rank;
+            next_rank += 1;
+            let neighbors: Vec<(usize, (f64, Option<usize>))> = adj[v].drain().collect();
+            for &(u, (length_m, middle)) in &neighbors {
+                up[v].push(ChEdge { to: u, length_m, middle });
+                adj[u].remove(&v);
+                depth[u] = depth[u].max(depth[v] + 1);
+                contracted_neighbors[u] += 1;
+            }
+            for (u, w, length) in shortcuts {
+                if adj[u].get(&w).is_none_or(|&(l, _)| length < l) {
+                    adj[u].insert(w, (length, Some(v)));
+                    adj[w].insert(u, (length, Some(v)));
+                }
+            }
+
+        }
+
+        ContractionHierarchy { rank, up, fingerprint: fingerprint(graph) }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.rank.len()
+    }
+
+    pub fn shortcut_count(&self) -> usize {
+        self.up.iter().flatten().filter(|e| e.middle.is_some()).count()
+    }
+
+    /// Whether this hierarchy was built for `graph`, i.e. the same nodes in
+    /// the same order
+    pub fn matches(&self, graph: &Graph) -> bool {
+        self.node_count() == graph.nodes().len() && self.fingerprint == fingerprint(graph)
+    }
+
+    /// Shortest path between two real nodes
+    pub fn shortest_path(&self, start: usize, goal: usize) -> Option<(Vec<usize>, f64)> {
+        self.route(&[(start, 0.0)], &[(goal, 0.0)]).map(|(path, _)| {
+            let distance = self.path_length(&path);
+            (path, distance)
+        })
+    }
+
+    pub fn distance(&self, start: usize, goal: usize) -> f64 {
+        self.shortest_path(start, goal).map_or(f64::INFINITY, |(_, d)| d)
+    }
+
+    /// Distances from every source to every target (`matrix[i][j]` from
+    /// `sources[i]` to `targets[j]`). Each node is searched from once: the
+    /// target searches leave their distances in buckets on the nodes they
+    /// reach, the source searches pick them up.
+    pub fn distance_matrix(&self, sources: &[usize], targets: &[usize]) -> Vec<Vec<f64>> {
+        let mut buckets: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+        for (j, &target) in targets.iter().enumerate() {
+            let (dist, _) = self.upward(&[(target, 0.0)]);
+            for (node, d) in dist {
+                buckets.entry(node).or_default().push((j, d));
+            }
+        }
+
+        sources
+            .iter()
+            .map(|&source| {
+                let mut row = vec![f64::INFINITY; targets.len()];
+                let (dist, _) = self.upward(&[(source, 0.0)]);
+                for (node, d) in dist {
+                    for &(j, dt) in buckets.get(&node).into_iter().flatten() {
+                        row[j] = row[j].min(d + dt);
+                    }
+                }
+                row
+            })
+            .collect()
+    }
+
+    // Full upward search from the seeds, the search spaces are small enough
+    // that there is no need to stop early.
+    fn upward(&self, seeds: &[(usize, f64)]) -> (HashMap<usize, f64>, HashMap<usize, usize>) {
+        let mut dist: HashMap<usize, f64> = HashMap::new();
+        let mut parent = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for &(node, d) in seeds {
+            if dist.get(&node).is_none_or(|&old| d < old) {
+                dist.insert(node, d);
+                heap.push(BasicState { node, dist: d });
+            }
+        }
+        while let Some(BasicState { node: u, dist: du }) = heap.pop() {
+            if du > dist[&u] {
+                continue;
+            }
+            // stall on demand: a higher node already offers a shorter way to u,
+            // so the shortest path doesn't climb through u
+            if self.up[u].iter().any(|e| dist.get(&e.to).is_some_and(|&d| d + e.length_m < du)) {
+                continue;
+            }
+            for edge in &self.up[u] {
+                let dv = du + edge.length_m;
+                if dist.get(&edge.to).is_none_or(|&d| dv < d) {
+                    dist.insert(edge.to, dv);
+                    parent.insert(edge.to, u);
+                    heap.push(BasicState { node: edge.to, dist: dv });
+                }
+            }
+        }
+        (dist, parent)
+    }
+
+    // Meets the upward searches from both seed sets and returns the unpacked
+    // path from one of the sources to one of the targets, with its length
+    // including the seed offsets.
+    fn route(&self, sources: &[(usize, f64)], targets: &[(usize, f64)]) -> Option<(Vec<usize>, f64)> {
+        let (fwd, fwd_parent) = self.upward(sources);
+        let (bwd, bwd_parent) = self.upward(targets);
+        let (meeting, distance) = fwd
+            .iter()
+            .filter_map(|(node, df)| bwd.get(node).map(|db| (*node, df + db)))
+            .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))?;
+
+        let mut up_path = vec![meeting];
+        while let Some(&prev) = fwd_parent.get(up_path.last().unwrap()) {
+            up_path.push(prev);
+        }
+        up_path.reverse();
+        while let Some(&next) = bwd_parent.get(up_path.last().unwrap()) {
+            up_path.push(next);
+        }
+
+        let mut path = vec![up_path[0]];
+        for pair in up_path.windows(2) {
+            self.unpack(pair[0], pair[1], &mut path);
+        }
+        Some((path, distance))
+    }
+
+    // appends the road nodes after `from` up to and including `to`
+    fn unpack(&self, from: usize, to: usize, path: &mut Vec<usize>) {
+        match self.edge(from, to).middle {
+            None => path.push(to),
+            Some(middle) => {
+                self.unpack(from, middle, path);
+                self.unpack(middle, to, path);
+            }
+        }
+    }
+
+    // the edge between two adjacent nodes, stored at the lower ranked one
+    fn edge(&self, a: usize, b: usize) -> &ChEdge {
+        let (low, high) = if self.rank[a] < self.rank[b] { (a, b) } else { (b, a) };
+        self.up[low].iter().find(|e| e.to == high).expect("nodes on a CH path are adjacent")
+    }
+
+    // summed segment by segment from the start, the same way Dijkstra adds them up
+    fn path_length(&self, path: &[usize]) -> f64 {
+        path.windows(2).fold(0.0, |acc, pair| acc + self.edge(pair[0], pair[1]).length_m)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(FILE_MAGIC)?;
+        write_u64(w, self.fingerprint)?;
+        write_u64(w, self.rank.len() as u64)?;
+        for (&rank, edges) in self.rank.iter().zip(&self.up) {
+            write_u64(w, rank as u64)?;
+            write_u64(w, edges.len() as u64)?;
+            for edge in edges {
+                write_u64(w, edge.to as u64)?;
+                write_u64(w, edge.length_m.to_bits())?;
+                write_u64(w, edge.middle.map_or(u64::MAX, |m| m as u64))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a hierarchy written by `write_to` for `graph`. A file for
+    /// another graph is refused before anything is allocated, and ranks and
+    /// edges are checked so a corrupt file is an error rather than a panic.
+    pub fn read_from<R: Read>(r: &mut R, graph: &Graph) -> io::Result<ContractionHierarchy> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err(invalid("not a contraction hierarchy file".to_string()));
+        }
+        let stored = read_u64(r)?;
+        let n = read_u64(r)?;
+        if n != graph.nodes().len() as u64 || stored != fingerprint(graph) {
+            return Err(invalid("built for a different graph".to_string()));
+        }
+        let n = n as usize;
+        let index = |v: u64| {
+            if (v as usize) < n {
+                Ok(v as usize)
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidData, format!("node index {} out of range", v)))
+            }
+        };
+
+        let mut rank = Vec::with_capacity(n);
+        let mut up = Vec::with_capacity(n);
+        let mut ranked = vec![false; n];
+        for _ in 0..n {
+            let node_rank = index(read_u64(r)?)?;
+            if std::mem::replace(&mut ranked[node_rank], true) {
+                return Err(invalid(format!("rank {} given twice", node_rank)));
+            }
+            rank.push(node_rank);
+            let count = read_u64(r)? as usize;
+            let mut edges = Vec::new();
+            for _ in 0..count {
+                let to = index(read_u64(r)?)?;
+                let length_m = f64::from_bits(read_u64(r)?);
+                let middle = match read_u64(r)? {
+                    u64::MAX => None,
+                    m => Some(index(m)?),
+                };
+                edges.push(ChEdge { to, length_m, middle });
+            }
+            up.push(edges);
+        }
+        // queries only ever go up from a node
+        for (v, edges) in up.iter().enumerate() {
+            if let Some(edge) = edges.iter().find(|e| rank[e.to] <= rank[v]) {
+                return Err(invalid(format!("edge {} -> {} does not go up", v, edge.to)));
+            }
+        }
+        Ok(ContractionHierarchy { rank, up, fingerprint: stored })
+    }
+}
+
+// Shortcuts needed when contracting `v`, as (u, w, length) with u < w.
+// A pair of neighbors needs one unless a witness path that avoids `v` is at
+// least as short as going through it.
+fn shortcuts_for(adj: &[HashMap<usize, (f64, Option<usize>)>], v: usize) -> Vec<(usize, usize, f64)> {
+    let mut neighbors: Vec<(usize, f64)> = adj[v].iter().map(|(&u, &(l, _))| (u, l)).collect();
+    neighbors.sort_by_key(|&(u, _)| u);
+    let mut shortcuts = Vec::new();
+    for (i, &(u, to_u)) in neighbors.iter().enumerate() {
+        let rest = &neighbors[i + 1..];
+        let Some(max) = rest.iter().map(|&(_, to_w)| to_u + to_w).max_by(f64::total_cmp) else {
+            continue;
+        };
+        let witness = witness_search(adj, u, v, max);
+        for &(w, to_w) in rest {
+            let via = to_u + to_w;
+            if witness.get(&w).is_none_or(|&d| d > via) {
+                shortcuts.push((u, w, via));
+            }
+        }
+    }
+    shortcuts
+}
+
+// Dijkstra from `source` in the remaining graph without `skip`, limited to
+// distances up to `max` and to WITNESS_SETTLE_LIMIT settled nodes
+fn witness_search(adj: &[HashMap<usize, (f64, Option<usize>)>], source: usize, skip: usize, max: f64) -> HashMap<usize, f64> {
+    let mut dist = HashMap::from([(source, 0.0)]);
+    let mut heap = BinaryHeap::from([BasicState { node: source, dist: 0.0 }]);
+    let mut settled = 0;
+    while let Some(BasicState { node: u, dist: du }) = heap.pop() {
+        if du > dist[&u] {
+            continue;
+        }
+        settled += 1;
+        if du > max || settled > WITNESS_SETTLE_LIMIT {
+            break;
+        }
+        for (&v, &(length, _)) in &adj[u] {
+            if v == skip {
+                continue;
+            }
+            let dv = du + length;
+            if dist.get(&v).is_none_or(|&d| dv < d) {
+                dist.insert(v, dv);
+                heap.push(BasicState { node: v, dist: dv });
+            }
+        }
+    }
+    dist
+}
+
+// cheap check that a saved hierarchy belongs to the graph: mixes the OSM
+// ids of all nodes in index order, the number of edges and every edge with
+// its length, so changed roads on the same nodes don't match either
+fn fingerprint(graph: &Graph) -> u64 {
+    let mix = |h: u64, v: u64| (h ^ v).wrapping_mul(0x0100_0000_01b3);
+    let h = graph.nodes().iter().fold(0xcbf2_9ce4_8422_2325, |h, node| mix(h, node.id()));
+    let edges = graph.adj().iter().map(Vec::len).sum::<usize>();
+    graph.adj().iter().enumerate().fold(mix(h, edges as u64), |h, (u, neighbors)| {
+        neighbors.iter().fold(h, |h, neighbor| {
+            let h = mix(mix(h, u as u64), neighbor.node_index as u64);
+            mix(h, neighbor.edge_data.length_m.to_bits())
+        })
+    })
+}
+
+fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl Graph {
+    /// Keeps a contraction hierarchy with the graph so point to point queries
+    /// use it. Like landmarks it is indexed by node, so attach it last.
+    pub fn with_contraction_hierarchy(mut self, ch: ContractionHierarchy) -> Self {
+        self.ch = Some(ch);
+        self
+    }
+
+    pub fn contraction_hierarchy(&self) -> Option<&ContractionHierarchy> {
+        self.ch.as_ref()
+    }
+
+    /// Road distances between all sources and targets, with the contraction
+    /// hierarchy if there is one and one Dijkstra per target otherwise.
+    pub fn distance_matrix(&self, sources: &[usize], targets: &[usize]) -> Vec<Vec<f64>> {
+        if let Some(ch) = self.contraction_hierarchy() {
+            return ch.distance_matrix(sources, targets);
+        }
+        let query = QueryGraph::new(self);
+        let columns: Vec<Vec<f64>> = targets.iter().map(|&t| query.dijkstra(t, None).0).collect();
+        sources
+            .iter()
+            .map(|&s| columns.iter().map(|column| column[s]).collect())
+            .collect()
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Shortest path with a contraction hierarchy of the underlying graph.
+    /// Virtual start and goal nodes enter the hierarchy through the real
+    /// nodes at the ends of their split edges.
+    pub fn ch_shortest_path(&self, ch: &ContractionHierarchy, start: usize, goal: usize) -> Option<(Vec<usize>, f64)> {
+        let (from_start, start_parent) = self.virtual_reach(start);
+        let (from_goal, goal_parent) = self.virtual_reach(goal);
+        let real = |reach: &HashMap<usize, f64>| -> Vec<(usize, f64)> {
+            let mut seeds: Vec<(usize, f64)> = reach.iter().filter(|&(&u, _)| !self.is_virtual(u)).map(|(&u, &d)| (u, d)).collect();
+            seeds.sort_by_key(|&(u, _)| u);
+            seeds
+        };
+        // walks back from `node` to the root of a virtual_reach search
+        let trail = |parent: &HashMap<usize, usize>, mut node: usize| {
+            let mut nodes = vec![node];
+            while let Some(&prev) = parent.get(&node) {
+                nodes.push(prev);
+                node = prev;
+            }
+            nodes
+        };
+
+        let mut best = ch.route(&real(&from_start), &real(&from_goal)).map(|(middle, _)| {
+            let mut path = trail(&start_parent, middle[0]);
+            path.reverse();
+            path.extend_from_slice(&middle[1..]);
+            path.extend_from_slice(&trail(&goal_parent, *middle.last().unwrap())[1..]);
+            path
+        });
+        // start and goal on the same split edge, the direct way may be shorter
+        if let Some(direct) = from_start.get(&goal) {
+            let mut path = trail(&start_parent, goal);
+            path.reverse();
+            let length = |p: &[usize]| p.windows(2).fold(0.0, |acc, w| acc + self.edge_length(w[0], w[1]).unwrap());
+            if best.as_ref().is_none_or(|b| *direct < length(b)) {
+                best = Some(path);
+            }
+        }
+
+        best.map(|path| {
+            let distance = path.windows(2).fold(0.0, |acc, w| acc + self.edge_length(w[0], w[1]).unwrap());
+            (path, distance)
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::haversine_distance;
+    use crate::model::{EdgeData, EdgeSnap, Neighbor, Node};
+    use crate::test_util::Grid;
+
+    /// n × n grid with pseudo random integer lengths, so sums are exact and
+    /// shortest paths are mostly unique
+    fn build_grid(n: usize) -> Graph {
+        let mut seed: u64 = 12345;
+        Grid::new(n, n).build(|_, _, _| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            Some(EdgeData { way_id: 1, length_m: 100.0 + (seed >> 58) as f64 })
+        })
+    }
+
+    fn walk(graph: &Graph, path: &[usize]) -> f64 {
+        let query = QueryGraph::new(graph);
+        path.windows(2).fold(0.0, |acc, w| acc + query.edge_length(w[0], w[1]).unwrap())
+    }
+
+    #[test]
+    fn matches_dijkstra_exactly() {
+        let graph = build_grid(8);
+        let ch = ContractionHierarchy::build(&graph);
+        let query = QueryGraph::new(&graph);
+        for start in 0..graph.nodes().len() {
+            let (dist, _) = query.dijkstra(start, None);
+            for (goal, expected) in dist.iter().enumerate() {
+                let (path, d) = ch.shortest_path(start, goal).unwrap();
+                assert_eq!(d, *expected, "{} -> {}", start, goal);
+                assert_eq!(path.first(), Some(&start));
+                assert_eq!(path.last(), Some(&goal));
+                assert_eq!(walk(&graph, &path), d);
+            }
+        }
+    }
+
+    #[test]
+    fn adds_shortcuts() {
+        let graph = build_grid(8);
+        let ch = ContractionHierarchy::build(&graph);
+        assert!(ch.shortcut_count() > 0);
+        // every node got its own rank
+        let mut ranks = ch.rank.clone();
+        ranks.sort();
+        assert_eq!(ranks, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn haversine_lengths() {
+        // a line with a detour: 0 - 1 - 2 and 0 - 3 - 2 further out
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001), Node::new(3, 0.0, 0.002), Node::new(4, 0.001, 0.001)];
+        let mut adj = vec![Vec::new(); 4];
+        for (u, v) in [(0, 1), (1, 2), (0, 3), (3, 2)] {
+            let (a, b): (Node, Node) = (nodes[u], nodes[v]);
+            let edge = EdgeData { way_id: 1, length_m: haversine_distance(a.lat(), a.lon(), b.lat(), b.lon()) };
+            adj[u].push(Neighbor { osm_id: b.id(), node_index: v, edge_data: edge });
+            adj[v].push(Neighbor { osm_id: a.id(), node_index: u, edge_data: edge });
+        }
+        let graph = Graph::new(nodes, adj);
+        let ch = ContractionHierarchy::build(&graph);
+        let (path, d) = ch.shortest_path(0, 2).unwrap();
+        assert_eq!(path, vec![0, 1, 2]);
+        assert_eq!(d, walk(&graph, &path));
+    }
+
+    #[test]
+    fn unreachable_and_trivial() {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001), Node::new(3, 0.0, 0.002)];
+        let edge = EdgeData { way_id: 1, length_m: 5.0 };
+        let adj = vec![
+            vec![Neighbor { osm_id: 2, node_index: 1, edge_data: edge }],
+            vec![Neighbor { osm_id: 1, node_index: 0, edge_data: edge }],
+            vec![],
+        ];
+        let graph = Graph::new(nodes, adj);
+        let ch = ContractionHierarchy::build(&graph);
+        assert!(ch.shortest_path(0, 2).is_none());
+        assert_eq!(ch.distance(1, 2), f64::INFINITY);
+        assert_eq!(ch.shortest_path(1, 1), Some((vec![1], 0.0)));
+        assert_eq!(ch.shortest_path(1, 0), Some((vec![1, 0], 5.0)));
+    }
+
+    #[test]
+    fn distance_matrix_matches_single_queries() {
+        let graph = build_grid(6);
+        let sources = [0, 7, 20, 35];
+        let targets = [3, 18, 30];
+        let plain = graph.distance_matrix(&sources, &targets);
+        let ch = ContractionHierarchy::build(&graph);
+        let graph = graph.with_contraction_hierarchy(ch);
+        let matrix = graph.distance_matrix(&sources, &targets);
+        assert_eq!(matrix.len(), 4);
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, d) in row.iter().enumerate() {
+                assert_eq!(*d, plain[i][j]);
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let graph = build_grid(5);
+        let ch = ContractionHierarchy::build(&graph);
+        let mut bytes = Vec::new();
+        ch.write_to(&mut bytes).unwrap();
+        let loaded = ContractionHierarchy::read_from(&mut bytes.as_slice(), &graph).unwrap();
+        assert_eq!(loaded, ch);
+        assert!(loaded.matches(&graph));
+        assert!(!loaded.matches(&build_grid(4)));
+        // same nodes, different roads
+        let mut longer = build_grid(5);
+        longer.adj[0][0].edge_data.length_m += 1.0;
+        assert!(!loaded.matches(&longer));
+        let mut fewer = build_grid(5);
+        fewer.adj[0].pop();
+        assert!(!loaded.matches(&fewer));
+
+        assert!(ContractionHierarchy::read_from(&mut bytes.as_slice(), &build_grid(4)).is_err());
+        assert!(ContractionHierarchy::read_from(&mut &b"garbage!"[..], &graph).is_err());
+        assert!(ContractionHierarchy::read_from(&mut &bytes[..bytes.len() - 3], &graph).is_err());
+        // a rank out of range, right after the node count
+        let mut corrupt = bytes.clone();
+        corrupt[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ContractionHierarchy::read_from(&mut corrupt.as_slice(), &graph).is_err());
+    }
+
+    #[test]
+    fn virtual_start_and_goal() {
+        let graph = build_grid(5);
+        let ch = ContractionHierarchy::build(&graph);
+        let mut query = QueryGraph::new(&graph);
+        let snap = |from: usize, to: usize, fraction: f64| {
+            let edge_length = query.edge_length(from, to).unwrap();
+            let (a, b) = (query.node(from), query.node(to));
+            let (lat, lon) = (a.lat() + (b.lat() - a.lat()) * fraction, a.lon() + (b.lon() - a.lon()) * fraction);
+            EdgeSnap { from, to, way_id: 1, fraction, lat, lon, distance: 0.0, edge_length }
+        };
+        // two on the same edge and one far away
+        let snaps = [snap(6, 7, 0.2), snap(6, 7, 0.7), snap(18, 23, 0.5)];
+        let idx = query.split_edges(&snaps);
+
+        for (s, t) in [(idx[0], idx[2]), (idx[2], idx[0]), (idx[0], idx[1]), (idx[1], idx[0]), (idx[1], 24)] {
+            let (dist, _) = query.dijkstra(s, None);
+            let (path, d) = query.ch_shortest_path(&ch, s, t).unwrap();
+            assert!((d - dist[t]).abs() < 1e-9, "{} -> {}: {} vs {}", s, t, d, dist[t]);
+            assert_eq!(path.first(), Some(&s));
+            assert_eq!(path.last(), Some(&t));
+            for w in path.windows(2) {
+                assert!(query.edge_length(w[0], w[1]).is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::model::Path;
+use crate::query::QueryGraph;
+
+// road segments of a path with their lengths, each counted once
+type EdgeSet = HashMap<(usize, usize), f64>;
+
+/// Rejects paths that overlap too much with the ones accepted before.
+///
+/// Overlap is the Jaccard index by length: the length of the road the two
+/// paths share divided by the length of all road either of them uses.
+/// 0 means disjoint, 1 means the same roads.
+pub struct DiversityFilter {
+    max_overlap: Option<f64>,
+    accepted: Vec<EdgeSet>,
+}
+
+impl DiversityFilter {
+    /// `None` accepts every path
+    pub fn new(max_overlap: Option<f64>) -> Self {
+        DiversityFilter { max_overlap, accepted: Vec::new() }
+    }
+
+    /// Accepts `path` if it overlaps at most `max_overlap` with every path
+    /// accepted so far and remembers it for the next ones.
+    pub fn accept(&mut self, query: &QueryGraph, path: &[usize]) -> bool {
+        let Some(max_overlap) = self.max_overlap else {
+            return true;
+        };
+        let edges = query.edge_set(path);
+        if self.accepted.iter().any(|other| jaccard(&edges, other) > max_overlap) {
+            return false;
+        }
+        self.accepted.push(edges);
+        true
+    }
+}
+
+fn jaccard(a: &EdgeSet, b: &EdgeSet) -> f64 {
+    let shared: f64 = a.iter().filter(|(edge, _)| b.contains_key(edge)).map(|(_, length)| length).sum();
+    let union = a.values().sum::<f64>() + b.values().sum::<f64>() - shared;
+    if union > 0.0 { shared / union } else { 1.0 }
+}
+
+impl QueryGraph<'_> {
+    fn edge_set(&self, path: &[usize]) -> EdgeSet {
+        path.windows(2)
+            .filter(|w| w[0] != w[1])
+            .map(|w| ((w[0].min(w[1]), w[0].max(w[1])), self.edge_length(w[0], w[1]).unwrap_or(0.0)))
+            .collect()
+    }
+
+    /// Jaccard overlap by length of two node sequences
+    pub fn overlap(&self, a: &[usize], b: &[usize]) -> f64 {
+        jaccard(&self.edge_set(a), &self.edge_set(b))
+    }
+
+    /// Converts search results into paths, each reporting its largest
+    /// overlap with any other path of the result.
+    pub(crate) fn convert_to_paths(&self, solutions: &[(Vec<usize>, f64)]) -> Vec<Path> {
+        let edges: Vec<EdgeSet> = solutions.iter().map(|(path, _)| self.edge_set(path)).collect();
+        solutions
+            .iter()
+            .enumerate()
+            .map(|(i, (path, distance))| {
+                let mut converted = self.convert_to_path(path, *distance);
+                converted.overlap = (0..edges.len())
+                    .filter(|&j| j != i)
+                    .map(|j| jaccard(&edges[i], &edges[j]))
+                    .fold(0.0, f64::max);
+                converted
+            })
+            .collect()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::model::{EdgeData, Neighbor, Node, SearchOptions};
+    use crate::test_util::Grid;
+
+    /// 0 -5- 5 -5- 1 -10- 2 -5- 3 -5- 4, plus a longer detour 5 -30- 6 -5- 3
+    fn build_graph() -> Graph {
+        let nodes = (0..7).map(|i| Node::new(i, 0.0, i as f64 * 0.001)).collect();
+        let mut adj = vec![Vec::new(); 7];
+        for (u, v, length_m) in [(0, 5, 5.0), (5, 1, 5.0), (1, 2, 10.0), (2, 3, 5.0), (5, 6, 30.0), (6, 3, 5.0), (3, 4, 5.0)] {
+            let edge_data = EdgeData { way_id: 1, length_m };
+            adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data });
+            adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data });
+        }
+        Graph::new(nodes, adj)
+    }
+
+    #[test]
+    fn overlap_by_length() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        let north = [0, 5, 1, 2, 3, 4];
+        let south = [0, 5, 6, 3, 4];
+        // shared 0-5 and 3-4 (10 m) out of 5 + 5 + 10 + 5 + 30 + 5 + 5 = 65 m
+        assert!((query.overlap(&north, &south) - 10.0 / 65.0).abs() < 1e-9);
+        assert_eq!(query.overlap(&north, &north), 1.0);
+        assert_eq!(query.overlap(&north, &south), query.overlap(&south, &north));
+    }
+
+    #[test]
+    fn filter_rejects_near_duplicates() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        let mut filter = DiversityFilter::new(Some(0.5));
+        assert!(filter.accept(&query, &[0, 5, 1, 2, 3, 4]));
+        assert!(!filter.accept(&query, &[0, 5, 1, 2, 3]));
+        assert!(filter.accept(&query, &[0, 5, 6, 3, 4]));
+
+        let mut everything = DiversityFilter::new(None);
+        assert!(everything.accept(&query, &[0, 5, 1, 2, 3, 4]));
+        assert!(everything.accept(&query, &[0, 5, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn paths_report_their_largest_overlap() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        let paths = query.convert_to_paths(&[
+            (vec![0, 5, 1, 2, 3, 4], 30.0),
+            (vec![0, 5, 6, 3, 4], 45.0),
+            (vec![0, 5, 1, 2, 3], 25.0),
+        ]);
+        assert!((paths[0].overlap - 25.0 / 30.0).abs() < 1e-9);
+        assert!((paths[1].overlap - 10.0 / 65.0).abs() < 1e-9);
+        assert!((paths[2].overlap - 25.0 / 30.0).abs() < 1e-9);
+
+        let single = query.convert_to_paths(&[(vec![0, 5], 5.0)]);
+        assert_eq!(single[0].overlap, 0.0);
+    }
+
+    /// 3 rows × 4 columns, 10 m of road between neighbors about 5.6 m apart
+    fn build_grid() -> Graph {
+        Grid::new(3, 4).with_spacing(0.00005).build(|_, _, _| Some(EdgeData { way_id: 1, length_m: 10.0 }))
+    }
+
+    fn max_pairwise(query: &QueryGraph, paths: &[(Vec<usize>, f64)]) -> f64 {
+        let mut max: f64 = 0.0;
+        for (i, (a, _)) in paths.iter().enumerate() {
+            for (b, _) in &paths[i + 1..] {
+                max = max.max(query.overlap(a, b));
+            }
+        }
+        max
+    }
+
+    #[test]
+    fn searches_respect_max_overlap() {
+        // every corner to corner path of 5 blocks, 10 of them
+        let graph = build_grid();
+        let query = QueryGraph::new(&graph);
+
+        let all = query.bfs(0, 11, 10, 50.0, 0.0, &SearchOptions::default()).paths;
+        assert_eq!(all.len(), 10);
+        // swapping one corner shares 3 of 5 blocks, 3 / 7 overlap
+        assert!((max_pairwise(&query, &all) - 3.0 / 7.0).abs() < 1e-9);
+
+        let options = SearchOptions { max_overlap: Some(0.3), ..Default::default() };
+        for paths in [query.bfs(0, 11, 10, 50.0, 0.0, &options).paths, query.special_dijkstra(0, 11, 10, 50.0, 0.0, &options).paths] {
+            assert!(paths.len() >= 2);
+            assert!(paths.len() < 10);
+            assert!(max_pairwise(&query, &paths) <= 0.3);
+        }
+    }
+}
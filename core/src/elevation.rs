@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::graph::Graph;
+use crate::model::AscentRange;
+use crate::query::QueryGraph;
+
+impl Graph {
+    /// Elevation in meters of every node, by node index. The table refers to
+    /// node indices, so do this after any reordering.
+    pub fn with_elevation(mut self, elevation: Vec<f64>) -> Self {
+        self.elevation = Some(elevation);
+        self
+    }
+
+    pub fn has_elevation(&self) -> bool {
+        self.elevation.is_some()
+    }
+
+    /// Heights for `with_elevation` from lines of `<OSM node id> <meters>`,
+    /// e.g. sampled from a DEM. Every node of the graph needs one, ids that
+    /// are not in the graph are skipped.
+    pub fn read_elevation<R: BufRead>(&self, r: R) -> io::Result<Vec<f64>> {
+        let mut heights = HashMap::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            let height = line.split_once(' ').and_then(|(id, m)| Some((id.parse::<u64>().ok()?, m.trim().parse::<f64>().ok()?)));
+            let Some((id, meters)) = height else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {} is not `<node id> <meters>`", i + 1)));
+            };
+            heights.insert(id, meters);
+        }
+        self.nodes()
+            .iter()
+            .map(|node| heights.get(&node.id()).copied())
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not every node has a height"))
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Elevation of a node, virtual nodes are interpolated along their edge
+    pub fn elevation(&self, v: usize) -> Option<f64> {
+        let elevation = self.graph().elevation.as_ref()?;
+        if !self.is_virtual(v) {
+            return Some(elevation[v]);
+        }
+        let (reach, _) = self.virtual_reach(v);
+        let (mut weighted, mut weights) = (0.0, 0.0);
+        for (&end, &d) in reach.iter().filter(|(end, _)| !self.is_virtual(**end)) {
+            // the closer end counts more
+            let weight = 1.0 / d.max(1e-9);
+            weighted += weight * elevation[end];
+            weights += weight;
+        }
+        (weights > 0.0).then(|| weighted / weights)
+    }
+
+    /// Elevation of every node of the query, virtual ones included, so
+    /// searches don't interpolate over and over. `None` without elevation data.
+    pub(crate) fn heights(&self) -> Option<Vec<f64>> {
+        self.graph().elevation.as_ref()?;
+        Some((0..self.node_count()).map(|v| self.elevation(v).unwrap_or(0.0)).collect())
+    }
+
+    /// Summed climbs along a node sequence, 0 without elevation data
+    pub fn ascent(&self, path: &[usize]) -> f64 {
+        path.windows(2)
+            .filter_map(|w| Some((self.elevation(w[1])? - self.elevation(w[0])?).max(0.0)))
+            .sum()
+    }
+}
+
+/// Keeps a search within an `AscentRange`: climbs only ever add up, and at
+/// least the height difference up to the goal is still to come
+pub(crate) struct Climb {
+    heights: Vec<f64>,
+    goal: usize,
+    pub(crate) range: AscentRange,
+}
+
+impl Climb {
+    /// `None` when the search has no range to keep to. Without elevation
+    /// data every road is flat.
+    pub(crate) fn new(query: &QueryGraph, goal: usize, range: Option<AscentRange>) -> Option<Self> {
+        let range = range?;
+        let heights = query.heights().unwrap_or_else(|| vec![0.0; query.node_count()]);
+        Some(Climb { heights, goal, range })
+    }
+
+    /// Climb of the edge from `u` to `v`
+    pub(crate) fn up(&self, u: usize, v: usize) -> f64 {
+        (self.heights[v] - self.heights[u]).max(0.0)
+    }
+
+    /// Whether a path at `v` that climbed `ascent` so far can still end in range
+    pub(crate) fn feasible(&self, v: usize, ascent: f64) -> bool {
+        ascent + (self.heights[self.goal] - self.heights[v]).max(0.0) <= self.range.max_m
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EdgeData, Neighbor, Node, PathQuery, RouteError, SearchOptions, ViaOrder};
+
+    /// Three ways from 0 to 4:
+    ///   0 -100- 1 -100- 4   over a 30 m hill at 1
+    ///   0 -150- 2 -150- 4   flat
+    ///   0 -120- 3 -120- 4   over a 10 m hill at 3
+    fn build_graph() -> Graph {
+        let nodes = (0..5).map(|i| Node::new(i, 0.0, 0.0)).collect();
+        let mut adj = vec![Vec::new(); 5];
+        for (via, length_m) in [(1, 100.0), (2, 150.0), (3, 120.0)] {
+            let edge_data = EdgeData { way_id: via as u64, length_m };
+            for (u, v) in [(0, via), (via, 4)] {
+                adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data });
+                adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data });
+            }
+        }
+        Graph::new(nodes, adj)
+    }
+
+    fn with_ascent(min_m: f64, max_m: f64) -> SearchOptions {
+        SearchOptions { ascent: Some(AscentRange { min_m, max_m }), ..Default::default() }
+    }
+
+    #[test]
+    fn searches_keep_to_the_ascent_range() {
+        let graph = build_graph().with_elevation(vec![0.0, 30.0, 0.0, 10.0, 0.0]);
+        let query = QueryGraph::new(&graph);
+        // every way is within 250 ± 60 m
+        let all = query.bfs(0, 4, 10, 250.0, 60.0, &SearchOptions::default());
+        assert_eq!(all.paths.len(), 3);
+
+        let climbs = |options: &SearchOptions| {
+            let bfs = query.bfs(0, 4, 10, 250.0, 60.0, options);
+            let dijkstra = query.special_dijkstra(0, 4, 10, 250.0, 60.0, options);
+            let beam = query.beam_search(0, 4, 10, 250.0, 60.0, 100, options);
+            let paths: Vec<Vec<usize>> = bfs.paths.into_iter().map(|(path, _)| path).collect();
+            assert_eq!(paths, dijkstra.paths.into_iter().map(|(path, _)| path).collect::<Vec<_>>());
+            assert_eq!(paths, beam.paths.into_iter().map(|(path, _)| path).collect::<Vec<_>>());
+            paths
+        };
+        assert_eq!(climbs(&with_ascent(5.0, 15.0)), vec![vec![0, 3, 4]]);
+        assert_eq!(climbs(&with_ascent(0.0, 5.0)), vec![vec![0, 2, 4]]);
+        assert_eq!(climbs(&with_ascent(20.0, 40.0)), vec![vec![0, 1, 4]]);
+        assert!(climbs(&with_ascent(50.0, 100.0)).is_empty());
+        assert_eq!(query.ascent(&[0, 1, 4, 3, 0]), 40.0);
+    }
+
+    #[test]
+    fn heights_are_read_by_node_id() {
+        let graph = build_graph();
+        let file = "4 0\n3 10\n2 0\n1 30.5\n0 0\n99 1000\n";
+        assert_eq!(graph.read_elevation(file.as_bytes()).unwrap(), vec![0.0, 30.5, 0.0, 10.0, 0.0]);
+        assert!(graph.read_elevation("4 0\n3 10\n".as_bytes()).is_err());
+        assert!(graph.read_elevation("0 high\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn ascent_needs_elevation_data() {
+        let req = PathQuery {
+            start_lat: 0.0,
+            start_lon: 0.0,
+            goal_lat: 0.0,
+            goal_lon: 0.0,
+            amount: 1,
+            target_distance: 250.0,
+            tol: 60.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via: Vec::new(),
+            via_order: ViaOrder::Ordered,
+            avoid: Default::default(),
+            repeats: Default::default(),
+            budget: Default::default(),
+            seed: None,
+            ascent: Some(AscentRange::around(10.0, 5.0)),
+            novelty: None,
+        };
+        assert!(matches!(build_graph().get_paths_bfs(&req), Err(RouteError::NoElevation)));
+    }
+}
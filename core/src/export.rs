@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Write};
+
+use serde_json::{Map, Value, json};
+
+use crate::graph::Graph;
+use crate::stats::BoundingBox;
+
+impl Graph {
+    // tag keys used by any way in the graph, sorted so the output is stable
+    fn tag_keys(&self) -> Vec<&str> {
+        let keys: BTreeSet<&str> = self.way_tags.values().flat_map(|tags| tags.keys().map(String::as_str)).collect();
+        keys.into_iter().collect()
+    }
+
+    /// Writes the graph as GraphML (e.g. for Gephi).
+    /// Nodes carry `osm_id`, `lat` and `lon`, edges `way_id`, `length_m` and
+    /// one attribute per OSM tag key.
+    pub fn write_graphml<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let tag_keys = self.tag_keys();
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(w, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        writeln!(w, r#"  <key id="osm_id" for="node" attr.name="osm_id" attr.type="long"/>"#)?;
+        writeln!(w, r#"  <key id="lat" for="node" attr.name="lat" attr.type="double"/>"#)?;
+        writeln!(w, r#"  <key id="lon" for="node" attr.name="lon" attr.type="double"/>"#)?;
+        writeln!(w, r#"  <key id="way_id" for="edge" attr.name="way_id" attr.type="long"/>"#)?;
+        writeln!(w, r#"  <key id="length_m" for="edge" attr.name="length_m" attr.type="double"/>"#)?;
+        for (i, key) in tag_keys.iter().enumerate() {
+            writeln!(w, r#"  <key id="tag{}" for="edge" attr.name="{}" attr.type="string"/>"#, i, xml_escape(key))?;
+        }
+        writeln!(w, r#"  <graph id="G" edgedefault="undirected">"#)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            writeln!(w, r#"    <node id="n{}">"#, i)?;
+            writeln!(w, r#"      <data key="osm_id">{}</data>"#, node.id())?;
+            writeln!(w, r#"      <data key="lat">{}</data>"#, node.lat())?;
+            writeln!(w, r#"      <data key="lon">{}</data>"#, node.lon())?;
+            writeln!(w, "    </node>")?;
+        }
+        for (u, v, edge) in self.segments() {
+            writeln!(w, r#"    <edge source="n{}" target="n{}">"#, u, v)?;
+            writeln!(w, r#"      <data key="way_id">{}</data>"#, edge.edge_data.way_id)?;
+            writeln!(w, r#"      <data key="length_m">{}</data>"#, edge.edge_data.length_m)?;
+            if let Some(tags) = self.way_tags(edge.edge_data.way_id) {
+                for (i, key) in tag_keys.iter().enumerate() {
+                    if let Some(value) = tags.get(*key) {
+                        writeln!(w, r#"      <data key="tag{}">{}</data>"#, i, xml_escape(value))?;
+                    }
+                }
+            }
+            writeln!(w, "    </edge>")?;
+        }
+        writeln!(w, "  </graph>")?;
+        writeln!(w, "</graphml>")
+    }
+
+    /// Writes the graph in Graphviz DOT format. Node positions are set to
+    /// lon/lat so `neato -n` draws the map as is.
+    pub fn write_dot<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "graph G {{")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            writeln!(
+                w,
+                "  n{} [osm_id={}, lat={}, lon={}, pos=\"{},{}!\"];",
+                i, node.id(), node.lat(), node.lon(), node.lon(), node.lat()
+            )?;
+        }
+        for (u, v, edge) in self.segments() {
+            write!(w, "  n{} -- n{} [way_id={}, length_m={}", u, v, edge.edge_data.way_id, edge.edge_data.length_m)?;
+            if let Some(tags) = self.way_tags(edge.edge_data.way_id) {
+                let mut tags: Vec<(&String, &String)> = tags.iter().collect();
+                tags.sort();
+                for (key, value) in tags {
+                    write!(w, ", \"{}\"=\"{}\"", dot_escape(key), dot_escape(value))?;
+                }
+            }
+            writeln!(w, "];")?;
+        }
+        writeln!(w, "}}")
+    }
+
+    /// Builds a GeoJSON FeatureCollection with a Point per node and a
+    /// LineString per road segment. With a `bbox` only nodes inside it and
+    /// segments with at least one end inside it are exported.
+    pub fn to_geojson(&self, bbox: Option<BoundingBox>) -> Value {
+        let inside = |idx: usize| {
+            let node = &self.nodes[idx];
+            bbox.is_none_or(|b| b.contains(node.lat(), node.lon()))
+        };
+
+        let mut features = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if !inside(i) {
+                continue;
+            }
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [node.lon(), node.lat()] },
+                "properties": { "index": i, "osm_id": node.id() },
+            }));
+        }
+        for (u, v, edge) in self.segments() {
+            if !inside(u) && !inside(v) {
+                continue;
+            }
+            let mut properties = Map::new();
+            properties.insert("way_id".to_string(), json!(edge.edge_data.way_id));
+            properties.insert("length_m".to_string(), json!(edge.edge_data.length_m));
+            let empty = HashMap::new();
+            for (key, value) in self.way_tags(edge.edge_data.way_id).unwrap_or(&empty) {
+                properties.insert(key.clone(), json!(value));
+            }
+            let (a, b) = (&self.nodes[u], &self.nodes[v]);
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": [[a.lon(), a.lat()], [b.lon(), b.lat()]] },
+                "properties": properties,
+            }));
+        }
+
+        json!({ "type": "FeatureCollection", "features": features })
+    }
+
+    pub fn write_geojson<W: Write>(&self, w: &mut W, bbox: Option<BoundingBox>) -> io::Result<()> {
+        serde_json::to_writer(&mut *w, &self.to_geojson(bbox))?;
+        writeln!(w)
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::GraphBuilder;
+    use crate::model::{Node, Way};
+
+    ///   1 --(way 10, "A & B")-- 2 --(way 11)-- 3
+    fn build_graph() -> Graph {
+        let n = |id: u64, lon: f64| Node::new(id, 0.0, lon);
+        let mut builder = GraphBuilder::new();
+        let tags = HashMap::from([
+            ("highway".to_string(), "residential".to_string()),
+            ("name".to_string(), "A & B".to_string()),
+        ]);
+        builder.add_way(&Way::new(10, vec![1, 2], vec![n(1, 0.0), n(2, 0.01)]).with_tags(tags));
+        builder.add_way(&Way::new(11, vec![2, 3], vec![n(2, 0.01), n(3, 0.02)]));
+        builder.build()
+    }
+
+    #[test]
+    fn graphml_declares_keys_and_escapes_values() {
+        let mut out = Vec::new();
+        build_graph().write_graphml(&mut out).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.contains(r#"<key id="tag1" for="edge" attr.name="name" attr.type="string"/>"#));
+        assert!(xml.contains(r#"<data key="tag1">A &amp; B</data>"#));
+        assert_eq!(xml.matches("<node ").count(), 3);
+        assert_eq!(xml.matches("<edge ").count(), 2);
+    }
+
+    #[test]
+    fn dot_writes_each_segment_once() {
+        let mut out = Vec::new();
+        build_graph().write_dot(&mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("graph G {"));
+        assert_eq!(dot.matches(" -- ").count(), 2);
+        assert!(dot.contains(r#"n0 -- n1 [way_id=10"#));
+        assert!(dot.contains(r#""highway"="residential""#));
+    }
+
+    #[test]
+    fn geojson_contains_nodes_and_segments() {
+        let geojson = build_graph().to_geojson(None);
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 5);
+        let line = features.iter().find(|f| f["properties"]["way_id"] == 10).unwrap();
+        assert_eq!(line["geometry"]["type"], "LineString");
+        assert_eq!(line["properties"]["name"], "A & B");
+        assert_eq!(line["geometry"]["coordinates"][1], json!([0.01, 0.0]));
+    }
+
+    #[test]
+    fn geojson_bbox_filter() {
+        // only node 1 lies inside, so only the segment touching it is kept
+        let bbox = BoundingBox { min_lat: -1.0, min_lon: -1.0, max_lat: 1.0, max_lon: 0.005 };
+        let geojson = build_graph().to_geojson(Some(bbox));
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[0]["properties"]["osm_id"], 1);
+        assert_eq!(features[1]["properties"]["way_id"], 10);
+    }
+}
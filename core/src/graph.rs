@@ -1,17 +1,36 @@
-use std::collections::{VecDeque, BinaryHeap, HashSet};
-use rand::rng;
+use std::collections::{VecDeque, BinaryHeap, HashMap, HashSet};
+use rand::rngs::StdRng;
+use rand::{rng, SeedableRng};
 use rand::seq::SliceRandom;
 use std::cmp::Ordering;
 
 
-use crate::model::{Node, Neighbor, Path};
+use crate::model::{EdgeSnap, Node, Neighbor, Path, PathQuery, PathsResponse, RepeatPolicy, RouteError, SearchOptions, SearchOutcome, SnapInfo};
+use crate::budget::Meter;
+use crate::query::QueryGraph;
+use crate::alt::Landmarks;
+use crate::ch::ContractionHierarchy;
+use crate::diversity::DiversityFilter;
+use crate::elevation::Climb;
+use crate::tree_cache::TreeCache;
 use crate::builder::haversine_distance;
 
-const MAX_LOOKBACK: u16 = 100; // how many states to look back in ancestry check
+const DFS_ATTEMPTS_PER_PATH: usize = 5; // random dfs runs per requested path when rejecting overlapping ones
+
+// seed for a search the request gave none for, kept below 2^53 so it
+// survives the round trip through a JavaScript number
+pub(crate) fn fresh_seed() -> u64 {
+    rand::random::<u64>() >> 11
+}
 
 pub struct Graph {
-    nodes: Vec<Node>,
-    adj: Vec<Vec<Neighbor>>,
+    pub(crate) nodes: Vec<Node>,
+    pub(crate) adj: Vec<Vec<Neighbor>>,
+    pub(crate) way_tags: HashMap<u64, HashMap<String, String>>, // OSM tags per way id
+    pub(crate) landmarks: Option<Landmarks>, // ALT distance tables, see `with_landmarks`
+    pub(crate) ch: Option<ContractionHierarchy>,
+    pub(crate) tree_cache: Option<TreeCache>, // reverse Dijkstra trees of recent goals, see `with_tree_cache`
+    pub(crate) elevation: Option<Vec<f64>>, // meters per node, see `with_elevation`
 }
 
 
@@ -21,13 +40,15 @@ pub struct SearchState {
     node: usize, // index of the current node in the graph
     prev: Option<usize>, // index in states
     distance: f64,
+    ascent: f64, // summed climbs, only counted when the search has an ascent range
+    known: f64, // length of roads in the novelty history, only counted with one
+    trail: u128, // bloom filter of what the repeat policy counts on the way here
 }
 
-#[derive(Debug, Clone)]
-pub struct SpecialDijkstraState {
-    node: usize, // index of the current node in the graph
-    prev: Option<usize>, // index in states
-    distance: f64,
+impl SearchState {
+    fn start(node: usize, repeats: RepeatPolicy) -> Self {
+        SearchState { node, prev: None, distance: 0.0, ascent: 0.0, known: 0.0, trail: repeats.key(None, node).map_or(0, bloom_bit) }
+    }
 }
 
 
@@ -65,9 +86,9 @@ impl Eq for HeapItem {}
 
 
 #[derive(Copy, Clone)]
-struct BasicState {
-    node: usize,
-    dist: f64,
+pub(crate) struct BasicState {
+    pub(crate) node: usize,
+    pub(crate) dist: f64,
 }
 
 impl PartialEq for BasicState {
@@ -78,14 +99,14 @@ impl Eq for BasicState {}
 
 impl PartialOrd for BasicState {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // Notice we flip the order to make a min-heap
-        other.dist.partial_cmp(&self.dist)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for BasicState {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        // Notice we flip the order to make a min-heap
+        other.dist.partial_cmp(&self.dist).unwrap()
     }
 }
 
@@ -98,9 +119,19 @@ impl Graph {
         Graph {
             nodes,
             adj,
+            way_tags: HashMap::new(),
+            landmarks: None,
+            ch: None,
+            tree_cache: None,
+            elevation: None,
         }
     }
 
+    pub fn with_way_tags(mut self, way_tags: HashMap<u64, HashMap<String, String>>) -> Self {
+        self.way_tags = way_tags;
+        self
+    }
+
     pub fn nodes(&self) -> &Vec<Node> {
         &self.nodes
     }
@@ -109,55 +140,318 @@ impl Graph {
         &self.adj
     }
 
-    pub fn in_ancestry(states: &[SearchState], mut idx: usize, candidate: usize, max_lookback: u16) -> bool {
-        for _ in 0..max_lookback {
-            if states[idx].node == candidate {
-                return true;
+    pub fn way_tags(&self, way_id: u64) -> Option<&HashMap<String, String>> {
+        self.way_tags.get(&way_id)
+    }
+
+    pub fn tag(&self, way_id: u64, key: &str) -> Option<&str> {
+        self.way_tags(way_id)?.get(key).map(String::as_str)
+    }
+
+    /// Every road segment once as (from, to, edge), in adjacency order.
+    /// Both directions of a segment are stored in `adj`, only the first is kept.
+    pub fn segments(&self) -> Vec<(usize, usize, &Neighbor)> {
+        let mut seen: HashSet<(usize, usize, u64)> = HashSet::new();
+        let mut segments = Vec::new();
+        for (u, neighbors) in self.adj.iter().enumerate() {
+            for neighbor in neighbors {
+                let v = neighbor.node_index;
+                if seen.insert((u.min(v), u.max(v), neighbor.edge_data.way_id)) {
+                    segments.push((u, v, neighbor));
+                }
             }
+        }
+        segments
+    }
+
+    pub fn bfs(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64) -> Vec<(Vec<usize>,f64)> {
+        QueryGraph::new(self).bfs(start, goal, k, target_distance, tol, &SearchOptions::default()).paths
+    }
+
+    pub fn special_dijkstra(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64) -> Vec<(Vec<usize>,f64)> {
+        QueryGraph::new(self).special_dijkstra(start, goal, k, target_distance, tol, &SearchOptions::default()).paths
+    }
+
+
+    /// Projects a coordinate onto the closest edge of the graph.
+    /// Returns `None` when the graph has no edges.
+    pub fn snap_to_edge(&self, lat: f64, lon: f64) -> Option<EdgeSnap> {
+        self.snap_to_edge_where(lat, lon, |_, _| true)
+    }
 
-            match states[idx].prev {
-                Some(parent_idx) => idx = parent_idx,
-                None => break,
+    /// Like `snap_to_edge` but only onto edges `allowed(u, neighbor)` accepts
+    pub fn snap_to_edge_where<F>(&self, lat: f64, lon: f64, allowed: F) -> Option<EdgeSnap>
+    where
+        F: Fn(usize, &Neighbor) -> bool,
+    {
+        let mut best: Option<EdgeSnap> = None;
+        for (u, neighbors) in self.adj.iter().enumerate() {
+            for neighbor in neighbors {
+                let v = neighbor.node_index;
+                // every edge is stored in both directions, only look at it once
+                if v < u || !allowed(u, neighbor) {
+                    continue;
+                }
+                let (fraction, snap_lat, snap_lon) = project_onto_segment(lat, lon, &self.nodes[u], &self.nodes[v]);
+                let distance = haversine_distance(lat, lon, snap_lat, snap_lon);
+                if best.is_none_or(|b| distance < b.distance) {
+                    best = Some(EdgeSnap {
+                        from: u,
+                        to: v,
+                        way_id: neighbor.edge_data.way_id,
+                        fraction,
+                        lat: snap_lat,
+                        lon: snap_lon,
+                        distance,
+                        edge_length: neighbor.edge_data.length_m,
+                    });
+                }
             }
         }
-        false
+        best
     }
 
-    pub fn in_ancestry_dijkstra(states: &[SpecialDijkstraState], mut idx: usize, candidate: usize, max_lookback: u16) -> bool {
-        for _ in 0..max_lookback {
-            if states[idx].node == candidate {
-                return true;
+    // maps a lat and lon to a node in the graph
+    // retursn the index of the node in the nodes array
+    pub(crate) fn map_lat_lon_to_node(&self, lat: f64, lon: f64) -> usize {
+        let mut idx: usize = 0;
+        let mut shortest_dist = haversine_distance(lat, lon, self.nodes[0].lat(), self.nodes[0].lon());
+
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let d = haversine_distance(lat, lon, node.lat(), node.lon());
+            if d < shortest_dist {
+                shortest_dist = d;
+                idx = i;
             }
+        }
+        idx
+    }
+
+    pub fn get_paths_bfs(&self, req: &PathQuery) -> Result<PathsResponse, RouteError> {
+        self.get_paths_with(req, |query, start, goal, target_distance, tol, options| {
+            query.bfs(start, goal, req.amount, target_distance, tol, options)
+        })
+    }
+    
+    /// Random by nature, `req.seed` makes it repeatable. The seed used is
+    /// in the response either way.
+    pub fn get_paths_dfs(&self, req: &PathQuery) -> Result<PathsResponse, RouteError> {
+        let seed = req.seed.unwrap_or_else(fresh_seed);
+        let req = PathQuery { seed: Some(seed), ..req.clone() };
+        let mut res = self.get_paths_with(&req, |query, start, goal, target_distance, tol, options| {
+            query.find_paths_with_dfs(start, goal, req.amount, target_distance, tol, options)
+        })?;
+        res.seed = Some(seed);
+        Ok(res)
+    }
+
+    pub fn get_paths_special_dijkstra(&self, req: &PathQuery) -> Result<PathsResponse, RouteError> {
+        self.get_paths_with(req, |query, start, goal, target_distance, tol, options| {
+            query.special_dijkstra(start, goal, req.amount, target_distance, tol, options)
+        })
+    }
 
-            match states[idx].prev {
-                Some(parent_idx) => idx = parent_idx,
-                None => break,
+    pub fn get_paths_beam(&self, req: &PathQuery, width: usize) -> Result<PathsResponse, RouteError> {
+        self.get_paths_with(req, |query, start, goal, target_distance, tol, options| {
+            query.beam_search(start, goal, req.amount, target_distance, tol, width, options)
+        })
+    }
+
+    // snaps start, via-points and goal onto a fresh query graph, runs
+    // `search(query, from, to, target_distance, tol, options)` along every
+    // leg between them and converts the found node sequences into paths
+    pub(crate) fn get_paths_with<F>(&self, req: &PathQuery, search: F) -> Result<PathsResponse, RouteError>
+    where
+        F: Fn(&QueryGraph, usize, usize, f64, f64, &SearchOptions) -> SearchOutcome,
+    {
+        if req.ascent.is_some() && !self.has_elevation() {
+            return Err(RouteError::NoElevation);
+        }
+        let mut query = QueryGraph::new(self).avoiding(&req.avoid);
+        let mut points = vec![(req.start_lat, req.start_lon)];
+        points.extend_from_slice(&req.via);
+        points.push((req.goal_lat, req.goal_lon));
+        let snapped = query.snap(&points);
+        let (start, goal) = (snapped[0], snapped[snapped.len() - 1]);
+        check_snap_distance("start", &start, req.max_snap_distance)?;
+        check_snap_distance("goal", &goal, req.max_snap_distance)?;
+        let via = &snapped[1..snapped.len() - 1];
+        for snap in via {
+            check_snap_distance("via-point", snap, req.max_snap_distance)?;
+        }
+
+        let via_nodes: Vec<usize> = via.iter().map(|snap| snap.node_index).collect();
+        let order = query.via_order(start.node_index, &via_nodes, goal.node_index, req.via_order);
+        let mut stops = vec![start.node_index];
+        stops.extend(order.iter().map(|&i| via_nodes[i]));
+        stops.push(goal.node_index);
+
+        let mut outcome = query.search_via(&stops, req.target_distance, req.tol, req.amount, &req.search_options(), search);
+        // legs only keep to the upper end, the whole route has to be in range
+        if let Some(range) = req.ascent {
+            outcome.paths.retain(|(path, _)| range.contains(query.ascent(path)));
+        }
+        // whatever order the search found them in, the newest roads first
+        if let Some(novelty) = &req.novelty {
+            let mut ranked: Vec<(f64, (Vec<usize>, f64))> = outcome
+                .paths
+                .into_iter()
+                .map(|(path, length)| (query.novelty_cost(novelty, &path, length, req.target_distance), (path, length)))
+                .collect();
+            ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+            outcome.paths = ranked.into_iter().map(|(_, path)| path).collect();
+        }
+        let paths = query.convert_to_paths(&outcome.paths);
+        Ok(PathsResponse { start, goal, via: order.iter().map(|&i| via[i]).collect(), stopped: outcome.stopped, seed: None, paths })
+    }
+
+}
+
+
+pub(crate) fn check_snap_distance(point: &'static str, snap: &SnapInfo, max: Option<f64>) -> Result<(), RouteError> {
+    match max {
+        Some(max) if snap.distance > max => Err(RouteError::SnapTooFar { point, distance: snap.distance, max }),
+        _ => Ok(()),
+    }
+}
+
+
+// projects a coordinate onto the segment a-b, using an equirectangular
+// approximation around the coordinate (fine at the length of a road segment)
+// returns the fraction along the segment and the projected lat/lon
+fn project_onto_segment(lat: f64, lon: f64, a: &Node, b: &Node) -> (f64, f64, f64) {
+    let cos_lat = lat.to_radians().cos();
+    let (ax, ay) = ((a.lon() - lon) * cos_lat, a.lat() - lat);
+    let (bx, by) = ((b.lon() - lon) * cos_lat, b.lat() - lat);
+    let (dx, dy) = (bx - ax, by - ay);
+    let len2 = dx * dx + dy * dy;
+    let fraction = if len2 == 0.0 {
+        0.0
+    } else {
+        (-(ax * dx + ay * dy) / len2).clamp(0.0, 1.0)
+    };
+    (
+        fraction,
+        a.lat() + fraction * (b.lat() - a.lat()),
+        a.lon() + fraction * (b.lon() - a.lon()),
+    )
+}
+
+
+impl RepeatPolicy {
+    // what stepping onto `v` from `u` uses up: the node itself, or the road
+    // between the two with the lower index first
+    fn key(self, u: Option<usize>, v: usize) -> Option<(usize, usize)> {
+        match self {
+            RepeatPolicy::NoNodeRepeats => Some((v, v)),
+            RepeatPolicy::NoEdgeRepeats | RepeatPolicy::MaxEdgeUses(_) => u.map(|u| (u.min(v), u.max(v))),
+        }
+    }
+
+    fn max_uses(self) -> usize {
+        match self {
+            RepeatPolicy::NoNodeRepeats | RepeatPolicy::NoEdgeRepeats => 1,
+            RepeatPolicy::MaxEdgeUses(n) => n.max(1) as usize,
+        }
+    }
+
+    /// Whether the path ending in `states[idx]` may go on to `next`.
+    /// The bloom filter answers most of these, only when it may have seen
+    /// the key the whole path is walked to count exactly.
+    fn allows(self, states: &[SearchState], idx: usize, next: usize, goal: usize) -> bool {
+        if self == RepeatPolicy::NoNodeRepeats && next == goal {
+            return true;
+        }
+        let Some(key) = self.key(Some(states[idx].node), next) else {
+            return true;
+        };
+        if states[idx].trail & bloom_bit(key) == 0 {
+            return true;
+        }
+        let mut uses = 0;
+        let mut cur = Some(idx);
+        while let Some(i) = cur {
+            let prev = states[i].prev;
+            if self.key(prev.map(|p| states[p].node), states[i].node) == Some(key) {
+                uses += 1;
             }
+            cur = prev;
         }
-        false
+        uses < self.max_uses()
     }
 
-    pub fn bfs(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64) -> Vec<(Vec<usize>,f64)> {
+    /// Whether a whole path keeps to the policy, for searches that build
+    /// paths some other way than step by step
+    pub(crate) fn permits(self, path: &[usize]) -> bool {
+        let Some(&goal) = path.last() else {
+            return true;
+        };
+        let mut uses: HashMap<(usize, usize), usize> = HashMap::new();
+        for (i, &v) in path.iter().enumerate() {
+            if self == RepeatPolicy::NoNodeRepeats && i > 0 && v == goal {
+                continue;
+            }
+            let Some(key) = self.key(i.checked_sub(1).map(|j| path[j]), v) else {
+                continue;
+            };
+            let count = uses.entry(key).or_default();
+            *count += 1;
+            if *count > self.max_uses() {
+                return false;
+            }
+        }
+        true
+    }
+
+    // the state after going from `states[idx]` to `next`
+    fn step(self, states: &[SearchState], idx: usize, next: usize, distance: f64, ascent: f64, known: f64) -> SearchState {
+        let key = self.key(Some(states[idx].node), next);
+        SearchState { node: next, prev: Some(idx), distance, ascent, known, trail: states[idx].trail | key.map_or(0, bloom_bit) }
+    }
+}
+
+// one of 128 bits for a node or road, Fibonacci hashing spreads neighbors apart
+fn bloom_bit((a, b): (usize, usize)) -> u128 {
+    let hash = (a as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (b as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    1u128 << (hash.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 57)
+}
+
+
+impl QueryGraph<'_> {
+    /// With `options.max_overlap` a path is only kept if it overlaps at most
+    /// that much with every path kept before, see `DiversityFilter`.
+    /// `options.repeats` decides which nodes or roads a path may pass again.
+    /// Stops early with what it has when `options.budget` runs out.
+    pub fn bfs(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64, options: &SearchOptions) -> SearchOutcome {
         let mut results = Vec::new();
         let mut states = Vec::new();
         let mut queue = VecDeque::new();
+        // road distance left to the goal, exact within the longest path allowed
+        let (d_goal, _) = self.reverse_tree(goal, target_distance + tol);
+        if d_goal[start] > target_distance + tol {
+            return SearchOutcome::default();
+        }
+        let mut diversity = DiversityFilter::new(options.max_overlap);
+        let repeats = options.repeats;
+        let climb = Climb::new(self, goal, options.ascent);
+        let mut meter = options.budget.meter();
 
-        states.push(SearchState {
-            node: start,
-            prev: None,
-            distance: 0.0,
-        });
+        states.push(SearchState::start(start, repeats));
         queue.push_back(0);
 
         while let Some(current) = queue.pop_front() {
             if results.len() >= k {
                 break; // stop if we have enough results
             }
+            let bytes = states.len() * size_of::<SearchState>() + queue.len() * size_of::<usize>();
+            if meter.exhausted(states.len(), bytes) {
+                break;
+            }
             let state = &states[current];
             let node = state.node;
             let distance_so_far = state.distance;
 
-            if node == goal && (distance_so_far - target_distance).abs() <= tol as f64 {
+            if node == goal && (distance_so_far - target_distance).abs() <= tol && climb.as_ref().is_none_or(|c| c.range.contains(state.ascent)) {
                 // found a valid path
                 // reconstruct the path
                 let mut path = Vec::new();
@@ -168,61 +462,66 @@ impl Graph {
                 }
                 path.reverse(); // don't know if this really matters, don't think it does for other
                 // implementations
-                results.push((path, distance_so_far));
+                if diversity.accept(self, &path) {
+                    results.push((path, distance_so_far));
+                }
                 continue;
             }
 
-            for neighbor in &self.adj[node] {
+            for neighbor in self.neighbors(node) {
                 let next = neighbor.node_index;
-                if (Self::in_ancestry(&states, current, next, MAX_LOOKBACK)) && next != goal {
+                if !repeats.allows(&states, current, next, goal) {
                     continue;
                 }
 
                 let new_distance = distance_so_far + neighbor.edge_data.length_m;
 
                 // check if we can still reach the target distance
-                if new_distance > target_distance + tol as f64 {
+                if new_distance > target_distance + tol {
                     continue; // skip paths that exceed the target distance
                 }
-                if new_distance + haversine_distance(
-                    self.nodes[next].lat(),
-                    self.nodes[next].lon(),
-                    self.nodes[goal].lat(),
-                    self.nodes[goal].lon(),
-                ) > target_distance + tol as f64 {
+                if new_distance + d_goal[next] > target_distance + tol {
                     continue; // skip paths that cannot reach the goal within tolerance
                 }
-                states.push(SearchState {
-                    node: next,
-                    prev: Some(current),
-                    distance: new_distance,
-                });
+                let new_ascent = states[current].ascent + climb.as_ref().map_or(0.0, |c| c.up(node, next));
+                if climb.as_ref().is_some_and(|c| !c.feasible(next, new_ascent)) {
+                    continue; // climbed too much already
+                }
+                states.push(repeats.step(&states, current, next, new_distance, new_ascent, 0.0));
                 queue.push_back(states.len() - 1);
             }
         }
-        results
+        SearchOutcome { paths: results, stopped: meter.stopped() }
     }
 
-    pub fn special_dijkstra(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64) -> Vec<(Vec<usize>,f64)> {
+    pub fn special_dijkstra(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64, options: &SearchOptions) -> SearchOutcome {
         let mut results = Vec::new();
+        let mut diversity = DiversityFilter::new(options.max_overlap);
         let mut heap = BinaryHeap::new();
-        let mut states: Vec<SpecialDijkstraState> = Vec::new();
-        states.push(SpecialDijkstraState {
-            node: start,
-            prev: None,
-            distance: 0.0,
-        });
-        let d0 = haversine_distance(self.nodes[start].lat(), self.nodes[start].lon(), self.nodes[goal].lon(), self.nodes[goal].lon());
+        let mut states: Vec<SearchState> = Vec::new();
+        let (d_goal, _) = self.reverse_tree(goal, target_distance + tol);
+        if d_goal[start] > target_distance + tol {
+            return SearchOutcome::default();
+        }
+        let repeats = options.repeats;
+        let climb = Climb::new(self, goal, options.ascent);
+        let novelty = options.novelty.as_ref();
+        let mut meter = options.budget.meter();
+        states.push(SearchState::start(start, repeats));
         heap.push(HeapItem {
             state_idx: 0,
-            priority: (d0 - target_distance).abs(), 
+            priority: (d_goal[start] - target_distance).abs(),
         });
 
         while let Some(heap_item) = heap.pop() {
+            let bytes = states.len() * size_of::<SearchState>() + heap.len() * size_of::<HeapItem>();
+            if meter.exhausted(states.len(), bytes) {
+                break;
+            }
             let state = &states[heap_item.state_idx];
             let current = state.node;
             let distance_so_far = state.distance;
-            if current == goal && (distance_so_far - target_distance).abs() <= tol as f64 {
+            if current == goal && (distance_so_far - target_distance).abs() <= tol && climb.as_ref().is_none_or(|c| c.range.contains(state.ascent)) {
                 // found a valid path
                 // reconstruct the path
                 let mut path = Vec::new();
@@ -233,50 +532,132 @@ impl Graph {
                 }
                 path.reverse(); // don't know if this really matters, don't think it does for other
                 // implementations
-                results.push((path, distance_so_far));
+                if diversity.accept(self, &path) {
+                    results.push((path, distance_so_far));
+                }
                 if results.len() >= k {
                     break; // stop if we have enough results
                 }
             }
 
-            for neighbor in &self.adj[current] {
+            for neighbor in self.neighbors(current) {
                 let next = neighbor.node_index;
                 let new_distance = distance_so_far + neighbor.edge_data.length_m;
 
-                if (Self::in_ancestry_dijkstra(&states, heap_item.state_idx, next, MAX_LOOKBACK)) && next != goal {
+                if !repeats.allows(&states, heap_item.state_idx, next, goal) {
                     continue;
                 }
                 // prune nodes too far away
-                if new_distance > target_distance + tol as f64 {
+                if new_distance > target_distance + tol {
                     continue; 
                 }
+                // and nodes the goal can't be reached from in time
+                if new_distance + d_goal[next] > target_distance + tol {
+                    continue;
+                }
+                // and paths that climbed too much already
+                let new_ascent = states[heap_item.state_idx].ascent + climb.as_ref().map_or(0.0, |c| c.up(current, next));
+                if climb.as_ref().is_some_and(|c| !c.feasible(next, new_ascent)) {
+                    continue;
+                }
 
-                let d = haversine_distance(self.nodes[next].lat(), self.nodes[next].lon(), self.nodes[goal].lat(), self.nodes[goal].lon()) + distance_so_far;
-                let priority = (d - target_distance).abs();
+                // how far off the target going straight to the goal from here would be,
+                // plus what the roads run before cost
+                let new_known = states[heap_item.state_idx].known + self.known_length(novelty, current, next, neighbor.edge_data.length_m);
+                let priority = (new_distance + d_goal[next] - target_distance).abs() + novelty.map_or(0.0, |n| n.weight * new_known);
 
-                states.push(SpecialDijkstraState {
-                    node: next,
-                    prev: Some(heap_item.state_idx),
-                    distance: new_distance,
-                });
+                states.push(repeats.step(&states, heap_item.state_idx, next, new_distance, new_ascent, new_known));
                 heap.push(HeapItem {
                     state_idx: states.len() - 1,
                     priority,
                 });
+            }
+        }
+        SearchOutcome { paths: results, stopped: meter.stopped() }
+    }
+
+    /// Goes edge by edge like `bfs`, but of all partial paths with the same
+    /// number of edges only keeps the `width` best, scored by how far their
+    /// length plus the road distance left to the goal is off `target_distance`. At
+    /// most `width` times the largest degree new states per edge, so time and
+    /// memory no longer depend on how many paths the graph has. With
+    /// `options.novelty` roads run before add to the score.
+    #[allow(clippy::too_many_arguments)]
+    pub fn beam_search(&self, start: usize, goal: usize, k: usize, target_distance: f64, tol: f64, width: usize, options: &SearchOptions) -> SearchOutcome {
+        let mut results = Vec::new();
+        let mut diversity = DiversityFilter::new(options.max_overlap);
+        let (d_goal, _) = self.reverse_tree(goal, target_distance + tol);
+        if d_goal[start] > target_distance + tol {
+            return SearchOutcome::default();
+        }
+        let repeats = options.repeats;
+        let climb = Climb::new(self, goal, options.ascent);
+        let novelty = options.novelty.as_ref();
+        let mut meter = options.budget.meter();
+        let mut states = vec![SearchState::start(start, repeats)];
+        let mut beam = vec![0];
+
+        'search: while !beam.is_empty() {
+            // (score, index in states) of the partial paths one edge longer
+            let mut candidates: Vec<(f64, usize)> = Vec::new();
+            for &current in &beam {
+                let bytes = states.len() * size_of::<SearchState>() + (beam.len() + candidates.len()) * size_of::<(f64, usize)>();
+                if meter.exhausted(states.len(), bytes) {
+                    break 'search;
+                }
+                let state = &states[current];
+                let node = state.node;
+                let distance_so_far = state.distance;
+
+                if node == goal && (distance_so_far - target_distance).abs() <= tol && climb.as_ref().is_none_or(|c| c.range.contains(state.ascent)) {
+                    let mut path = Vec::new();
+                    let mut cur = Some(current);
+                    while let Some(i) = cur {
+                        path.push(states[i].node);
+                        cur = states[i].prev;
+                    }
+                    path.reverse();
+                    if diversity.accept(self, &path) {
+                        results.push((path, distance_so_far));
+                    }
+                    if results.len() >= k {
+                        break 'search;
+                    }
+                    continue;
+                }
 
-                if states.len() % 100000 == 0 {
-                    println!("states len: {}",states.len());
+                for neighbor in self.neighbors(node) {
+                    let next = neighbor.node_index;
+                    if !repeats.allows(&states, current, next, goal) {
+                        continue;
+                    }
+                    let new_distance = distance_so_far + neighbor.edge_data.length_m;
+                    // can't reach the goal within tolerance anymore
+                    if new_distance + d_goal[next] > target_distance + tol {
+                        continue;
+                    }
+                    let new_ascent = states[current].ascent + climb.as_ref().map_or(0.0, |c| c.up(node, next));
+                    if climb.as_ref().is_some_and(|c| !c.feasible(next, new_ascent)) {
+                        continue;
+                    }
+                    let new_known = states[current].known + self.known_length(novelty, node, next, neighbor.edge_data.length_m);
+                    states.push(repeats.step(&states, current, next, new_distance, new_ascent, new_known));
+                    let score = (target_distance - new_distance - d_goal[next]).abs() + novelty.map_or(0.0, |n| n.weight * new_known);
+                    candidates.push((score, states.len() - 1));
                 }
             }
+            candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+            candidates.truncate(width);
+            beam = candidates.into_iter().map(|(_, idx)| idx).collect();
         }
-        results
+        SearchOutcome { paths: results, stopped: meter.stopped() }
     }
 
 
 
 
-    fn dijkstra(&self, goal: usize, max_dist: Option<f64>) -> (Vec<f64>,Vec<Option<usize>>) {
-        let n = self.nodes.len();
+    pub(crate) fn dijkstra(&self, goal: usize, max_dist: Option<f64>) -> (Vec<f64>,Vec<Option<usize>>) {
+        let n = self.node_count();
         let mut dist = vec![f64::INFINITY; n];
         let mut heap = BinaryHeap::new();
         let mut parent = vec![None; n];
@@ -289,11 +670,9 @@ impl Graph {
             if du > dist[u] { continue; }
 
             // stop exploring once du exceeds a cutoff
-            if let Some(cutoff) = max_dist {
-                if du > cutoff { break; }
-            }
+            if let Some(cutoff) = max_dist && du > cutoff { break; }
 
-            for edge in &self.adj[u] {
+            for edge in self.neighbors(u) {
                 let v = edge.node_index;
                 let dv = du + edge.edge_data.length_m;
                 if dv < dist[v] {
@@ -308,8 +687,14 @@ impl Graph {
     }
 
 
-    fn recursive_dfs(&self, node: usize, traversed_distance: f64, start: usize, d_goal: &Vec<f64>,
-        target: f64, tol: f64, rng: &mut impl rand::Rng, path: &mut Vec<usize>, visited: &mut HashSet<usize>) -> Option<(Vec<usize>,f64)> {
+    #[allow(clippy::too_many_arguments)]
+    fn recursive_dfs(&self, node: usize, traversed_distance: f64, start: usize, d_goal: &[f64],
+        target: f64, tol: f64, rng: &mut impl rand::Rng, path: &mut Vec<usize>, visited: &mut HashSet<usize>, meter: &mut Meter) -> Option<(Vec<usize>,f64)> {
+        // every call is a state, the path and visited set are all it keeps
+        let bytes = path.len() * size_of::<usize>() * 3;
+        if meter.exhausted(path.len(), bytes) {
+            return None;
+        }
         // Check midpoint condition (skip the start node)
         if node != start {
             let total = traversed_distance+ d_goal[node];
@@ -319,8 +704,8 @@ impl Graph {
         }
 
         // Gather and shuffle neighbors
-        let mut neighs: Vec<_> = self.adj[node]
-            .iter()
+        let mut neighs: Vec<_> = self
+            .neighbors(node)
             .map(|e| e.node_index)
             .collect();
         neighs.shuffle(rng);
@@ -329,8 +714,8 @@ impl Graph {
             if visited.contains(&v) {
                 continue;
             }
-            let edge_len = self.adj[node]
-                .iter()
+            let edge_len = self
+                .neighbors(node)
                 .find(|e| e.node_index == v)
                 .unwrap()
             .edge_data.length_m;
@@ -346,10 +731,13 @@ impl Graph {
             path.push(v);
             if let Some(solution) = Self::recursive_dfs(
                 self, v, new_traversed_distance, start, d_goal, target, tol,
-                rng, path, visited,
+                rng, path, visited, meter,
             ) {
                 return Some(solution);
             }
+            if meter.stopped().is_some() {
+                return None;
+            }
             path.pop();
             visited.remove(&v);
         }
@@ -357,24 +745,32 @@ impl Graph {
     }
 
 
-    fn find_paths_with_dfs(&self, start: usize, goal: usize, k:usize, target_distance: f64, tol: f64) -> Option<Vec<(Vec<usize>,f64)>> {
-        let (d_goal, parent) = self.dijkstra(goal, Some(target_distance + tol));
+    fn find_paths_with_dfs(&self, start: usize, goal: usize, k:usize, target_distance: f64, tol: f64, options: &SearchOptions) -> SearchOutcome {
+        let (d_goal, parent) = self.reverse_tree(goal, target_distance + tol);
 
-        // If the goal is unreachable, return None
+        // If the goal is unreachable, there is nothing to find
         if d_goal[start].is_infinite() {
-            return None;
+            return SearchOutcome::default();
         }
+        let max_overlap = options.max_overlap;
+        let mut meter = options.budget.meter();
 
-        let mut rng = rng();
+        let mut rng = options.seed.map_or_else(|| StdRng::from_rng(&mut rng()), StdRng::seed_from_u64);
         let mut results: Vec<(Vec<usize>,f64)> = Vec::new();
+        let mut diversity = DiversityFilter::new(max_overlap);
+        // rejected paths get a few more random tries
+        let attempts = if max_overlap.is_some() || options.ascent.is_some() { k * DFS_ATTEMPTS_PER_PATH } else { k };
 
-        for _ in 0..k {
+        for _ in 0..attempts {
+            if results.len() >= k || meter.stopped().is_some() {
+                break;
+            }
             // Prepare for DFS
             let mut path = vec![start];
             let mut visited = HashSet::new();
             visited.insert(start);
             // Find one random midpoint path
-            if let Some(( mut outbound, traversed_distance)) = self.recursive_dfs(start, 0.0, start, &d_goal, target_distance, tol, &mut rng, &mut path, &mut visited) {
+            if let Some(( mut outbound, traversed_distance)) = self.recursive_dfs(start, 0.0, start, &d_goal, target_distance, tol, &mut rng, &mut path, &mut visited, &mut meter) {
                 // midpoint
                 let mid = *outbound.last().unwrap();
                 // Reconstruct inbound via parent pointers
@@ -391,82 +787,31 @@ impl Graph {
                 // Combine (avoid duplicate mid)
                 outbound.pop();
                 outbound.extend(inbound);
+                // the dfs doesn't track climbs, whole paths out of range are another try
+                if options.ascent.is_some_and(|range| !range.contains(self.ascent(&outbound))) {
+                    continue;
+                }
                 // Create path object
-                results.push((outbound, traversed_distance + d_goal[mid]));
+                if diversity.accept(self, &outbound) {
+                    results.push((outbound, traversed_distance + d_goal[mid]));
+                }
             }
         }
 
-        Some(results)
+        SearchOutcome { paths: results, stopped: meter.stopped() }
     }
 
 
-    // maps a lat and lon to a node in the graph
-    // retursn the index of the node in the nodes array
-    fn map_lat_lon_to_node(&self, lat: f64, lon: f64) -> usize {
-        let mut idx: usize = 0;
-        let mut shortest_dist = haversine_distance(lat, lon, self.nodes[0].lat(), self.nodes[0].lon());
 
-        for (i, node) in self.nodes.iter().enumerate().skip(1) {
-            let d = haversine_distance(lat, lon, node.lat(), node.lon());
-            if d < shortest_dist {
-                shortest_dist = d;
-                idx = i;
-            }
-        }
-        idx
-    }
-
-
-    fn convert_to_path(&self, indicies: &Vec<usize>, distance: f64) -> Path {
+    pub(crate) fn convert_to_path(&self, indicies: &[usize], distance: f64) -> Path {
         let mut nodes: Vec<Node> = Vec::new();
         for idx in indicies.iter() {
-            nodes.push(self.nodes[*idx]);
-        }
-        Path::new(nodes, distance)
-    }
-
-    pub fn get_paths_bfs(&self, start_lat: f64,start_lon:f64,   goal_lat: f64, goal_lon:f64, k: usize, target_distance: f64, tol: f64) -> Vec<Path> {
-        let mut paths = Vec::new();
-        let start_idx = self.map_lat_lon_to_node(start_lat, start_lon);
-        let goal_idx = self.map_lat_lon_to_node(goal_lat, goal_lon);
-        println!("Start node index: {}, Goal node index: {}", start_idx, goal_idx);
-        let solutions = self.bfs(start_idx, goal_idx, k,target_distance, tol);
-        for (solution,dist) in solutions.iter() {
-            paths.push(self.convert_to_path(solution, *dist));
-        }
-
-        paths
-    }
-    
-    pub fn get_paths_dfs(&self, start_lat: f64, start_lon: f64, goal_lat: f64, goal_lon: f64, k: usize, target_distance: f64, tol: f64) -> Option<Vec<Path>> {
-        let mut paths = Vec::new();
-        let start_idx = self.map_lat_lon_to_node(start_lat, start_lon);
-        let goal_idx = self.map_lat_lon_to_node(goal_lat, goal_lon);
-        println!("Start node index: {}, Goal node index: {}", start_idx, goal_idx);
-        let solutions = self.find_paths_with_dfs(start_idx, goal_idx, k, target_distance, tol);
-        if let Some(solutions) = solutions {
-            for (solution,dist) in solutions.iter() {
-                paths.push(self.convert_to_path(solution, *dist));
-            }
+            nodes.push(*self.node(*idx));
         }
-        Some(paths)
-    }
-
-    pub fn get_paths_special_dijkstra(
-        &self, start_lat: f64, start_lon: f64, goal_lat: f64, goal_lon: f64, k: usize, target_distance: f64, tol: f64
-    ) -> Vec<Path> {
-        let mut paths = Vec::new();
-        let start_idx = self.map_lat_lon_to_node(start_lat, start_lon);
-        let goal_idx = self.map_lat_lon_to_node(goal_lat, goal_lon);
-        println!("Start node index: {}, Goal node index: {}", start_idx, goal_idx);
-        let solutions = self.special_dijkstra(start_idx, goal_idx, k,target_distance, tol);
-        for (solution,dist) in solutions.iter() {
-            paths.push(self.convert_to_path(solution, *dist));
-        }
-
-        paths
+        let mut path = Path::new(nodes, distance);
+        path.criteria = self.criteria(indicies);
+        path
     }
-
 }
 
 
@@ -477,13 +822,10 @@ impl Graph {
 
 
 
-
-
-
-
 #[cfg(test)]
 mod constrained_bfs_tests {
     use super::*;
+    use crate::budget::Budget;
     use crate::model::{Node, Neighbor, EdgeData};
 
     fn make_node(id: u64) -> Node {
@@ -654,6 +996,69 @@ mod constrained_bfs_tests {
         ];
         assert_eq!(paths, expected);
     }
+
+    // every path from start to goal within tol of target that passes no node
+    // but the goal twice, by brute force
+    fn all_paths(graph: &Graph, start: usize, goal: usize, target: f64, tol: f64) -> Vec<Vec<usize>> {
+        fn extend(graph: &Graph, path: &mut Vec<usize>, length: f64, goal: usize, target: f64, tol: f64, found: &mut Vec<Vec<usize>>) {
+            if length > target + tol {
+                return;
+            }
+            let node = *path.last().unwrap();
+            if node == goal && path.len() > 1 && (length - target).abs() <= tol {
+                found.push(path.clone());
+                return;
+            }
+            for neighbor in &graph.adj()[node] {
+                if neighbor.node_index == goal || !path.contains(&neighbor.node_index) {
+                    path.push(neighbor.node_index);
+                    extend(graph, path, length + neighbor.edge_data.length_m, goal, target, tol, found);
+                    path.pop();
+                }
+            }
+        }
+        let mut found = Vec::new();
+        extend(graph, &mut vec![start], 0.0, goal, target, tol, &mut found);
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn exact_pruning_keeps_every_path() {
+        for (graph, goal) in [(build_diamond(), 3), (build_complex(), 5)] {
+            for (target, tol) in [(6.0, 0.0), (6.0, 1.0), (8.0, 0.0), (10.0, 0.0), (11.0, 2.0), (20.0, 5.0)] {
+                let expected = all_paths(&graph, 0, goal, target, tol);
+                for found in [graph.bfs(0, goal, 100, target, tol), graph.special_dijkstra(0, goal, 100, target, tol)] {
+                    let mut paths: Vec<Vec<usize>> = found.into_iter().map(|(path, _)| path).collect();
+                    paths.sort();
+                    assert_eq!(paths, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dead_ends_are_pruned_right_away() {
+        // the goal 10 m away and a dead end of 30 nodes off the start,
+        // all nodes in one place so the straight line distance says nothing
+        let mut adj = vec![Vec::new(); 32];
+        let mut edge = |u: usize, v: usize, length_m: f64| {
+            adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data: EdgeData { way_id: 0, length_m } });
+            adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data: EdgeData { way_id: 0, length_m } });
+        };
+        edge(0, 1, 10.0);
+        edge(0, 2, 1.0);
+        for v in 2..31 {
+            edge(v, v + 1, 1.0);
+        }
+        let graph = Graph::new((0..32).map(make_node).collect(), adj);
+        let query = QueryGraph::new(&graph);
+        let options = SearchOptions { budget: Budget { max_states: Some(2), ..Default::default() }, ..Default::default() };
+        for found in [query.bfs(0, 1, 5, 10.0, 0.5, &options), query.special_dijkstra(0, 1, 5, 10.0, 0.5, &options)] {
+            assert_eq!(found.stopped, None);
+            assert_eq!(found.paths, vec![(vec![0, 1], 10.0)]);
+        }
+    }
 }
 
 
@@ -790,7 +1195,7 @@ mod loop_tests {
 
         let (path, dist) = &paths[0];
         assert_eq!(path, &vec![1, 2, 3, 1], "wrong loop sequence");
-        assert!((dist - 15.0).abs() < std::f64::EPSILON, "wrong loop distance");
+        assert!((dist - 15.0).abs() < f64::EPSILON, "wrong loop distance");
     }
 }
 
@@ -856,10 +1261,7 @@ mod special_dijkstra_tests {
     #[test]
     fn sd_returns_within_tolerance() {
         let graph = build_diamond();
-        let results = graph.special_dijkstra(0, 3, 5, 11.0, 2.0)
-            .into_iter()
-            .map(|(p, d)| (p, d))
-            .collect::<Vec<_>>();
+        let results = graph.special_dijkstra(0, 3, 5, 11.0, 2.0);
         // Expect both routes [0,1,3]=10 and [0,2,3]=12
         let mut paths: Vec<Vec<usize>> = results.iter().map(|(p, _)| p.clone()).collect();
         paths.sort();
@@ -952,3 +1354,361 @@ mod special_dijkstra_tests {
     }
 }
 
+
+
+#[cfg(test)]
+mod repeat_policy_tests {
+    use super::*;
+    use crate::model::{EdgeData, Neighbor, Node};
+    use crate::test_util::build_grid;
+
+    fn build(n: usize, edges: &[(usize, usize, f64)]) -> Graph {
+        let nodes = (0..n).map(|i| Node::new(i as u64, 0.0, 0.0)).collect();
+        let mut adj = vec![Vec::new(); n];
+        for &(u, v, length_m) in edges {
+            let edge_data = EdgeData { way_id: 0, length_m };
+            adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data });
+            adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data });
+        }
+        Graph::new(nodes, adj)
+    }
+
+    fn both(graph: &Graph, start: usize, goal: usize, target: f64, repeats: RepeatPolicy) -> [Vec<Vec<usize>>; 2] {
+        let query = QueryGraph::new(graph);
+        let options = SearchOptions { repeats, ..Default::default() };
+        let sorted = |results: Vec<(Vec<usize>, f64)>| {
+            let mut paths: Vec<Vec<usize>> = results.into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+            paths
+        };
+        [
+            sorted(query.bfs(start, goal, 5, target, 0.0, &options).paths),
+            sorted(query.special_dijkstra(start, goal, 5, target, 0.0, &options).paths),
+        ]
+    }
+
+    #[test]
+    fn revisits_far_back_in_the_path_are_caught() {
+        // a ring of 120 nodes through the start and the goal hanging off the start,
+        // the only 121 m paths go round the ring and pass the start again
+        let mut edges: Vec<(usize, usize, f64)> = (0..120).map(|i| (i, (i + 1) % 120, 1.0)).collect();
+        edges.push((0, 120, 1.0));
+        let graph = build(121, &edges);
+
+        for paths in both(&graph, 0, 120, 121.0, RepeatPolicy::NoNodeRepeats) {
+            assert!(paths.is_empty());
+        }
+        for paths in both(&graph, 0, 120, 121.0, RepeatPolicy::NoEdgeRepeats) {
+            assert_eq!(paths.len(), 2);
+            assert!(paths.iter().all(|path| path.len() == 122 && path[120] == 0));
+        }
+    }
+
+    #[test]
+    fn edges_can_be_used_a_limited_number_of_times() {
+        // the diamond 0-1-3 (5 m edges) and 0-2-3 (6 m edges), 22 m only works
+        // by driving one edge of each side twice
+        let graph = build(4, &[(0, 1, 5.0), (1, 3, 5.0), (0, 2, 6.0), (2, 3, 6.0)]);
+        for paths in both(&graph, 0, 3, 22.0, RepeatPolicy::NoEdgeRepeats) {
+            assert!(paths.is_empty());
+        }
+        for paths in both(&graph, 0, 3, 22.0, RepeatPolicy::MaxEdgeUses(2)) {
+            assert_eq!(paths, vec![vec![0, 1, 0, 2, 3], vec![0, 1, 3, 2, 3], vec![0, 2, 0, 1, 3], vec![0, 2, 3, 1, 3]]);
+        }
+        // 25 m from 0 to 1 takes five 5 m edges, one of the two gets driven three times
+        for paths in both(&graph, 0, 1, 25.0, RepeatPolicy::MaxEdgeUses(2)) {
+            assert!(paths.is_empty());
+        }
+    }
+
+    #[test]
+    fn snapped_start_is_not_passed_again_over_its_edge() {
+        // 2 × 2 grid, start in the middle of the road 0 - 1: back to 0 and on
+        // to 1 over the whole road would run over the start again
+        let graph = build_grid(2);
+        let mut query = QueryGraph::new(&graph);
+        let start = query.snap(&[(0.0, 0.0005)])[0].node_index;
+        let block = crate::builder::haversine_distance(0.0, 0.0, 0.0, 0.001);
+        let options = SearchOptions { repeats: RepeatPolicy::NoEdgeRepeats, ..Default::default() };
+        let target = 2.5 * block;
+        for outcome in [query.bfs(start, 3, 5, target, 1.0, &options), query.special_dijkstra(start, 3, 5, target, 1.0, &options)] {
+            let paths: Vec<Vec<usize>> = outcome.paths.into_iter().map(|(path, _)| path).collect();
+            assert_eq!(paths, vec![vec![start, 0, 2, 3]]);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod budget_tests {
+    use super::*;
+    use crate::budget::{Budget, CancelToken};
+    use crate::model::{EdgeData, Neighbor, Node, StopReason};
+
+    // 2 × 6 ladder of 1 m edges, lots of 10 m paths from one end to the other
+    fn build_ladder() -> Graph {
+        let n = 12;
+        let nodes = (0..n).map(|i| Node::new(i as u64, 0.0, 0.0)).collect();
+        let mut adj = vec![Vec::new(); n];
+        let mut edge = |u: usize, v: usize| {
+            let edge_data = EdgeData { way_id: 0, length_m: 1.0 };
+            adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data });
+            adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data });
+        };
+        for i in 0..n / 2 - 1 {
+            edge(i, i + 1);
+            edge(n / 2 + i, n / 2 + i + 1);
+        }
+        for i in 0..n / 2 {
+            edge(i, n / 2 + i);
+        }
+        Graph::new(nodes, adj)
+    }
+
+    #[test]
+    fn exhausted_searches_return_what_they_have() {
+        let graph = build_ladder();
+        let query = QueryGraph::new(&graph);
+        let unlimited = SearchOptions::default();
+        let limited = SearchOptions { budget: Budget { max_states: Some(20), ..Default::default() }, ..Default::default() };
+        let searches: [&dyn Fn(&SearchOptions) -> SearchOutcome; 2] = [
+            &|options| query.bfs(0, 11, 50, 10.0, 0.0, options),
+            &|options| query.special_dijkstra(0, 11, 50, 10.0, 0.0, options),
+        ];
+        for search in searches {
+            let all = search(&unlimited);
+            assert_eq!(all.stopped, None);
+            let partial = search(&limited);
+            assert_eq!(partial.stopped, Some(StopReason::StateLimit));
+            assert!(partial.paths.len() < all.paths.len());
+            assert!(partial.paths.iter().all(|found| all.paths.contains(found)));
+        }
+
+        let token = CancelToken::new();
+        token.cancel();
+        let cancelled = SearchOptions { budget: Budget { cancel: Some(token), ..Default::default() }, ..Default::default() };
+        let res = query.find_paths_with_dfs(0, 11, 5, 10.0, 0.0, &cancelled);
+        assert!(res.paths.is_empty());
+        assert_eq!(res.stopped, Some(StopReason::Cancelled));
+    }
+}
+
+
+#[cfg(test)]
+mod dfs_seed_tests {
+    use super::*;
+    use crate::model::{Node, ViaOrder};
+    use crate::test_util::build_grid;
+
+    fn query(seed: Option<u64>) -> PathQuery {
+        PathQuery {
+            start_lat: 0.0,
+            start_lon: 0.0,
+            goal_lat: 0.005,
+            goal_lon: 0.005,
+            amount: 5,
+            target_distance: 2000.0,
+            tol: 50.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via: Vec::new(),
+            via_order: ViaOrder::Ordered,
+            avoid: Default::default(),
+            repeats: Default::default(),
+            budget: Default::default(),
+            seed,
+            ascent: None,
+            novelty: None,
+        }
+    }
+
+    fn node_ids(res: &PathsResponse) -> Vec<Vec<u64>> {
+        res.paths.iter().map(|path| path.nodes.iter().map(Node::id).collect()).collect()
+    }
+
+    #[test]
+    fn the_seed_decides_the_paths() {
+        let graph = build_grid(6);
+        let first = graph.get_paths_dfs(&query(None)).unwrap();
+        let seed = first.seed.unwrap();
+        assert!(seed < 1 << 53);
+        assert!(!first.paths.is_empty());
+
+        // sending the seed back gives the very same paths
+        let again = graph.get_paths_dfs(&query(Some(seed))).unwrap();
+        assert_eq!(again.seed, Some(seed));
+        assert_eq!(node_ids(&again), node_ids(&first));
+
+        let others: Vec<_> = (1..=5).map(|seed| node_ids(&graph.get_paths_dfs(&query(Some(seed))).unwrap())).collect();
+        assert!(others.iter().any(|paths| *paths != others[0]));
+    }
+}
+
+
+#[cfg(test)]
+mod beam_tests {
+    use super::*;
+    use crate::budget::Budget;
+    use crate::test_util::build_grid;
+
+    #[test]
+    fn beam_finds_paths_within_tolerance() {
+        let graph = build_grid(6);
+        let query = QueryGraph::new(&graph);
+        // corner to corner is 10 blocks, ask for 16
+        let target = 16.0 * 111.2;
+        let res = query.beam_search(0, 35, 5, target, 30.0, 50, &SearchOptions::default());
+        assert_eq!(res.paths.len(), 5);
+        for (path, distance) in &res.paths {
+            assert_eq!((path[0], path[path.len() - 1]), (0, 35));
+            assert!((distance - target).abs() <= 30.0);
+            let mut nodes = path.clone();
+            nodes.sort();
+            nodes.dedup();
+            assert_eq!(nodes.len(), path.len());
+        }
+    }
+
+    #[test]
+    fn the_width_bounds_the_states() {
+        let graph = build_grid(6);
+        let query = QueryGraph::new(&graph);
+        let target = 18.0 * 111.2;
+        // at most 18 edges deep, 4 neighbors each
+        let width = 8;
+        let budget = Budget { max_states: Some(1 + 18 * width * 4), ..Default::default() };
+        let options = SearchOptions { budget, ..Default::default() };
+
+        let beam = query.beam_search(0, 35, 100, target, 30.0, width, &options);
+        assert_eq!(beam.stopped, None);
+        assert!(!beam.paths.is_empty());
+        // bfs tries every path there is
+        assert!(query.bfs(0, 35, 100, target, 30.0, &options).stopped.is_some());
+    }
+}
+
+
+#[cfg(test)]
+mod edge_snap_tests {
+    use super::*;
+    use crate::builder::GraphBuilder;
+    use crate::model::{Avoid, ViaOrder, Way};
+
+    /// Straight road along the equator, roughly 1.1 km between nodes:
+    ///
+    ///   1 ---------- 2 ---------- 3
+    /// (0,0)       (0,0.01)     (0,0.02)
+    fn build_road() -> Graph {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.01), Node::new(3, 0.0, 0.02)];
+        let mut builder = GraphBuilder::new();
+        builder.add_way(&Way::new(1, vec![1, 2, 3], nodes));
+        builder.build()
+    }
+
+    fn query(start_lat: f64, start_lon: f64, goal_lat: f64, goal_lon: f64, target_distance: f64) -> PathQuery {
+        PathQuery {
+            start_lat,
+            start_lon,
+            goal_lat,
+            goal_lon,
+            amount: 1,
+            target_distance,
+            tol: 1.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via: Vec::new(),
+            via_order: ViaOrder::Ordered,
+            avoid: Avoid::default(),
+            repeats: Default::default(),
+            budget: Default::default(),
+            seed: None,
+            ascent: None,
+            novelty: None,
+        }
+    }
+
+    #[test]
+    fn snaps_onto_edge_interior() {
+        let graph = build_road();
+        let snap = graph.snap_to_edge(0.001, 0.0025).unwrap();
+        assert_eq!((snap.from, snap.to), (0, 1));
+        assert!((snap.fraction - 0.25).abs() < 1e-6);
+        assert!(snap.lat.abs() < 1e-9);
+        assert!((snap.lon - 0.0025).abs() < 1e-9);
+        // about 111 m north of the road
+        assert!((snap.distance - 111.2).abs() < 1.0);
+    }
+
+    #[test]
+    fn snaps_onto_edge_end_past_the_road() {
+        let graph = build_road();
+        let snap = graph.snap_to_edge(0.0, 0.03).unwrap();
+        assert_eq!((snap.from, snap.to), (1, 2));
+        assert_eq!(snap.fraction, 1.0);
+    }
+
+    #[test]
+    fn no_edges_means_no_snap() {
+        let graph = Graph::new(vec![Node::new(1, 0.0, 0.0)], vec![Vec::new()]);
+        assert!(graph.snap_to_edge(0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn paths_start_and_end_on_the_road() {
+        let graph = build_road();
+        let expected = haversine_distance(0.0, 0.0025, 0.0, 0.0175);
+        let res = graph.get_paths_bfs(&query(0.0001, 0.0025, 0.0001, 0.0175, expected)).unwrap();
+        assert_eq!(res.paths.len(), 1);
+
+        let path = &res.paths[0];
+        // virtual start, node 2, virtual goal
+        assert_eq!(path.nodes.len(), 3);
+        assert!((path.nodes[0].lon() - 0.0025).abs() < 1e-9);
+        assert_eq!(path.nodes[1].id(), 2);
+        assert!((path.nodes[2].lon() - 0.0175).abs() < 1e-9);
+        assert!((path.distance - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn start_and_goal_on_the_same_edge() {
+        let graph = build_road();
+        let expected = haversine_distance(0.0, 0.002, 0.0, 0.008);
+        let paths = graph.get_paths_special_dijkstra(&query(0.0, 0.002, 0.0, 0.008, expected)).unwrap().paths;
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].nodes.len(), 2);
+        assert!((paths[0].distance - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reports_snap_diagnostics() {
+        let graph = build_road();
+        let expected = haversine_distance(0.0, 0.0025, 0.0, 0.01);
+        let res = graph.get_paths_dfs(&query(0.001, 0.0025, 0.0, 0.01, expected)).unwrap();
+
+        assert_eq!(res.start.node_id, None);
+        assert_eq!(res.start.way_id, Some(1));
+        assert!((res.start.lon - 0.0025).abs() < 1e-9);
+        assert!((res.start.distance - 111.2).abs() < 1.0);
+        // the goal lies exactly on node 2
+        assert_eq!(res.goal.node_id, Some(2));
+        assert!(res.goal.distance < 1e-6);
+        assert_eq!(res.paths.len(), 1);
+    }
+
+    #[test]
+    fn rejects_coordinates_beyond_max_snap_distance() {
+        let graph = build_road();
+        let mut req = query(0.0, 0.0025, 0.5, 0.01, 1000.0);
+        req.max_snap_distance = Some(500.0);
+        match graph.get_paths_bfs(&req) {
+            Err(RouteError::SnapTooFar { point, max, .. }) => {
+                assert_eq!(point, "goal");
+                assert_eq!(max, 500.0);
+            }
+            other => panic!("expected SnapTooFar, got {:?}", other.map(|r| r.paths.len())),
+        }
+
+        req.max_snap_distance = Some(100_000.0);
+        assert!(graph.get_paths_bfs(&req).is_ok());
+    }
+}
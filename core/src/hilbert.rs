@@ -0,0 +1,150 @@
+use crate::graph::Graph;
+use crate::model::Neighbor;
+
+// resolution of the curve, 2^16 cells along each axis (a few meters per cell on a city extract)
+const HILBERT_ORDER: u32 = 16;
+
+/// Position of cell (x, y) along a Hilbert curve that covers a 2^order × 2^order grid
+pub fn hilbert_index(mut x: u32, mut y: u32, order: u32) -> u64 {
+    let n: u32 = 1 << order;
+    let mut d: u64 = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += (s as u64) * (s as u64) * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+impl Graph {
+    /// Renumbers the nodes along a Hilbert curve over their coordinates and
+    /// remaps every neighbor index, so nodes that are close on the map are
+    /// also close in `nodes` and `adj`. Way tags and elevation are carried
+    /// over, the per-node tables of landmarks, contraction hierarchy and tree
+    /// cache are not, so add those afterwards.
+    pub fn reorder_hilbert(mut self) -> Graph {
+        debug_assert!(self.landmarks.is_none(), "add landmarks after reordering");
+        debug_assert!(self.ch.is_none(), "add the contraction hierarchy after reordering");
+        debug_assert!(self.tree_cache.is_none(), "add the tree cache after reordering");
+        let nodes = self.nodes();
+        let n = nodes.len();
+        if n == 0 {
+            return self;
+        }
+
+        let (mut min_lat, mut max_lat) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut min_lon, mut max_lon) = (f64::INFINITY, f64::NEG_INFINITY);
+        for node in nodes {
+            min_lat = min_lat.min(node.lat());
+            max_lat = max_lat.max(node.lat());
+            min_lon = min_lon.min(node.lon());
+            max_lon = max_lon.max(node.lon());
+        }
+        let max_cell = ((1u32 << HILBERT_ORDER) - 1) as f64;
+        let to_cell = |v: f64, min: f64, max: f64| {
+            if max > min { ((v - min) / (max - min) * max_cell) as u32 } else { 0 }
+        };
+
+        let keys: Vec<u64> = nodes
+            .iter()
+            .map(|node| {
+                hilbert_index(
+                    to_cell(node.lon(), min_lon, max_lon),
+                    to_cell(node.lat(), min_lat, max_lat),
+                    HILBERT_ORDER,
+                )
+            })
+            .collect();
+
+        // order[new] = old, ties keep the original order
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&i| (keys[i], i));
+        let mut new_idx = vec![0; n];
+        for (new, &old) in order.iter().enumerate() {
+            new_idx[old] = new;
+        }
+
+        let new_nodes = order.iter().map(|&old| nodes[old]).collect();
+        let new_adj = order
+            .iter()
+            .map(|&old| {
+                self.adj()[old]
+                    .iter()
+                    .map(|neighbor| Neighbor { node_index: new_idx[neighbor.node_index], ..*neighbor })
+                    .collect()
+            })
+            .collect();
+        let way_tags = std::mem::take(&mut self.way_tags);
+        let elevation = self.elevation.take().map(|heights| order.iter().map(|&old| heights[old]).collect());
+        let mut graph = Graph::new(new_nodes, new_adj).with_way_tags(way_tags);
+        graph.elevation = elevation;
+        graph
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EdgeData, Node};
+
+    #[test]
+    fn first_order_curve() {
+        // the curve visits the four quadrants as (0,0) (0,1) (1,1) (1,0)
+        assert_eq!(hilbert_index(0, 0, 1), 0);
+        assert_eq!(hilbert_index(0, 1, 1), 1);
+        assert_eq!(hilbert_index(1, 1, 1), 2);
+        assert_eq!(hilbert_index(1, 0, 1), 3);
+    }
+
+    #[test]
+    fn curve_is_a_bijection_and_continuous() {
+        let order = 3;
+        let side = 1u32 << order;
+        let mut cells = vec![(0, 0); (side * side) as usize];
+        for x in 0..side {
+            for y in 0..side {
+                cells[hilbert_index(x, y, order) as usize] = (x, y);
+            }
+        }
+        // consecutive positions are always adjacent cells
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1, "{:?} -> {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn reorder_keeps_the_graph_intact() {
+        // path 0 - 1 - 2 stored in the "wrong" order: west, east, middle
+        let nodes = vec![Node::new(10, 0.0, 0.0), Node::new(30, 0.0, 2.0), Node::new(20, 0.0, 1.0)];
+        let edge = |to: usize, osm_id: u64| Neighbor { osm_id, node_index: to, edge_data: EdgeData { way_id: 1, length_m: 1.0 } };
+        let adj = vec![vec![edge(2, 20)], vec![edge(2, 20)], vec![edge(0, 10), edge(1, 30)]];
+
+        let graph = Graph::new(nodes, adj).with_elevation(vec![1.0, 3.0, 2.0]).reorder_hilbert();
+        let ids: Vec<u64> = graph.nodes().iter().map(|n| n.id()).collect();
+        assert_eq!(ids, vec![10, 20, 30]);
+        // heights move with their nodes
+        assert_eq!(graph.elevation, Some(vec![1.0, 2.0, 3.0]));
+
+        for (u, neighbors) in graph.adj().iter().enumerate() {
+            for neighbor in neighbors {
+                // indices still point at the node the osm id names
+                assert_eq!(graph.nodes()[neighbor.node_index].id(), neighbor.osm_id);
+                // and the reverse edge is still there
+                assert!(graph.adj()[neighbor.node_index].iter().any(|back| back.node_index == u));
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use serde::Serialize;
+
+use crate::builder::haversine_distance;
+use crate::graph::Graph;
+use crate::model::{HistoryError, Novelty};
+use crate::query::QueryGraph;
+
+// GPX points closer than this to the last one used add nothing
+const TRACK_STEP_M: f64 = 20.0;
+// GPX points further than this from any road are off the map
+const MAX_TRACK_SNAP_M: f64 = 30.0;
+// gaps between matched roads are only filled in by a shortest path up to this long
+const MAX_TRACK_GAP_M: f64 = 300.0;
+// GPX points matched per upload, after dropping the ones closer than TRACK_STEP_M,
+// every one of them snaps against the whole map
+const MAX_TRACK_POINTS: usize = 10_000;
+const MAX_USER_LEN: usize = 64;
+
+/// Roads one user already ran, as pairs of OSM node ids with the lower id
+/// first, so a history survives rebuilding the graph from the map
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    roads: HashSet<(u64, u64)>,
+}
+
+impl History {
+    pub fn contains(&self, a: u64, b: u64) -> bool {
+        self.roads.contains(&(a.min(b), a.max(b)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roads.is_empty()
+    }
+
+    fn insert(&mut self, a: u64, b: u64) -> bool {
+        self.roads.insert((a.min(b), a.max(b)))
+    }
+}
+
+/// What an import did to a history
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HistoryStats {
+    pub roads: usize,
+    pub added: usize,
+}
+
+/// How much of the map a history covers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HistorySummary {
+    pub roads: usize,
+}
+
+/// Histories of every user, one text file per user in `dir` with a road
+/// per line. Files are only ever appended to, histories are kept in memory
+/// once read. Users without a file are not kept, so made up names cost
+/// nothing.
+pub struct HistoryStore {
+    dir: PathBuf,
+    loaded: Mutex<HashMap<String, Arc<History>>>,
+}
+
+impl HistoryStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, HistoryError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(HistoryStore { dir, loaded: Mutex::new(HashMap::new()) })
+    }
+
+    // user names end up in file names, so they are kept to a safe alphabet
+    fn file(&self, user: &str) -> Result<PathBuf, HistoryError> {
+        let valid = !user.is_empty() && user.len() <= MAX_USER_LEN && user.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(HistoryError::BadUser(user.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.roads", user)))
+    }
+
+    /// The history of `user`, empty for users without one
+    pub fn history(&self, user: &str) -> Result<Arc<History>, HistoryError> {
+        self.load(&mut self.loaded.lock().unwrap(), user)
+    }
+
+    fn load(&self, loaded: &mut HashMap<String, Arc<History>>, user: &str) -> Result<Arc<History>, HistoryError> {
+        let file = self.file(user)?;
+        if let Some(history) = loaded.get(user) {
+            return Ok(history.clone());
+        }
+        if !file.exists() {
+            return Ok(Arc::default());
+        }
+        let mut history = History::default();
+        for (i, line) in BufReader::new(File::open(&file)?).lines().enumerate() {
+            let line = line?;
+            let road = line.split_once(' ').and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)));
+            let Some((a, b)) = road else {
+                return Err(HistoryError::Corrupt { user: user.to_string(), line: i + 1 });
+            };
+            history.insert(a, b);
+        }
+        let history = Arc::new(history);
+        loaded.insert(user.to_string(), history.clone());
+        Ok(history)
+    }
+
+    /// Adds roads given as pairs of OSM node ids to the history of `user`
+    pub fn add(&self, user: &str, roads: impl IntoIterator<Item = (u64, u64)>) -> Result<HistoryStats, HistoryError> {
+        let file = self.file(user)?;
+        // held until the end, so two imports for one user don't lose roads
+        let mut loaded = self.loaded.lock().unwrap();
+        let mut history = (*self.load(&mut loaded, user)?).clone();
+        let added: Vec<(u64, u64)> = roads.into_iter().filter(|&(a, b)| a != b && history.insert(a, b)).collect();
+        if !added.is_empty() {
+            let mut w = BufWriter::new(OpenOptions::new().create(true).append(true).open(file)?);
+            for (a, b) in &added {
+                writeln!(w, "{} {}", a.min(b), a.max(b))?;
+            }
+            w.flush()?;
+        }
+        let stats = HistoryStats { roads: history.len(), added: added.len() };
+        // nothing new, nothing to keep for a user without a file
+        if !added.is_empty() {
+            loaded.insert(user.to_string(), Arc::new(history));
+        }
+        Ok(stats)
+    }
+
+    /// Adds routes given as OSM node ids, as the paths of earlier responses
+    /// have them. Nodes without an id (0, snapped points) break a route.
+    pub fn add_routes(&self, user: &str, routes: &[Vec<u64>]) -> Result<HistoryStats, HistoryError> {
+        let roads = routes.iter().flat_map(|route| route.windows(2)).filter(|w| w[0] != 0 && w[1] != 0).map(|w| (w[0], w[1]));
+        self.add(user, roads.collect::<Vec<_>>())
+    }
+
+    /// Adds the roads a GPX file's tracks ran along, at most
+    /// `MAX_TRACK_POINTS` points of them
+    pub fn add_gpx(&self, user: &str, graph: &Graph, gpx: &str) -> Result<HistoryStats, HistoryError> {
+        let segments: Vec<Vec<(f64, f64)>> = parse_gpx(gpx)?.iter().map(|segment| thin_track(segment)).collect();
+        if segments.iter().map(Vec::len).sum::<usize>() > MAX_TRACK_POINTS {
+            return Err(HistoryError::Gpx(format!("tracks may have at most {} points {} m apart", MAX_TRACK_POINTS, TRACK_STEP_M)));
+        }
+        let roads: Vec<(u64, u64)> = segments.iter().flat_map(|segment| graph.match_track(segment)).collect();
+        self.add(user, roads)
+    }
+}
+
+/// Points of every track segment in a GPX file
+pub fn parse_gpx(gpx: &str) -> Result<Vec<Vec<(f64, f64)>>, HistoryError> {
+    let mut xml = Reader::from_str(gpx);
+    xml.trim_text(true);
+    let mut segments = Vec::new();
+    let mut points = Vec::new();
+    loop {
+        match xml.read_event()? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.name().0 == b"trkpt" => {
+                let (mut lat, mut lon) = (None, None);
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    let value = std::str::from_utf8(&attr.value).ok().and_then(|v| v.parse::<f64>().ok());
+                    match attr.key.0 {
+                        b"lat" => lat = value,
+                        b"lon" => lon = value,
+                        _ => {}
+                    }
+                }
+                match (lat, lon) {
+                    (Some(lat), Some(lon)) => points.push((lat, lon)),
+                    _ => return Err(HistoryError::Gpx("track point without a valid lat and lon".to_string())),
+                }
+            }
+            Event::End(ref e) if e.name().0 == b"trkseg" => segments.push(std::mem::take(&mut points)),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !points.is_empty() {
+        segments.push(points);
+    }
+    Ok(segments)
+}
+
+// the points of a track that are at least TRACK_STEP_M from the last one kept
+fn thin_track(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut kept: Vec<(f64, f64)> = Vec::new();
+    for &(lat, lon) in points {
+        if kept.last().is_none_or(|&(a, b)| haversine_distance(a, b, lat, lon) >= TRACK_STEP_M) {
+            kept.push((lat, lon));
+        }
+    }
+    kept
+}
+
+impl Graph {
+    /// Roads a recorded track ran along, as OSM node id pairs. Every point
+    /// is snapped to its closest road, and consecutive roads that don't touch
+    /// are joined by the shortest path between them if that is short.
+    pub fn match_track(&self, points: &[(f64, f64)]) -> Vec<(u64, u64)> {
+        let query = QueryGraph::new(self);
+        let mut roads = Vec::new();
+        let mut last_road: Option<(usize, usize)> = None;
+        for (lat, lon) in thin_track(points) {
+            let Some(snap) = self.snap_to_edge(lat, lon).filter(|snap| snap.distance <= MAX_TRACK_SNAP_M) else {
+                continue;
+            };
+            let road = (snap.from, snap.to);
+            if let Some(prev) = last_road.filter(|&prev| prev != road) {
+                // the closest ends of the two roads
+                let ends = [(prev.0, road.0), (prev.0, road.1), (prev.1, road.0), (prev.1, road.1)];
+                let (from, to) = ends
+                    .into_iter()
+                    .min_by(|a, b| self.straight_distance(a.0, a.1).total_cmp(&self.straight_distance(b.0, b.1)))
+                    .unwrap();
+                // only searched as far as a gap may be long
+                let (dist, parent) = query.dijkstra(from, Some(MAX_TRACK_GAP_M));
+                if dist[to] <= MAX_TRACK_GAP_M {
+                    let mut v = to;
+                    while let Some(p) = parent[v] {
+                        roads.push((self.nodes[p].id(), self.nodes[v].id()));
+                        v = p;
+                    }
+                }
+            }
+            roads.push((self.nodes[road.0].id(), self.nodes[road.1].id()));
+            last_road = Some(road);
+        }
+        roads
+    }
+
+    fn straight_distance(&self, u: usize, v: usize) -> f64 {
+        haversine_distance(self.nodes[u].lat(), self.nodes[u].lon(), self.nodes[v].lat(), self.nodes[v].lon())
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Whether the road between two adjacent nodes is in `history`. Pieces of
+    /// a split edge count as the whole edge.
+    pub fn is_known(&self, history: &History, u: usize, v: usize) -> bool {
+        let (a, b) = self.split_edge_of(u).or(self.split_edge_of(v)).unwrap_or((u, v));
+        history.contains(self.node(a).id(), self.node(b).id())
+    }
+
+    // length of the edge if the novelty's history has it, 0 otherwise
+    pub(crate) fn known_length(&self, novelty: Option<&Novelty>, u: usize, v: usize, length: f64) -> f64 {
+        match novelty {
+            Some(novelty) if self.is_known(&novelty.history, u, v) => length,
+            _ => 0.0,
+        }
+    }
+
+    /// What a path costs with a novelty weight: how far it is off the target
+    /// plus the weight times the length of already run roads on it
+    pub(crate) fn novelty_cost(&self, novelty: &Novelty, path: &[usize], length: f64, target_distance: f64) -> f64 {
+        let known: f64 = path
+            .windows(2)
+            .map(|w| self.known_length(Some(novelty), w[0], w[1], self.edge_length(w[0], w[1]).unwrap_or(0.0)))
+            .sum();
+        (length - target_distance).abs() + novelty.weight * known
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{EdgeData, Neighbor, Node, SearchOptions};
+
+    /// Two ways from 0 to 4 of the same length, over 1 and over 2, and a
+    /// longer one over 3
+    fn build_graph() -> Graph {
+        let nodes = (0..5).map(|i| Node::new(i as u64 + 100, 0.0, 0.0)).collect();
+        let mut adj = vec![Vec::new(); 5];
+        for (via, length_m) in [(1, 100.0), (2, 100.0), (3, 130.0)] {
+            let edge_data = EdgeData { way_id: via as u64, length_m };
+            for (u, v) in [(0, via), (via, 4)] {
+                adj[u].push(Neighbor { osm_id: v as u64 + 100, node_index: v, edge_data });
+                adj[v].push(Neighbor { osm_id: u as u64 + 100, node_index: u, edge_data });
+            }
+        }
+        Graph::new(nodes, adj)
+    }
+
+    fn temp_store() -> (HistoryStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("history-{}", uuid::Uuid::new_v4()));
+        (HistoryStore::open(&dir).unwrap(), dir)
+    }
+
+    #[test]
+    fn store_keeps_histories_across_restarts() {
+        let (store, dir) = temp_store();
+        let stats = store.add_routes("runner_1", &[vec![100, 101, 104], vec![104, 101, 0, 103]]).unwrap();
+        assert_eq!(stats, HistoryStats { roads: 2, added: 2 });
+        assert_eq!(store.add("runner_1", [(104, 102)]).unwrap(), HistoryStats { roads: 3, added: 1 });
+
+        let reopened = HistoryStore::open(&dir).unwrap();
+        let history = reopened.history("runner_1").unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history.contains(101, 100) && history.contains(102, 104));
+        assert!(reopened.history("someone-else").unwrap().is_empty());
+        assert_eq!(reopened.add("someone-else", []).unwrap(), HistoryStats { roads: 0, added: 0 });
+        // only the users that have a file stay in memory
+        assert_eq!(reopened.loaded.lock().unwrap().keys().collect::<Vec<_>>(), vec!["runner_1"]);
+        assert!(matches!(reopened.history("../etc/passwd"), Err(HistoryError::BadUser(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gpx_tracks_are_matched_onto_roads() {
+        // a road along the equator, nodes 0.001° (about 111 m) apart, and a side road
+        let nodes: Vec<Node> = (0..5).map(|i| Node::new(i as u64 + 1, 0.0, i as f64 * 0.001)).chain([Node::new(6, 0.001, 0.0)]).collect();
+        let mut adj = vec![Vec::new(); 6];
+        for (u, v) in [(0, 1), (1, 2), (2, 3), (3, 4), (0, 5)] {
+            let length_m = haversine_distance(nodes[u].lat(), nodes[u].lon(), nodes[v].lat(), nodes[v].lon());
+            let edge_data = EdgeData { way_id: 1, length_m };
+            adj[u].push(Neighbor { osm_id: v as u64 + 1, node_index: v, edge_data });
+            adj[v].push(Neighbor { osm_id: u as u64 + 1, node_index: u, edge_data });
+        }
+        let graph = Graph::new(nodes, adj);
+
+        // a few meters next to the road, skipping the road from 2 to 3
+        let gpx = r#"<?xml version="1.0"?>
+            <gpx version="1.1"><trk><trkseg>
+              <trkpt lat="0.00005" lon="0.0005"></trkpt>
+              <trkpt lat="0.00005" lon="0.0015"/>
+              <trkpt lat="0.00005" lon="0.0035"/>
+            </trkseg></trk></gpx>"#;
+        let segments = parse_gpx(gpx).unwrap();
+        assert_eq!(segments.len(), 1);
+        let mut roads: Vec<(u64, u64)> = graph.match_track(&segments[0]).into_iter().map(|(a, b)| (a.min(b), a.max(b))).collect();
+        roads.sort();
+        roads.dedup();
+        assert_eq!(roads, vec![(1, 2), (2, 3), (3, 4), (4, 5)]);
+
+        assert!(matches!(parse_gpx(r#"<gpx><trk><trkseg><trkpt lat="x"/></trkseg></trk></gpx>"#), Err(HistoryError::Gpx(_))));
+    }
+
+    #[test]
+    fn long_tracks_are_refused() {
+        let (store, dir) = temp_store();
+        let points: String = (0..=MAX_TRACK_POINTS).map(|i| format!(r#"<trkpt lat="0" lon="{}"/>"#, i as f64 * 0.001)).collect();
+        let gpx = format!("<gpx><trk><trkseg>{}</trkseg></trk></gpx>", points);
+        assert!(matches!(store.add_gpx("runner_1", &build_graph(), &gpx), Err(HistoryError::Gpx(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn novelty_prefers_new_roads() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        let mut history = History::default();
+        history.insert(100, 101);
+        history.insert(101, 104);
+        let novelty = Novelty { history: Arc::new(history), weight: 1.0 };
+        let options = SearchOptions { novelty: Some(novelty.clone()), ..Default::default() };
+
+        let dijkstra = query.special_dijkstra(0, 4, 1, 200.0, 70.0, &options);
+        assert_eq!(dijkstra.paths[0].0, vec![0, 2, 4]);
+        let beam = query.beam_search(0, 4, 1, 200.0, 70.0, 10, &options);
+        assert_eq!(beam.paths[0].0, vec![0, 2, 4]);
+        assert_eq!(query.novelty_cost(&novelty, &[0, 1, 4], 200.0, 200.0), 200.0);
+
+        // with every short way run before the longer new one wins
+        let mut history = (*novelty.history).clone();
+        history.insert(100, 102);
+        let options = SearchOptions { novelty: Some(Novelty::explore(Arc::new(history))), ..Default::default() };
+        let dijkstra = query.special_dijkstra(0, 4, 1, 200.0, 70.0, &options);
+        assert_eq!(dijkstra.paths[0].0, vec![0, 3, 4]);
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::budget::Budget;
+use crate::graph::{Graph, check_snap_distance};
+use crate::model::{IsochroneQuery, RouteError, SnapInfo, StopReason};
+use crate::query::QueryGraph;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+/// Smallest polygon cell, finer ones are raised to it. The cells along a
+/// road grow with its length over the resolution, so a tiny one would build
+/// millions of cells for a single band.
+pub const MIN_ISOCHRONE_RESOLUTION_M: f64 = 5.0;
+
+/// Everything reachable from `start`, one band per requested distance
+#[derive(Debug, Clone, Serialize)]
+pub struct Isochrone {
+    pub start: SnapInfo,
+    pub bands: Vec<IsochroneBand>, // shortest distance first
+    pub stopped: Option<StopReason>, // set when the budget ran out, the longer bands are missing then
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IsochroneBand {
+    pub distance_m: f64,
+    pub node_ids: Vec<u64>, // OSM nodes within the distance
+    pub edges: Value, // GeoJSON MultiLineString, edges that run out of distance are cut where they do
+    pub polygon: Value, // GeoJSON MultiPolygon around the reachable road
+}
+
+// a vertex of the cell grid, cell (i, j) spans [i, i + 1] × [j, j + 1]
+type Corner = (i64, i64);
+// lat/lon of both ends of a piece of road
+type Segment = ((f64, f64), (f64, f64));
+
+impl Graph {
+    pub fn isochrone(&self, req: &IsochroneQuery) -> Result<Isochrone, RouteError> {
+        let mut query = QueryGraph::new(self);
+        let start = query.snap(&[(req.lat, req.lon)])[0];
+        check_snap_distance("start", &start, req.max_snap_distance)?;
+
+        let mut distances: Vec<f64> = req.bands.iter().copied().filter(|d| *d > 0.0).collect();
+        distances.sort_by(f64::total_cmp);
+        distances.dedup();
+        let resolution_m = req.resolution_m.max(MIN_ISOCHRONE_RESOLUTION_M);
+        let (bands, stopped) = query.isochrone_bands(start.node_index, &distances, resolution_m, &req.budget);
+        Ok(Isochrone { start, bands, stopped })
+    }
+}
+
+impl QueryGraph<'_> {
+    /// One bounded Dijkstra up to the largest distance, then per distance
+    /// the reached nodes, the reached (parts of) edges and a polygon made of
+    /// the grid cells of `resolution_m` around them. `budget` is checked
+    /// before the Dijkstra and before every band, every band is a state and
+    /// the cells count towards the memory. Bands built before it ran out are
+    /// returned with the reason.
+    pub fn isochrone_bands(&self, start: usize, distances: &[f64], resolution_m: f64, budget: &Budget) -> (Vec<IsochroneBand>, Option<StopReason>) {
+        let Some(&max) = distances.last() else {
+            return (Vec::new(), None);
+        };
+        let mut meter = budget.coarse_meter();
+        if meter.exhausted(0, 0) {
+            return (Vec::new(), meter.stopped());
+        }
+        let (dist, _) = self.dijkstra(start, Some(max));
+        let reached: Vec<usize> = (0..self.node_count()).filter(|&u| dist[u] <= max).collect();
+        let origin = self.node(start);
+        let projection = Projection::new(origin.lat(), origin.lon(), resolution_m);
+
+        let mut bands = Vec::new();
+        let mut cell_bytes = 0;
+        for &band in distances {
+            if meter.exhausted(bands.len(), cell_bytes) {
+                break;
+            }
+            let node_ids = reached
+                .iter()
+                .filter(|&&u| dist[u] <= band && !self.is_virtual(u))
+                .map(|&u| self.node(u).id())
+                .collect();
+            let segments = self.reached_segments(&reached, &dist, band);
+            let lines: Vec<Value> = segments
+                .iter()
+                .map(|&((lat1, lon1), (lat2, lon2))| json!([[lon1, lat1], [lon2, lat2]]))
+                .collect();
+            let cells = projection.cells_along(&segments);
+            cell_bytes += cells.len() * size_of::<Corner>();
+            bands.push(IsochroneBand {
+                distance_m: band,
+                node_ids,
+                edges: json!({ "type": "MultiLineString", "coordinates": lines }),
+                polygon: projection.multi_polygon(&cell_polygons(&cells)),
+            });
+        }
+        (bands, meter.stopped())
+    }
+
+    // lat/lon pieces of road within `band`: whole edges once, edges that are
+    // only partly reached from one or both ends as the reached pieces
+    fn reached_segments(&self, reached: &[usize], dist: &[f64], band: f64) -> Vec<Segment> {
+        let covered = |u: usize, length: f64| if dist[u] <= band { (band - dist[u]).min(length) } else { 0.0 };
+        let mut segments = Vec::new();
+        for &u in reached {
+            if dist[u] > band {
+                continue;
+            }
+            for edge in self.neighbors(u) {
+                let v = edge.node_index;
+                let length = edge.edge_data.length_m;
+                let (a, b) = (self.node(u), self.node(v));
+                let from_u = covered(u, length);
+                if from_u + covered(v, length) >= length {
+                    if u < v || dist[v] > band {
+                        segments.push(((a.lat(), a.lon()), (b.lat(), b.lon())));
+                    }
+                } else if from_u > 0.0 {
+                    let t = from_u / length;
+                    let end = (a.lat() + t * (b.lat() - a.lat()), a.lon() + t * (b.lon() - a.lon()));
+                    segments.push(((a.lat(), a.lon()), end));
+                }
+            }
+        }
+        segments
+    }
+}
+
+// equirectangular grid around the start, fine at city scale
+struct Projection {
+    lat0: f64,
+    lon0: f64,
+    cos_lat: f64,
+    cell_m: f64,
+}
+
+impl Projection {
+    fn new(lat0: f64, lon0: f64, cell_m: f64) -> Self {
+        Projection { lat0, lon0, cos_lat: lat0.to_radians().cos(), cell_m }
+    }
+
+    // meters east and north of the start
+    fn to_xy(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        ((lon - self.lon0) * METERS_PER_DEGREE * self.cos_lat, (lat - self.lat0) * METERS_PER_DEGREE)
+    }
+
+    fn to_lon_lat(&self, (i, j): Corner) -> [f64; 2] {
+        let (x, y) = (i as f64 * self.cell_m, j as f64 * self.cell_m);
+        [self.lon0 + x / (METERS_PER_DEGREE * self.cos_lat), self.lat0 + y / METERS_PER_DEGREE]
+    }
+
+    // every cell a segment passes through and the cells around those, so
+    // the road ends up buffered by about one cell on each side
+    fn cells_along(&self, segments: &[Segment]) -> HashSet<Corner> {
+        let mut touched = HashSet::new();
+        for &(a, b) in segments {
+            let ((x1, y1), (x2, y2)) = (self.to_xy(a), self.to_xy(b));
+            let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+            let steps = (2.0 * length / self.cell_m).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let t = step as f64 / steps as f64;
+                let (x, y) = (x1 + t * (x2 - x1), y1 + t * (y2 - y1));
+                touched.insert(((x / self.cell_m).floor() as i64, (y / self.cell_m).floor() as i64));
+            }
+        }
+        let mut cells = HashSet::new();
+        for (i, j) in touched {
+            for di in -1..=1 {
+                for dj in -1..=1 {
+                    cells.insert((i + di, j + dj));
+                }
+            }
+        }
+        cells
+    }
+
+    fn multi_polygon(&self, polygons: &[Vec<Vec<Corner>>]) -> Value {
+        let coordinates: Vec<Vec<Vec<[f64; 2]>>> = polygons
+            .iter()
+            .map(|rings| {
+                rings
+                    .iter()
+                    .map(|ring| {
+                        // GeoJSON rings repeat their first position at the end
+                        ring.iter().chain(ring.first()).map(|&corner| self.to_lon_lat(corner)).collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        json!({ "type": "MultiPolygon", "coordinates": coordinates })
+    }
+}
+
+/// Outline of a set of grid cells as polygons, each an outer ring
+/// (counterclockwise) followed by its holes (clockwise). Cells that only
+/// touch at a corner end up in separate rings.
+fn cell_polygons(cells: &HashSet<Corner>) -> Vec<Vec<Vec<Corner>>> {
+    // boundary edges with the cells on their left side
+    let mut outgoing: BTreeMap<Corner, Vec<Corner>> = BTreeMap::new();
+    for &(i, j) in cells {
+        let sides = [
+            ((i, j - 1), (i, j), (i + 1, j)),
+            ((i + 1, j), (i + 1, j), (i + 1, j + 1)),
+            ((i, j + 1), (i + 1, j + 1), (i, j + 1)),
+            ((i - 1, j), (i, j + 1), (i, j)),
+        ];
+        for (neighbor, from, to) in sides {
+            if !cells.contains(&neighbor) {
+                outgoing.entry(from).or_default().push(to);
+            }
+        }
+    }
+
+    let mut outers: Vec<Vec<Corner>> = Vec::new();
+    let mut holes: Vec<Vec<Corner>> = Vec::new();
+    while let Some(&first) = outgoing.keys().next() {
+        let mut ring = vec![first];
+        let mut at = take_edge(&mut outgoing, first, None);
+        let mut from = first;
+        while at != first {
+            ring.push(at);
+            let heading = (at.0 - from.0, at.1 - from.1);
+            from = at;
+            at = take_edge(&mut outgoing, at, Some(heading));
+        }
+        let ring = drop_straight_corners(ring);
+        if signed_area(&ring) > 0.0 {
+            outers.push(ring);
+        } else {
+            holes.push(ring);
+        }
+    }
+
+    let mut polygons: Vec<Vec<Vec<Corner>>> = outers.into_iter().map(|ring| vec![ring]).collect();
+    for hole in holes {
+        // the cell left of the hole's first edge is inside the outer ring the
+        // hole belongs to, and that is the smallest outer ring around it
+        let (a, b) = (hole[0], hole[1]);
+        let (dx, dy) = ((b.0 - a.0).signum(), (b.1 - a.1).signum());
+        let point = ((a.0 + b.0) as f64 / 2.0 - dy as f64 * 0.25, (a.1 + b.1) as f64 / 2.0 + dx as f64 * 0.25);
+        let owner = polygons
+            .iter_mut()
+            .filter(|rings| contains(&rings[0], point))
+            .min_by(|a, b| signed_area(&a[0]).total_cmp(&signed_area(&b[0])));
+        if let Some(rings) = owner {
+            rings.push(hole);
+        }
+    }
+    polygons
+}
+
+// removes the edge leaving `at`, taking the sharpest left turn coming from
+// `heading` so rings split where cells only touch at a corner
+fn take_edge(outgoing: &mut BTreeMap<Corner, Vec<Corner>>, at: Corner, heading: Option<Corner>) -> Corner {
+    let ends = outgoing.get_mut(&at).expect("boundary rings are closed");
+    let pick = match heading {
+        Some((dx, dy)) => {
+            let preference = [(-dy, dx), (dx, dy), (dy, -dx)];
+            preference
+                .iter()
+                .find_map(|&(px, py)| ends.iter().position(|&(x, y)| (x - at.0, y - at.1) == (px, py)))
+                .unwrap_or(0)
+        }
+        None => 0,
+    };
+    let end = ends.swap_remove(pick);
+    if ends.is_empty() {
+        outgoing.remove(&at);
+    }
+    end
+}
+
+fn drop_straight_corners(ring: Vec<Corner>) -> Vec<Corner> {
+    let n = ring.len();
+    (0..n)
+        .filter(|&k| {
+            let (prev, at, next) = (ring[(k + n - 1) % n], ring[k], ring[(k + 1) % n]);
+            (at.0 - prev.0) * (next.1 - at.1) != (at.1 - prev.1) * (next.0 - at.0)
+        })
+        .map(|k| ring[k])
+        .collect()
+}
+
+fn signed_area(ring: &[Corner]) -> f64 {
+    let n = ring.len();
+    let twice: i64 = (0..n).map(|k| ring[k].0 * ring[(k + 1) % n].1 - ring[(k + 1) % n].0 * ring[k].1).sum();
+    twice as f64 / 2.0
+}
+
+// even-odd ray casting
+fn contains(ring: &[Corner], (x, y): (f64, f64)) -> bool {
+    let n = ring.len();
+    let mut inside = false;
+    for k in 0..n {
+        let ((x1, y1), (x2, y2)) = (ring[k], ring[(k + 1) % n]);
+        let (x1, y1, x2, y2) = (x1 as f64, y1 as f64, x2 as f64, y2 as f64);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::haversine_distance;
+    use crate::model::{EdgeData, Node};
+    use crate::test_util::Grid;
+
+    #[test]
+    fn ring_of_cells_has_a_hole() {
+        let mut cells: HashSet<Corner> = (0..3).flat_map(|i| (0..3).map(move |j| (i, j))).collect();
+        cells.remove(&(1, 1));
+        let polygons = cell_polygons(&cells);
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].len(), 2);
+        assert_eq!(signed_area(&polygons[0][0]), 9.0);
+        assert_eq!(signed_area(&polygons[0][1]), -1.0);
+        assert_eq!(polygons[0][0].len(), 4);
+    }
+
+    #[test]
+    fn cells_touching_at_a_corner_are_separate() {
+        let cells = HashSet::from([(0, 0), (1, 1)]);
+        let mut polygons = cell_polygons(&cells);
+        polygons.sort();
+        assert_eq!(polygons.len(), 2);
+        assert!(polygons.iter().all(|rings| rings.len() == 1 && signed_area(&rings[0]) == 1.0));
+    }
+
+    /// 5 × 5 grid 0.001° (about 111 m) apart with haversine lengths, node
+    /// `row * 5 + col`, and a node off on its own
+    fn build_grid() -> Graph {
+        let (mut nodes, mut adj) = Grid::new(5, 5).parts(|_, _, length_m| Some(EdgeData { way_id: 1, length_m }));
+        nodes.push(Node::new(25, 0.0021, 0.0021));
+        adj.push(Vec::new());
+        Graph::new(nodes, adj)
+    }
+
+    fn query(bands: Vec<f64>) -> IsochroneQuery {
+        IsochroneQuery { lat: 0.002, lon: 0.002, bands, resolution_m: 20.0, max_snap_distance: None, budget: Budget::default() }
+    }
+
+    // even-odd test on the lon/lat rings of a GeoJSON MultiPolygon
+    fn polygon_contains(polygon: &Value, lat: f64, lon: f64) -> bool {
+        let inside = |ring: &Value| {
+            let ring = ring.as_array().unwrap();
+            let mut inside = false;
+            for pair in ring.windows(2) {
+                let (x1, y1) = (pair[0][0].as_f64().unwrap(), pair[0][1].as_f64().unwrap());
+                let (x2, y2) = (pair[1][0].as_f64().unwrap(), pair[1][1].as_f64().unwrap());
+                if (y1 > lat) != (y2 > lat) && lon < x1 + (lat - y1) / (y2 - y1) * (x2 - x1) {
+                    inside = !inside;
+                }
+            }
+            inside
+        };
+        polygon["coordinates"].as_array().unwrap().iter().any(|rings| {
+            let rings = rings.as_array().unwrap();
+            inside(&rings[0]) && !rings[1..].iter().any(inside)
+        })
+    }
+
+    #[test]
+    fn bands_grow_with_distance() {
+        let graph = build_grid();
+        let isochrone = graph.isochrone(&query(vec![250.0, 150.0])).unwrap();
+        assert_eq!(isochrone.bands.len(), 2);
+        let (near, far) = (&isochrone.bands[0], &isochrone.bands[1]);
+        assert_eq!(near.distance_m, 150.0);
+
+        // the center and its 4 neighbors, then everything at most 2 blocks away
+        let mut ids = near.node_ids.clone();
+        ids.sort();
+        assert_eq!(ids, vec![7, 11, 12, 13, 17]);
+        assert_eq!(far.node_ids.len(), 13);
+        assert!(!far.node_ids.contains(&25));
+
+        // 4 whole blocks and 3 more 39 m stubs at each of the neighbors
+        let block = haversine_distance(0.0, 0.0, 0.001, 0.0);
+        let length: f64 = near.edges["coordinates"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| haversine_distance(line[0][1].as_f64().unwrap(), line[0][0].as_f64().unwrap(), line[1][1].as_f64().unwrap(), line[1][0].as_f64().unwrap()))
+            .sum();
+        assert!((length - (4.0 * block + 12.0 * (150.0 - block))).abs() < 1.0);
+    }
+
+    #[test]
+    fn polygon_covers_the_reachable_road() {
+        let graph = build_grid();
+        let isochrone = graph.isochrone(&query(vec![150.0])).unwrap();
+        let polygon = &isochrone.bands[0].polygon;
+        assert!(polygon_contains(polygon, 0.002, 0.002));
+        assert!(polygon_contains(polygon, 0.003, 0.002));
+        assert!(polygon_contains(polygon, 0.003, 0.0023));
+        // unreached corners of the grid and the middle of a block
+        assert!(!polygon_contains(polygon, 0.0, 0.0));
+        assert!(!polygon_contains(polygon, 0.004, 0.004));
+        assert!(!polygon_contains(polygon, 0.0015, 0.0015));
+    }
+
+    #[test]
+    fn tiny_resolution_is_raised_to_the_minimum() {
+        let graph = build_grid();
+        let polygon = |resolution_m| {
+            let req = IsochroneQuery { resolution_m, ..query(vec![150.0]) };
+            graph.isochrone(&req).unwrap().bands[0].polygon.clone()
+        };
+        assert_eq!(polygon(1e-6), polygon(MIN_ISOCHRONE_RESOLUTION_M));
+    }
+
+    #[test]
+    fn no_bands_no_result() {
+        let graph = build_grid();
+        assert!(graph.isochrone(&query(vec![])).unwrap().bands.is_empty());
+        assert!(graph.isochrone(&query(vec![-5.0, 0.0])).unwrap().bands.is_empty());
+    }
+}
@@ -2,7 +2,27 @@ pub mod model;
 pub mod parser;
 pub mod graph;
 pub mod builder;
+pub mod query;
+pub mod hilbert;
+pub mod stats;
+pub mod export;
+pub mod loops;
+pub mod shortest_path;
+pub mod alt;
+pub mod ch;
+pub mod diversity;
+pub mod anneal;
+pub mod via;
+pub mod isochrone;
+pub mod avoid;
+pub mod budget;
+pub mod tree_cache;
+pub mod elevation;
+pub mod pareto;
+pub mod history;
 pub mod benchmarking;
+#[cfg(test)]
+mod test_util;
 
 use std::{fs::File, io::BufReader};
 use crate::model::{OsmError, Way};
@@ -13,11 +33,11 @@ use crate::builder::GraphBuilder;
 
 pub fn parse_osm_ways(file_path: &str, tags: &[&str]) -> Result<Vec<Way>,OsmError>  {
 
-    let file1 = File::open(file_path).expect(String::as_str(&format!("failed to open {}", file_path)));
+    let file1 = File::open(file_path).unwrap_or_else(|_| panic!("failed to open {}", file_path));
     let reader1 = BufReader::new(file1);
     let all_nodes = parse_nodes(reader1)?;
     
-    let file2 = File::open(file_path).expect(String::as_str(&format!("failed to open {}", file_path)));
+    let file2 = File::open(file_path).unwrap_or_else(|_| panic!("failed to open {}", file_path));
     let reader2 = BufReader::new(file2);
     let all_ways = parse_ways_with_tags(tags ,reader2)?;
 
@@ -32,7 +52,9 @@ pub fn create_graph(file_path: &str,tags: &[&str]) -> Result<Graph, OsmError> {
         graph_builder.add_way(way);
     }
 
-    let graph = graph_builder.build();
+    // renumber along a Hilbert curve so searches touch memory in spatial order,
+    // landmarks and the like are indexed by node and go on top of this
+    let graph = graph_builder.build().reorder_hilbert();
     Ok(graph)
 }
 
@@ -0,0 +1,462 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::f64::consts::PI;
+
+use crate::budget::{Budget, Meter};
+use crate::graph::{BasicState, Graph, check_snap_distance};
+use crate::model::{LoopQuery, LoopShape, PathsResponse, RouteError, SearchOutcome};
+use crate::query::QueryGraph;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+// roads are never straight, first guess of route length / ellipse perimeter
+const DETOUR_FACTOR: f64 = 1.3;
+// an edge the loop already used costs this many times its length again
+const REUSE_PENALTY: f64 = 4.0;
+// waypoints on the ellipse besides the start
+const WAYPOINTS: usize = 4;
+// how often the ellipse is rescaled to hit the target length
+const SCALE_ITERATIONS: usize = 6;
+const MIN_CANDIDATES: usize = 8;
+
+impl LoopShape {
+    // minor / major axis of the ellipse the waypoints are put on
+    fn aspect_ratio(self) -> f64 {
+        match self {
+            // petals are round, out-and-back routes don't use an ellipse
+            LoopShape::Round | LoopShape::Petals | LoopShape::OutAndBack => 1.0,
+            LoopShape::Elongated => 0.35,
+        }
+    }
+}
+
+// ratio between the perimeter of an ellipse and its semi-major axis (Ramanujan)
+fn perimeter_factor(ratio: f64) -> f64 {
+    PI * (3.0 * (1.0 + ratio) - ((3.0 + ratio) * (1.0 + 3.0 * ratio)).sqrt())
+}
+
+struct LoopCandidate {
+    path: Vec<usize>,
+    length: f64,
+    score: f64,
+}
+
+impl Graph {
+    pub fn get_loops(&self, req: &LoopQuery) -> Result<PathsResponse, RouteError> {
+        let mut query = QueryGraph::new(self);
+        let start = query.snap(&[(req.lat, req.lon)])[0];
+        check_snap_distance("start", &start, req.max_snap_distance)?;
+
+        let outcome = match req.shape {
+            LoopShape::OutAndBack => query.out_and_back(start.node_index, req.amount, req.target_distance, req.tol, &req.budget),
+            LoopShape::Petals => query.petal_loops(start.node_index, req.amount, req.target_distance, req.tol, req.petals, &req.budget),
+            LoopShape::Round | LoopShape::Elongated => query.round_trips(start.node_index, req.amount, req.target_distance, req.tol, req.shape, &req.budget),
+        };
+        let paths = query.convert_to_paths(&outcome.paths);
+        Ok(PathsResponse { start, goal: start, via: Vec::new(), stopped: outcome.stopped, seed: None, paths })
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Generates up to `k` closed routes from `start` back to `start` whose
+    /// length is within `tol` of `target_distance`.
+    ///
+    /// Every candidate puts waypoints on an ellipse through the start, pointing
+    /// in a different direction, and connects them with shortest paths that
+    /// avoid edges the loop already used. The ellipse is rescaled until the
+    /// route has the right length. Candidates are ranked by length error,
+    /// reused edge length and U-turns, best first. Every stitched route
+    /// counts as a state of `budget`, when it runs out the candidates so far
+    /// are ranked.
+    pub fn round_trips(&self, start: usize, k: usize, target_distance: f64, tol: f64, shape: LoopShape, budget: &Budget) -> SearchOutcome {
+        // waypoints further away than half the loop can never be reached and left again
+        let (dist, _) = self.dijkstra(start, Some(target_distance / 2.0));
+        let reachable: Vec<usize> = (0..self.node_count())
+            .filter(|&i| i != start && dist[i] <= target_distance / 2.0)
+            .collect();
+        if reachable.is_empty() {
+            return SearchOutcome::default();
+        }
+
+        let mut meter = budget.coarse_meter();
+        let mut stitches = 0;
+        let candidates = (2 * k).max(MIN_CANDIDATES);
+        let mut found: Vec<LoopCandidate> = Vec::new();
+        for c in 0..candidates {
+            if meter.stopped().is_some() {
+                break;
+            }
+            let bearing = 2.0 * PI * c as f64 / candidates as f64;
+            if let Some(candidate) = self.loop_towards(start, bearing, target_distance, tol, shape, &reachable, &mut meter, &mut stitches)
+                && (candidate.length - target_distance).abs() <= tol
+                && !found.iter().any(|f| f.path == candidate.path)
+            {
+                found.push(candidate);
+            }
+        }
+
+        found.sort_by(|a, b| a.score.total_cmp(&b.score));
+        SearchOutcome { paths: found.into_iter().take(k).map(|c| (c.path, c.length)).collect(), stopped: meter.stopped() }
+    }
+
+    /// Up to `k` routes out to a turnaround point and back the same way. The
+    /// turnaround is about half of `target_distance` along the road from the
+    /// start, at most one per direction so the routes head different ways.
+    /// It is one bounded Dijkstra, `budget` is checked before it and for
+    /// every route built.
+    pub fn out_and_back(&self, start: usize, k: usize, target_distance: f64, tol: f64, budget: &Budget) -> SearchOutcome {
+        let mut meter = budget.coarse_meter();
+        if meter.exhausted(0, 0) {
+            return SearchOutcome { paths: Vec::new(), stopped: meter.stopped() };
+        }
+        let (dist, parent) = self.dijkstra(start, Some((target_distance + tol) / 2.0));
+        let error = |v: usize| (2.0 * dist[v] - target_distance).abs();
+        let origin = self.node(start);
+        let meters_per_lon = METERS_PER_DEGREE * origin.lat().to_radians().cos();
+
+        // best turnaround per slice of the compass
+        let sectors = (2 * k).max(MIN_CANDIDATES);
+        let mut best: Vec<Option<usize>> = vec![None; sectors];
+        for v in (0..self.node_count()).filter(|&v| v != start && error(v) <= tol) {
+            let node = self.node(v);
+            // clockwise from north
+            let bearing = ((node.lon() - origin.lon()) * meters_per_lon).atan2((node.lat() - origin.lat()) * METERS_PER_DEGREE);
+            let sector = ((bearing.rem_euclid(2.0 * PI) / (2.0 * PI) * sectors as f64) as usize).min(sectors - 1);
+            if best[sector].is_none_or(|b| error(v) < error(b)) {
+                best[sector] = Some(v);
+            }
+        }
+
+        let mut turns: Vec<usize> = best.into_iter().flatten().collect();
+        turns.sort_by(|&a, &b| error(a).total_cmp(&error(b)).then(a.cmp(&b)));
+        let mut routes: Vec<(Vec<usize>, f64)> = Vec::new();
+        for turn in turns.into_iter().take(k) {
+            let bytes = routes.iter().map(|(path, _)| path.len() * size_of::<usize>()).sum();
+            if meter.exhausted(routes.len(), bytes) {
+                break;
+            }
+            // the tree points back to the start
+            let mut back = vec![turn];
+            while let Some(p) = parent[*back.last().unwrap()] {
+                back.push(p);
+            }
+            let mut path: Vec<usize> = back.iter().rev().copied().collect();
+            path.extend_from_slice(&back[1..]);
+            routes.push((path, 2.0 * dist[turn]));
+        }
+        SearchOutcome { paths: routes, stopped: meter.stopped() }
+    }
+
+    /// Up to `k` routes of `petals` round loops, each about
+    /// `target_distance / petals` long. The route passes the start between
+    /// them, petals of one route share as little road as they can. `budget`
+    /// limits the search for petals, see `round_trips`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn petal_loops(&self, start: usize, k: usize, target_distance: f64, tol: f64, petals: usize, budget: &Budget) -> SearchOutcome {
+        let petals = petals.max(1);
+        let share = petals as f64;
+        let SearchOutcome { paths: found, stopped } = self.round_trips(start, (k * petals).max(MIN_CANDIDATES), target_distance / share, tol / share, LoopShape::Round, budget);
+        let roads: Vec<HashSet<(usize, usize)>> = found
+            .iter()
+            .map(|(path, _)| path.windows(2).map(|pair| (pair[0].min(pair[1]), pair[0].max(pair[1]))).collect())
+            .collect();
+
+        let mut routes = Vec::new();
+        let mut seen: HashSet<Vec<usize>> = HashSet::new();
+        if found.len() < petals {
+            return SearchOutcome { paths: routes, stopped };
+        }
+        // every petal gets to lead once, followed by the ones overlapping least
+        // with what is chosen so far, better ranked first on a tie
+        for first in 0..found.len() {
+            if routes.len() >= k {
+                break;
+            }
+            let mut chosen = vec![first];
+            while chosen.len() < petals {
+                let shared = |next: usize| chosen.iter().map(|&c| roads[c].intersection(&roads[next]).count()).sum::<usize>();
+                let next = (0..found.len()).filter(|i| !chosen.contains(i)).min_by_key(|&i| (shared(i), i)).unwrap();
+                chosen.push(next);
+            }
+            let mut key = chosen.clone();
+            key.sort();
+            if !seen.insert(key) {
+                continue;
+            }
+            let mut path = vec![start];
+            let mut length = 0.0;
+            for &c in &chosen {
+                path.extend_from_slice(&found[c].0[1..]);
+                length += found[c].1;
+            }
+            routes.push((path, length));
+        }
+        SearchOutcome { paths: routes, stopped }
+    }
+
+    // best loop with its ellipse pointing towards `bearing` (radians, clockwise from north)
+    #[allow(clippy::too_many_arguments)]
+    fn loop_towards(&self, start: usize, bearing: f64, target_distance: f64, tol: f64, shape: LoopShape, reachable: &[usize], meter: &mut Meter, stitches: &mut usize) -> Option<LoopCandidate> {
+        let mut perimeter = target_distance / DETOUR_FACTOR;
+        let mut best: Option<LoopCandidate> = None;
+
+        for _ in 0..SCALE_ITERATIONS {
+            *stitches += 1;
+            if meter.exhausted(*stitches, best.as_ref().map_or(0, |b| b.path.len() * size_of::<usize>())) {
+                break;
+            }
+            let waypoints = self.ellipse_waypoints(start, bearing, perimeter, shape, reachable);
+            let Some((path, length)) = self.stitch(start, &waypoints) else {
+                break;
+            };
+            let score = self.loop_score(&path, length, target_distance);
+            let done = (length - target_distance).abs() <= tol;
+            if best.as_ref().is_none_or(|b| score < b.score) {
+                best = Some(LoopCandidate { path, length, score });
+            }
+            if done || length == 0.0 {
+                break;
+            }
+            perimeter *= target_distance / length;
+        }
+        best
+    }
+
+    // nodes closest to evenly spaced points on an ellipse that passes through the start
+    fn ellipse_waypoints(&self, start: usize, bearing: f64, perimeter: f64, shape: LoopShape, reachable: &[usize]) -> Vec<usize> {
+        let ratio = shape.aspect_ratio();
+        let a = perimeter / perimeter_factor(ratio);
+        let b = a * ratio;
+        let origin = self.node(start);
+        let meters_per_lon = METERS_PER_DEGREE * origin.lat().to_radians().cos();
+
+        // major axis along the bearing, in (east, north) meters
+        let (ux, uy) = (bearing.sin(), bearing.cos());
+        let (vx, vy) = (uy, -ux);
+        // the start sits at angle PI, so the center is `a` ahead of it
+        let (cx, cy) = (a * ux, a * uy);
+
+        let mut waypoints = Vec::new();
+        for i in 1..=WAYPOINTS {
+            let theta = PI + 2.0 * PI * i as f64 / (WAYPOINTS + 1) as f64;
+            let x = cx + a * theta.cos() * ux + b * theta.sin() * vx;
+            let y = cy + a * theta.cos() * uy + b * theta.sin() * vy;
+            let lat = origin.lat() + y / METERS_PER_DEGREE;
+            let lon = origin.lon() + x / meters_per_lon;
+            // squared distance in meters, plenty for picking the closest node
+            let dist2 = |i: usize| {
+                let node = self.node(i);
+                ((node.lat() - lat) * METERS_PER_DEGREE).powi(2) + ((node.lon() - lon) * meters_per_lon).powi(2)
+            };
+            let nearest = reachable
+                .iter()
+                .copied()
+                .min_by(|&p, &q| dist2(p).total_cmp(&dist2(q)))
+                .expect("reachable is never empty");
+            if waypoints.last() != Some(&nearest) {
+                waypoints.push(nearest);
+            }
+        }
+        waypoints
+    }
+
+    // connects start -> waypoints -> start, returns the node sequence and its length
+    fn stitch(&self, start: usize, waypoints: &[usize]) -> Option<(Vec<usize>, f64)> {
+        let mut used: HashSet<(usize, usize)> = HashSet::new();
+        let mut path = vec![start];
+        let mut length = 0.0;
+        let mut from = start;
+        for &to in waypoints.iter().chain(std::iter::once(&start)) {
+            if to == from {
+                continue;
+            }
+            let (leg, leg_length) = self.penalized_path(from, to, &used)?;
+            for pair in leg.windows(2) {
+                used.insert((pair[0].min(pair[1]), pair[0].max(pair[1])));
+            }
+            path.extend_from_slice(&leg[1..]);
+            length += leg_length;
+            from = to;
+        }
+        Some((path, length))
+    }
+
+    // Dijkstra from `from` to `to` where edges in `used` cost more,
+    // returns the path and its real length
+    fn penalized_path(&self, from: usize, to: usize, used: &HashSet<(usize, usize)>) -> Option<(Vec<usize>, f64)> {
+        let mut cost: HashMap<usize, f64> = HashMap::new();
+        let mut parent: HashMap<usize, (usize, f64)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        cost.insert(from, 0.0);
+        heap.push(BasicState { node: from, dist: 0.0 });
+
+        while let Some(BasicState { node: u, dist: du }) = heap.pop() {
+            if u == to {
+                break;
+            }
+            if du > cost[&u] {
+                continue;
+            }
+            for edge in self.neighbors(u) {
+                let v = edge.node_index;
+                let length = edge.edge_data.length_m;
+                let penalty = if used.contains(&(u.min(v), u.max(v))) { 1.0 + REUSE_PENALTY } else { 1.0 };
+                let dv = du + length * penalty;
+                if cost.get(&v).is_none_or(|&c| dv < c) {
+                    cost.insert(v, dv);
+                    parent.insert(v, (u, length));
+                    heap.push(BasicState { node: v, dist: dv });
+                }
+            }
+        }
+
+        if !cost.contains_key(&to) {
+            return None;
+        }
+        let mut path = vec![to];
+        let mut length = 0.0;
+        let mut cur = to;
+        while let Some(&(prev, edge_length)) = parent.get(&cur) {
+            path.push(prev);
+            length += edge_length;
+            cur = prev;
+        }
+        path.reverse();
+        Some((path, length))
+    }
+
+    // lower is better: relative length error + share of reused length + U-turns
+    fn loop_score(&self, path: &[usize], length: f64, target_distance: f64) -> f64 {
+        let mut seen: HashSet<(usize, usize)> = HashSet::new();
+        let mut reused = 0.0;
+        for pair in path.windows(2) {
+            if !seen.insert((pair[0].min(pair[1]), pair[0].max(pair[1]))) {
+                reused += self.edge_length(pair[0], pair[1]).unwrap_or(0.0);
+            }
+        }
+        let u_turns = path.windows(3).filter(|w| w[0] == w[2]).count();
+        (length - target_distance).abs() / target_distance + reused / length.max(1.0) + 0.1 * u_turns as f64
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Node, StopReason};
+    use crate::test_util::build_grid;
+
+    fn reused_edges(path: &[usize]) -> usize {
+        let mut seen = HashSet::new();
+        path.windows(2).filter(|p| !seen.insert((p[0].min(p[1]), p[0].max(p[1])))).count()
+    }
+
+    #[test]
+    fn loops_are_closed_and_hit_the_target() {
+        let graph = build_grid(21);
+        let center = 10 * 21 + 10;
+        let loops = QueryGraph::new(&graph).round_trips(center, 3, 2000.0, 150.0, LoopShape::Round, &Budget::default()).paths;
+        assert!(!loops.is_empty());
+        for (path, length) in &loops {
+            assert_eq!(path.first(), Some(&center));
+            assert_eq!(path.last(), Some(&center));
+            assert!((length - 2000.0).abs() <= 150.0, "length {}", length);
+            assert_eq!(reused_edges(path), 0, "loop reuses edges: {:?}", path);
+        }
+    }
+
+    #[test]
+    fn elongated_loops_reach_further() {
+        let graph = build_grid(31);
+        let center = 15 * 31 + 15;
+        let query = QueryGraph::new(&graph);
+        // furthest straight-line reach of the best loop
+        let reach = |shape| {
+            let loops = query.round_trips(center, 1, 2500.0, 200.0, shape, &Budget::default()).paths;
+            let (path, _) = &loops[0];
+            path.iter()
+                .map(|&i| {
+                    let (a, b) = (graph.nodes()[center], graph.nodes()[i]);
+                    crate::builder::haversine_distance(a.lat(), a.lon(), b.lat(), b.lon())
+                })
+                .fold(0.0, f64::max)
+        };
+        assert!(reach(LoopShape::Elongated) > reach(LoopShape::Round));
+    }
+
+    #[test]
+    fn out_and_back_turns_around_halfway() {
+        let graph = build_grid(21);
+        let center = 10 * 21 + 10;
+        let routes = QueryGraph::new(&graph).out_and_back(center, 4, 2000.0, 150.0, &Budget::default()).paths;
+        assert_eq!(routes.len(), 4);
+        let mut turns = HashSet::new();
+        for (path, length) in &routes {
+            assert!((length - 2000.0).abs() <= 150.0, "length {}", length);
+            // the way back is the way out
+            let reversed: Vec<usize> = path.iter().rev().copied().collect();
+            assert_eq!(path, &reversed);
+            assert!(turns.insert(path[path.len() / 2]));
+        }
+    }
+
+    #[test]
+    fn petals_pass_the_start_between_laps() {
+        let graph = build_grid(31);
+        let center = 15 * 31 + 15;
+        let routes = QueryGraph::new(&graph).petal_loops(center, 2, 3000.0, 200.0, 2, &Budget::default()).paths;
+        assert_eq!(routes.len(), 2);
+        for (path, length) in &routes {
+            assert!((length - 3000.0).abs() <= 200.0, "length {}", length);
+            // start, between the laps and the end
+            let at_start: Vec<usize> = (0..path.len()).filter(|&i| path[i] == center).collect();
+            assert_eq!(at_start.len(), 3, "{:?}", path);
+            // two different loops, not one run twice
+            assert_ne!(path[..=at_start[1]], path[at_start[1]..]);
+            assert!(reused_edges(path) <= 2, "petals share too much road: {:?}", path);
+        }
+    }
+
+    #[test]
+    fn get_loops_starts_on_the_road() {
+        let graph = build_grid(21);
+        let req = LoopQuery {
+            lat: 0.01,
+            lon: 0.0105,
+            amount: 2,
+            target_distance: 1500.0,
+            tol: 150.0,
+            shape: LoopShape::Round,
+            petals: 2,
+            max_snap_distance: Some(50.0),
+            budget: Budget::default(),
+        };
+        let res = graph.get_loops(&req).unwrap();
+        assert!(!res.paths.is_empty());
+        assert_eq!(res.start.node_id, None);
+        for path in &res.paths {
+            let (first, last) = (path.nodes.first().unwrap(), path.nodes.last().unwrap());
+            assert!((first.lon() - 0.0105).abs() < 1e-9);
+            assert_eq!((first.lat(), first.lon()), (last.lat(), last.lon()));
+        }
+    }
+
+    #[test]
+    fn loops_stop_when_the_budget_runs_out() {
+        let graph = build_grid(21);
+        let center = 10 * 21 + 10;
+        let query = QueryGraph::new(&graph);
+        let budget = Budget { max_states: Some(2), ..Default::default() };
+        let outcome = query.round_trips(center, 3, 2000.0, 150.0, LoopShape::Round, &budget);
+        assert_eq!(outcome.stopped, Some(StopReason::StateLimit));
+        assert!(outcome.paths.len() <= 2);
+        let outcome = query.out_and_back(center, 4, 2000.0, 150.0, &budget);
+        assert_eq!(outcome.stopped, Some(StopReason::StateLimit));
+        assert_eq!(outcome.paths.len(), 3);
+    }
+
+    #[test]
+    fn isolated_start_gives_no_loops() {
+        let graph = Graph::new(vec![Node::new(1, 0.0, 0.0)], vec![Vec::new()]);
+        assert!(QueryGraph::new(&graph).round_trips(0, 1, 1000.0, 100.0, LoopShape::Round, &Budget::default()).paths.is_empty());
+    }
+}
@@ -5,13 +5,35 @@ use rocket::http::Status;
 use rocket_cors::{CorsOptions, AllowedOrigins};
 use rocket::serde::json::Json;
 use rocket::State;
+use rocket::data::{Data, ToByteUnit};
 
 use serde::Deserialize;
+use std::time::{Duration, Instant};
 
 use route_parser::graph::Graph;
-use route_parser::model::{Way,Path};
+use route_parser::ch::ContractionHierarchy;
+use route_parser::stats::{BoundingBox, GraphStats};
+use route_parser::model::{Way, PathQuery, PathsResponse, RouteError, HistoryError, Novelty, AscentRange, LoopQuery, LoopShape, Objective, ViaOrder, IsochroneQuery, Avoid, RepeatPolicy};
+use route_parser::isochrone::{Isochrone, MIN_ISOCHRONE_RESOLUTION_M};
+use route_parser::anneal::AnnealingOptions;
+use route_parser::budget::Budget;
+use route_parser::tree_cache::{CacheStats, TreeCache};
+use route_parser::history::{HistoryStats, HistoryStore, HistorySummary};
 use route_parser::{parse_osm_ways, create_graph};
 
+const MAP_FILE: &str = "data/map";
+// contraction hierarchy for MAP_FILE, written by `server ch`
+const CH_FILE: &str = "data/map.ch";
+// height of every node of MAP_FILE, lines of `<OSM node id> <meters>`,
+// without it ascent requests are refused
+const ELEVATION_FILE: &str = "data/map.elevation";
+// ALT landmarks of the server's graph, for A* where the hierarchy can't be used
+const LANDMARK_COUNT: usize = 8;
+// roads every user ran before, one file per user
+const HISTORY_DIR: &str = "data/history";
+// largest GPX upload
+const GPX_LIMIT_MIB: u64 = 32;
+
 pub const ACCEPTED_ROAD_TYPES: &[&str] = &[
     "residential",
     "unclassified",
@@ -29,6 +51,27 @@ pub const ACCEPTED_ROAD_TYPES: &[&str] = &[
     "highway",
 ];
 
+// defaul tol = 200 meters
+// don't want the user to decide the tolerance
+const PATH_TOLERANCE: f64 = 200.0;
+// clicks further than this from any road are rejected, unless the request says otherwise
+const DEFAULT_MAX_SNAP_DISTANCE: f64 = 500.0;
+// grid cell size of isochrone polygons
+const DEFAULT_ISOCHRONE_RESOLUTION: f64 = 50.0;
+// how far off `target_ascent` a route may be, unless the request says otherwise
+const DEFAULT_ASCENT_TOLERANCE: f64 = 20.0;
+// loops of a route with the petals shape
+const DEFAULT_PETALS: usize = 2;
+// partial paths the beam search keeps per step, unless the request says otherwise
+const DEFAULT_BEAM_WIDTH: usize = 500;
+// searches give up after this long and return what they have, unless the request says otherwise
+const DEFAULT_SEARCH_TIMEOUT_MS: u64 = 30_000;
+// rough cap on the memory of one search
+const SEARCH_MEMORY_LIMIT: usize = 1 << 30;
+// reverse Dijkstra trees kept for goals many requests share
+const TREE_CACHE_ENTRIES: usize = 64;
+const TREE_CACHE_BYTES: usize = 512 << 20;
+
 
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -47,12 +90,230 @@ struct PathsRequest {
 
     amount: u16,
     target_distance: f64,
+    max_snap_distance: Option<f64>,
+    max_overlap: Option<f64>,
+    #[serde(default)]
+    via: Vec<Coordinate>,
+    #[serde(default)]
+    via_order: ViaOrder,
+    // roads closed for this request only
+    #[serde(default)]
+    avoid_polygons: Vec<Vec<Coordinate>>,
+    #[serde(default)]
+    avoid_way_ids: Vec<u64>,
+    #[serde(default)]
+    avoid_node_ids: Vec<u64>,
+    #[serde(default)]
+    repeats: RepeatPolicy,
+    timeout_ms: Option<u64>,
+    max_states: Option<usize>,
+    // repeats an earlier randomized search, the response has the seed it used
+    seed: Option<u64>,
+    // summed climbs in meters, only on maps with elevation data
+    target_ascent: Option<f64>,
+    ascent_tolerance: Option<f64>,
+    // roads this user ran before count against a route, see /history
+    user: Option<String>,
+    novelty_weight: Option<f64>,
+    // as many new roads as possible, overrides the novelty weight
+    #[serde(default)]
+    explore: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Coordinate {
+    lat: f64,
+    lon: f64,
+}
+
+impl PathsRequest {
+    fn to_query(&self, history: &HistoryStore) -> Result<PathQuery, Custom<String>> {
+        let novelty = match (&self.user, self.explore, self.novelty_weight) {
+            (_, false, None) => None,
+            (None, _, _) => return Err(Custom(Status::UnprocessableEntity, "Novelty needs a user".to_string())),
+            (Some(_), false, Some(weight)) if weight < 0.0 => {
+                return Err(Custom(Status::UnprocessableEntity, "Novelty weight can't be negative".to_string()));
+            }
+            (Some(user), explore, weight) => {
+                let history = history.history(user).map_err(history_error)?;
+                Some(if explore { Novelty::explore(history) } else { Novelty { history, weight: weight.unwrap_or_default() } })
+            }
+        };
+        Ok(PathQuery {
+            start_lat: self.start_lat,
+            start_lon: self.start_lon,
+            goal_lat: self.goal_lat,
+            goal_lon: self.goal_lon,
+            amount: self.amount as usize,
+            target_distance: self.target_distance,
+            tol: PATH_TOLERANCE,
+            max_snap_distance: Some(self.max_snap_distance.unwrap_or(DEFAULT_MAX_SNAP_DISTANCE)),
+            max_overlap: self.max_overlap,
+            via: self.via.iter().map(|point| (point.lat, point.lon)).collect(),
+            via_order: self.via_order,
+            avoid: Avoid {
+                polygons: self
+                    .avoid_polygons
+                    .iter()
+                    .map(|ring| ring.iter().map(|point| (point.lat, point.lon)).collect())
+                    .collect(),
+                way_ids: self.avoid_way_ids.clone(),
+                node_ids: self.avoid_node_ids.clone(),
+            },
+            repeats: self.repeats,
+            budget: search_budget(self.timeout_ms, self.max_states),
+            seed: self.seed,
+            ascent: self.target_ascent.map(|target| AscentRange::around(target, self.ascent_tolerance.unwrap_or(DEFAULT_ASCENT_TOLERANCE))),
+            novelty,
+        })
+    }
+}
+
+// the usual path request plus how long to search and what makes a route good
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AnnealingRequest {
+    #[serde(flatten)]
+    path: PathsRequest,
+    time_budget_ms: Option<u64>,
+    #[serde(default)]
+    objective: Objective,
+}
+
+impl AnnealingRequest {
+    fn to_options(&self) -> AnnealingOptions {
+        let defaults = AnnealingOptions::default();
+        AnnealingOptions {
+            time_budget: self.time_budget_ms.map_or(defaults.time_budget, Duration::from_millis),
+            objective: self.objective.clone(),
+            seed: self.path.seed,
+            ..defaults
+        }
+    }
+}
+
+// the usual path request plus how many partial paths to keep per step
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BeamRequest {
+    #[serde(flatten)]
+    path: PathsRequest,
+    beam_width: Option<usize>,
+}
+
+// the deadline starts counting when the request is turned into a query. A
+// client that goes away is not noticed, the deadline is what ends its search,
+// so there is no cancel token here.
+fn search_budget(timeout_ms: Option<u64>, max_states: Option<usize>) -> Budget {
+    Budget {
+        max_states,
+        deadline: Some(Instant::now() + Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_SEARCH_TIMEOUT_MS))),
+        max_memory_bytes: Some(SEARCH_MEMORY_LIMIT),
+        cancel: None,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ShortestPathRequest {
+    start_lat: f64,
+    start_lon: f64,
+    goal_lat: f64,
+    goal_lon: f64,
+    max_snap_distance: Option<f64>,
+}
+
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct KShortestPathsRequest {
+    start_lat: f64,
+    start_lon: f64,
+    goal_lat: f64,
+    goal_lon: f64,
+    amount: u16,
+    max_snap_distance: Option<f64>,
+    timeout_ms: Option<u64>,
+    max_states: Option<usize>, // spur searches
+}
+
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct LoopsRequest {
+    lat: f64,
+    lon: f64,
+
+    amount: u16,
+    target_distance: f64,
+    #[serde(default)]
+    shape: LoopShape,
+    // loops of the petals shape, a figure eight unless the request says otherwise
+    petals: Option<usize>,
+    max_snap_distance: Option<f64>,
+    timeout_ms: Option<u64>,
+    max_states: Option<usize>, // stitched candidate routes
+}
+
+impl LoopsRequest {
+    fn to_query(&self) -> LoopQuery {
+        LoopQuery {
+            lat: self.lat,
+            lon: self.lon,
+            amount: self.amount as usize,
+            target_distance: self.target_distance,
+            tol: PATH_TOLERANCE,
+            shape: self.shape,
+            petals: self.petals.unwrap_or(DEFAULT_PETALS),
+            max_snap_distance: Some(self.max_snap_distance.unwrap_or(DEFAULT_MAX_SNAP_DISTANCE)),
+            budget: search_budget(self.timeout_ms, self.max_states),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct IsochroneRequest {
+    lat: f64,
+    lon: f64,
+    bands: Vec<f64>,
+    resolution_m: Option<f64>,
+    max_snap_distance: Option<f64>,
+    timeout_ms: Option<u64>,
+    max_states: Option<usize>, // bands
+}
+
+impl IsochroneRequest {
+    fn to_query(&self) -> IsochroneQuery {
+        IsochroneQuery {
+            lat: self.lat,
+            lon: self.lon,
+            bands: self.bands.clone(),
+            resolution_m: self.resolution_m.unwrap_or(DEFAULT_ISOCHRONE_RESOLUTION),
+            max_snap_distance: Some(self.max_snap_distance.unwrap_or(DEFAULT_MAX_SNAP_DISTANCE)),
+            budget: search_budget(self.timeout_ms, self.max_states),
+        }
+    }
+}
+
+fn route_error(e: RouteError) -> Custom<String> {
+    match e {
+        RouteError::SnapTooFar { .. } | RouteError::NoElevation => Custom(Status::UnprocessableEntity, e.to_string()),
+    }
+}
+
+fn history_error(e: HistoryError) -> Custom<String> {
+    match e {
+        HistoryError::BadUser(_) | HistoryError::Gpx(_) => Custom(Status::UnprocessableEntity, e.to_string()),
+        HistoryError::Io(_) | HistoryError::Corrupt { .. } => Custom(Status::InternalServerError, e.to_string()),
+    }
 }
 
 
 #[post("/ways_by_tags", format = "json", data = "<req>")]
 async fn ways_by_tags(req: Json<TagsRequest>) -> Result<Json<Vec<Way>>,Custom<String>> {
-    let file_path = "data/map";
+    let file_path = MAP_FILE;
 
     let tag_slices: Vec<&str> = req.tags.iter().map(String::as_str).collect();
     
@@ -63,39 +324,152 @@ async fn ways_by_tags(req: Json<TagsRequest>) -> Result<Json<Vec<Way>>,Custom<St
 
 
 #[post("/paths_bfs", format = "json", data = "<req>")]
-async fn paths(graph: &State<Graph>, req: Json<PathsRequest>) -> Result<Json<Vec<Path>>,Custom<String>> {
-    // defaul tol = 200 meters
-    // don't want the user to decide the tolerance
-    let paths = graph.get_paths_bfs(req.start_lat, req.start_lon, req.goal_lat, req.goal_lon, req.amount as usize, req.target_distance, 200.0);
-    Ok(Json(paths))
+async fn paths(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<PathsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_paths_bfs(&req.to_query(history)?).map_err(route_error)?;
+    Ok(Json(res))
 }
 
 #[post("/paths_special_dijkstra", format = "json", data = "<req>")]
-async fn paths_special_dijkstra(graph: &State<Graph>, req: Json<PathsRequest>) -> Result<Json<Vec<Path>>,Custom<String>> {
-    // defaul tol = 200 meters
-    // don't want the user to decide the tolerance
-    let paths = graph.get_paths_special_dijkstra(req.start_lat, req.start_lon, req.goal_lat, req.goal_lon, req.amount as usize, req.target_distance, 200.0);
-    Ok(Json(paths))
+async fn paths_special_dijkstra(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<PathsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_paths_special_dijkstra(&req.to_query(history)?).map_err(route_error)?;
+    Ok(Json(res))
 }
 
 #[post("/paths_dfs", format = "json", data = "<req>")]
-async fn paths_dfs(graph: &State<Graph>, req: Json<PathsRequest>) -> Result<Json<Vec<Path>>,Custom<String>> {
-    // defaul tol = 200 meters
-    // don't want the user to decide the tolerance
-    let paths = graph.get_paths_dfs(req.start_lat, req.start_lon, req.goal_lat, req.goal_lon, req.amount as usize, req.target_distance, 200.0);
-    if let Some(paths) = paths {
-        Ok(Json(paths))
+async fn paths_dfs(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<PathsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_paths_dfs(&req.to_query(history)?).map_err(route_error)?;
+    if res.paths.is_empty() {
+        Err(Custom(Status::NotFound, "No paths found".to_string()))
+    } else {
+        Ok(Json(res))
+    }
+}
+
+#[post("/paths_annealing", format = "json", data = "<req>")]
+async fn paths_annealing(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<AnnealingRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_paths_annealing(&req.path.to_query(history)?, &req.to_options()).map_err(route_error)?;
+    if res.paths.is_empty() {
+        Err(Custom(Status::NotFound, "No paths found".to_string()))
     } else {
+        Ok(Json(res))
+    }
+}
+
+#[post("/paths_beam", format = "json", data = "<req>")]
+async fn paths_beam(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<BeamRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let width = req.beam_width.unwrap_or(DEFAULT_BEAM_WIDTH);
+    if width == 0 {
+        return Err(Custom(Status::UnprocessableEntity, "Beam width must be at least 1".to_string()));
+    }
+    let res = graph.get_paths_beam(&req.path.to_query(history)?, width).map_err(route_error)?;
+    Ok(Json(res))
+}
+
+#[post("/paths_pareto", format = "json", data = "<req>")]
+async fn paths_pareto(graph: &State<Graph>, history: &State<HistoryStore>, req: Json<PathsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_paths_pareto(&req.to_query(history)?).map_err(route_error)?;
+    if res.paths.is_empty() {
         Err(Custom(Status::NotFound, "No paths found".to_string()))
+    } else {
+        Ok(Json(res))
+    }
+}
+
+
+#[post("/loops", format = "json", data = "<req>")]
+async fn loops(graph: &State<Graph>, req: Json<LoopsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let res = graph.get_loops(&req.to_query()).map_err(route_error)?;
+    if res.paths.is_empty() {
+        Err(Custom(Status::NotFound, "No loops found".to_string()))
+    } else {
+        Ok(Json(res))
+    }
+}
+
+#[post("/shortest_path", format = "json", data = "<req>")]
+async fn shortest_path(graph: &State<Graph>, req: Json<ShortestPathRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let max_snap_distance = Some(req.max_snap_distance.unwrap_or(DEFAULT_MAX_SNAP_DISTANCE));
+    let res = graph
+        .get_shortest_path(req.start_lat, req.start_lon, req.goal_lat, req.goal_lon, max_snap_distance)
+        .map_err(route_error)?;
+    if res.paths.is_empty() {
+        Err(Custom(Status::NotFound, "Goal is not reachable from start".to_string()))
+    } else {
+        Ok(Json(res))
+    }
+}
+
+#[post("/k_shortest_paths", format = "json", data = "<req>")]
+async fn k_shortest_paths(graph: &State<Graph>, req: Json<KShortestPathsRequest>) -> Result<Json<PathsResponse>,Custom<String>> {
+    let max_snap_distance = Some(req.max_snap_distance.unwrap_or(DEFAULT_MAX_SNAP_DISTANCE));
+    let res = graph
+        .get_k_shortest_paths(req.start_lat, req.start_lon, req.goal_lat, req.goal_lon, req.amount as usize, max_snap_distance, &search_budget(req.timeout_ms, req.max_states))
+        .map_err(route_error)?;
+    if res.paths.is_empty() {
+        Err(Custom(Status::NotFound, "Goal is not reachable from start".to_string()))
+    } else {
+        Ok(Json(res))
+    }
+}
+
+#[post("/isochrone", format = "json", data = "<req>")]
+async fn isochrone(graph: &State<Graph>, req: Json<IsochroneRequest>) -> Result<Json<Isochrone>,Custom<String>> {
+    if !req.bands.iter().any(|d| *d > 0.0) {
+        return Err(Custom(Status::UnprocessableEntity, "At least one band must be longer than 0 m".to_string()));
     }
+    if req.resolution_m.is_some_and(|r| r.is_nan() || r < MIN_ISOCHRONE_RESOLUTION_M) {
+        return Err(Custom(Status::UnprocessableEntity, format!("Resolution must be at least {} m", MIN_ISOCHRONE_RESOLUTION_M)));
+    }
+    let res = graph.isochrone(&req.to_query()).map_err(route_error)?;
+    Ok(Json(res))
+}
+
+#[get("/graph/stats")]
+async fn graph_stats(graph: &State<Graph>) -> Json<GraphStats> {
+    Json(graph.stats())
+}
+
+#[get("/graph/cache")]
+async fn cache_stats(graph: &State<Graph>) -> Json<Option<CacheStats>> {
+    Json(graph.tree_cache().map(TreeCache::stats))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct HistoryRoutesRequest {
+    // OSM node ids of every route, as the paths of a response have them
+    routes: Vec<Vec<u64>>,
+}
+
+#[get("/history/<user>")]
+async fn history_summary(history: &State<HistoryStore>, user: &str) -> Result<Json<HistorySummary>,Custom<String>> {
+    let roads = history.history(user).map_err(history_error)?.len();
+    Ok(Json(HistorySummary { roads }))
+}
+
+#[post("/history/<user>/routes", format = "json", data = "<req>")]
+async fn history_routes(history: &State<HistoryStore>, user: &str, req: Json<HistoryRoutesRequest>) -> Result<Json<HistoryStats>,Custom<String>> {
+    let stats = history.add_routes(user, &req.routes).map_err(history_error)?;
+    Ok(Json(stats))
 }
 
+#[post("/history/<user>/gpx", data = "<gpx>")]
+async fn history_gpx(graph: &State<Graph>, history: &State<HistoryStore>, user: &str, gpx: Data<'_>) -> Result<Json<HistoryStats>,Custom<String>> {
+    let gpx = gpx
+        .open(GPX_LIMIT_MIB.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| Custom(Status::BadRequest, format!("Error reading GPX: {}", e)))?;
+    if !gpx.is_complete() {
+        return Err(Custom(Status::PayloadTooLarge, format!("GPX files may be at most {} MiB", GPX_LIMIT_MIB)));
+    }
+    let stats = history.add_gpx(user, graph, &gpx).map_err(history_error)?;
+    Ok(Json(stats))
+}
 
 
-#[launch]
-fn rocket() -> _ {
-    let graph = create_graph("data/map", ACCEPTED_ROAD_TYPES).expect("Failed to create graph");
 
+fn build_rocket(graph: Graph, history: HistoryStore) -> rocket::Rocket<rocket::Build> {
     let cors = CorsOptions {
         allowed_origins: AllowedOrigins::all(),
         allow_credentials: true,
@@ -106,6 +480,96 @@ fn rocket() -> _ {
 
     rocket::build()
         .manage(graph)
+        .manage(history)
         .attach(cors)
-        .mount("/", routes![ways_by_tags, paths, paths_special_dijkstra, paths_dfs])
+        .mount("/", routes![ways_by_tags, paths, paths_special_dijkstra, paths_dfs, paths_annealing, paths_beam, paths_pareto, loops, shortest_path, k_shortest_paths, isochrone, graph_stats, cache_stats, history_summary, history_routes, history_gpx])
+}
+
+// parses "min_lat,min_lon,max_lat,max_lon"
+fn parse_bbox(s: &str) -> BoundingBox {
+    let v: Vec<f64> = s.split(',').map(|x| x.trim().parse().expect("bbox values must be numbers")).collect();
+    assert_eq!(v.len(), 4, "bbox must be min_lat,min_lon,max_lat,max_lon");
+    BoundingBox { min_lat: v[0], min_lon: v[1], max_lat: v[2], max_lon: v[3] }
+}
+
+fn export(format: &str, output: &str, bbox: Option<BoundingBox>) {
+    let graph = create_graph(MAP_FILE, ACCEPTED_ROAD_TYPES).expect("Failed to create graph");
+    let file = std::fs::File::create(output).unwrap_or_else(|_| panic!("failed to create {}", output));
+    let mut w = std::io::BufWriter::new(file);
+    match format {
+        "graphml" => graph.write_graphml(&mut w),
+        "dot" => graph.write_dot(&mut w),
+        "geojson" => graph.write_geojson(&mut w, bbox),
+        _ => panic!("unknown export format {}, use graphml, dot or geojson", format),
+    }
+    .expect("Failed to write export");
+}
+
+fn build_ch(output: &str) {
+    let graph = create_graph(MAP_FILE, ACCEPTED_ROAD_TYPES).expect("Failed to create graph");
+    let ch = ContractionHierarchy::build(&graph);
+    println!("{} nodes, {} shortcuts", ch.node_count(), ch.shortcut_count());
+    let file = std::fs::File::create(output).unwrap_or_else(|_| panic!("failed to create {}", output));
+    ch.write_to(&mut std::io::BufWriter::new(file)).expect("Failed to write contraction hierarchy");
+}
+
+// uses the saved contraction hierarchy if there is one for this graph
+fn attach_ch(graph: Graph) -> Graph {
+    let Ok(file) = std::fs::File::open(CH_FILE) else {
+        return graph;
+    };
+    match ContractionHierarchy::read_from(&mut std::io::BufReader::new(file), &graph) {
+        Ok(ch) => graph.with_contraction_hierarchy(ch),
+        Err(e) => {
+            eprintln!("failed to read {}: {}, run `server ch` again", CH_FILE, e);
+            graph
+        }
+    }
+}
+
+// reads ELEVATION_FILE if there is one
+fn attach_elevation(graph: Graph) -> Graph {
+    let Ok(file) = std::fs::File::open(ELEVATION_FILE) else {
+        return graph;
+    };
+    match graph.read_elevation(std::io::BufReader::new(file)) {
+        Ok(heights) => graph.with_elevation(heights),
+        Err(e) => {
+            eprintln!("failed to read {}: {}", ELEVATION_FILE, e);
+            graph
+        }
+    }
+}
+
+// usage:
+//   server                 start the web server on data/map, with data/map.ch and
+//                          data/map.elevation when they exist
+//   server stats [file]    print statistics of the graph built from the file
+//   server export <graphml|dot|geojson> <output> [min_lat,min_lon,max_lat,max_lon]
+//                          write the graph of data/map, the bbox only applies to geojson
+//   server ch [output]     build the contraction hierarchy of data/map (default data/map.ch)
+#[rocket::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("stats") => {
+            let file_path = args.get(2).map(String::as_str).unwrap_or(MAP_FILE);
+            let graph = create_graph(file_path, ACCEPTED_ROAD_TYPES).expect("Failed to create graph");
+            let stats = serde_json::to_string_pretty(&graph.stats()).expect("Failed to serialize stats");
+            println!("{}", stats);
+        }
+        Some("export") => {
+            let format = args.get(2).expect("missing export format");
+            let output = args.get(3).expect("missing output file");
+            export(format, output, args.get(4).map(|b| parse_bbox(b)));
+        }
+        Some("ch") => build_ch(args.get(2).map(String::as_str).unwrap_or(CH_FILE)),
+        _ => {
+            let graph = attach_elevation(attach_ch(create_graph(MAP_FILE, ACCEPTED_ROAD_TYPES).expect("Failed to create graph")))
+                .with_landmarks(LANDMARK_COUNT)
+                .with_tree_cache(TreeCache::new(TREE_CACHE_ENTRIES, TREE_CACHE_BYTES));
+            let history = HistoryStore::open(HISTORY_DIR).expect("Failed to open the history store");
+            build_rocket(graph, history).launch().await.expect("Failed to launch server");
+        }
+    }
 }
@@ -1,9 +1,15 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use quick_xml::Error as xmlError;
 use quick_xml::events::attributes::AttrError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
+use crate::budget::Budget;
+use crate::history::History;
+
 
 #[derive(Error, Debug)]
 pub enum OsmError {
@@ -13,20 +19,263 @@ pub enum OsmError {
     AttributeParseError(#[from] AttrError),
 }
 
+#[derive(Error, Debug)]
+pub enum RouteError {
+    #[error("{point} is {distance:.0} m away from the nearest road, the maximum is {max:.0} m")]
+    SnapTooFar { point: &'static str, distance: f64, max: f64 },
+    #[error("the map has no elevation data, routes can't be searched by ascent")]
+    NoElevation,
+}
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("history file error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("user names may only have letters, digits, - and _, got {0:?}")]
+    BadUser(String),
+    #[error("history of {user} is broken at line {line}")]
+    Corrupt { user: String, line: usize },
+    #[error("GPX error: {0}")]
+    Gpx(String),
+}
+
+impl From<xmlError> for HistoryError {
+    fn from(e: xmlError) -> Self {
+        HistoryError::Gpx(e.to_string())
+    }
+}
+
+impl From<AttrError> for HistoryError {
+    fn from(e: AttrError) -> Self {
+        HistoryError::Gpx(e.to_string())
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Path {
     pub id: Uuid, // add id to have difference between paths in frontend
     pub distance: f64,
-    pub nodes: Vec<Node>
+    pub nodes: Vec<Node>,
+    pub overlap: f64, // largest overlap with another path of the same response, see DiversityFilter
+    pub criteria: Criteria,
 }
 
 impl Path {
 
     pub fn new(nodes: Vec<Node>, distance: f64) -> Path {
-        Path { id: Uuid::new_v4(), distance, nodes }
+        Path { id: Uuid::new_v4(), distance, nodes, overlap: 0.0, criteria: Criteria::default() }
     }
 }
 
+/// What a route is like besides its length, see the pareto module
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Criteria {
+    pub elevation_gain_m: f64, // summed climbs, 0 without elevation data
+    pub comfort: f64, // 0 (all trunk road) to 1 (all cycleway or footpath), weighted by length
+}
+
+/// Parameters of a target-distance path search between two coordinates
+#[derive(Debug, Clone)]
+pub struct PathQuery {
+    pub start_lat: f64,
+    pub start_lon: f64,
+    pub goal_lat: f64,
+    pub goal_lon: f64,
+    pub amount: usize,
+    pub target_distance: f64,
+    pub tol: f64,
+    pub max_snap_distance: Option<f64>, // meters, None means snap no matter how far
+    pub max_overlap: Option<f64>, // reject paths overlapping an earlier one by more than this, None keeps all
+    pub via: Vec<(f64, f64)>, // lat/lon of points the paths pass between start and goal
+    pub via_order: ViaOrder,
+    pub avoid: Avoid,
+    pub repeats: RepeatPolicy,
+    pub budget: Budget,
+    pub seed: Option<u64>, // for the randomized searches, None picks a fresh one
+    pub ascent: Option<AscentRange>, // summed climbs the paths must have, None for any
+    pub novelty: Option<Novelty>,
+}
+
+impl PathQuery {
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions { max_overlap: self.max_overlap, repeats: self.repeats, budget: self.budget.clone(), seed: self.seed, ascent: self.ascent, novelty: self.novelty.clone() }
+    }
+}
+
+/// What a path may pass more than once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatPolicy {
+    /// every node at most once, the goal excepted
+    #[default]
+    NoNodeRepeats,
+    /// every road at most once, crossings may be passed again
+    NoEdgeRepeats,
+    /// every road at most this many times, 2 allows out-and-back stretches
+    MaxEdgeUses(u32),
+}
+
+/// Settings the path searches share
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub max_overlap: Option<f64>, // see DiversityFilter
+    pub repeats: RepeatPolicy,
+    pub budget: Budget,
+    pub seed: Option<u64>, // only used by the dfs
+    pub ascent: Option<AscentRange>,
+    pub novelty: Option<Novelty>,
+}
+
+/// Makes roads a user ran before count against a path: every meter of them
+/// costs as much as `weight` meters off the target distance
+#[derive(Debug, Clone)]
+pub struct Novelty {
+    pub history: Arc<History>,
+    pub weight: f64,
+}
+
+impl Novelty {
+    // a meter of known road outweighs any length error within a tolerance
+    pub const EXPLORE_WEIGHT: f64 = 1000.0;
+
+    /// Routes with as many new roads as possible, the length only has to be
+    /// within the tolerance
+    pub fn explore(history: Arc<History>) -> Self {
+        Novelty { history, weight: Self::EXPLORE_WEIGHT }
+    }
+}
+
+/// How much a path may climb in total, in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AscentRange {
+    pub min_m: f64,
+    pub max_m: f64,
+}
+
+impl AscentRange {
+    pub fn around(target_m: f64, tol_m: f64) -> Self {
+        AscentRange { min_m: target_m - tol_m, max_m: target_m + tol_m }
+    }
+
+    pub fn contains(&self, ascent: f64) -> bool {
+        ascent >= self.min_m && ascent <= self.max_m
+    }
+}
+
+/// Why a search gave up before it was done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    StateLimit,
+    Deadline,
+    MemoryLimit,
+    Cancelled,
+}
+
+/// Paths a search found, as node sequences with their lengths, and why it
+/// stopped early if it did
+#[derive(Debug, Clone, Default)]
+pub struct SearchOutcome {
+    pub paths: Vec<(Vec<usize>, f64)>,
+    pub stopped: Option<StopReason>,
+}
+
+/// Parts of the map a single request must not use
+#[derive(Debug, Clone, Default)]
+pub struct Avoid {
+    pub polygons: Vec<Vec<(f64, f64)>>, // lat/lon rings, roads inside or crossing them are closed
+    pub way_ids: Vec<u64>,
+    pub node_ids: Vec<u64>, // OSM ids, roads leading to these nodes are closed
+}
+
+impl Avoid {
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty() && self.way_ids.is_empty() && self.node_ids.is_empty()
+    }
+}
+
+/// Whether via-points are visited as given or in the order that is shortest
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViaOrder {
+    #[default]
+    Ordered,
+    Unordered,
+}
+
+/// What the route optimizer minimizes besides hitting the target distance.
+/// Every term is a fraction of the route length times its weight.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Objective {
+    pub distance_weight: f64, // per fraction the length is off the target
+    pub reuse_weight: f64, // per fraction of the route that is driven more than once
+    pub highway_penalties: HashMap<String, f64>, // highway tag -> weight per fraction on it
+}
+
+impl Default for Objective {
+    fn default() -> Self {
+        Objective { distance_weight: 1.0, reuse_weight: 0.5, highway_penalties: HashMap::new() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopShape {
+    #[default]
+    Round,
+    Elongated,
+    OutAndBack, // to a turnaround point and back the same way
+    Petals, // several round loops through the start, two make a figure eight
+}
+
+/// Parameters of a round trip that starts and ends at one coordinate
+#[derive(Debug, Clone)]
+pub struct LoopQuery {
+    pub lat: f64,
+    pub lon: f64,
+    pub amount: usize,
+    pub target_distance: f64,
+    pub tol: f64,
+    pub shape: LoopShape,
+    pub petals: usize, // only for `LoopShape::Petals`
+    pub max_snap_distance: Option<f64>,
+    pub budget: Budget,
+}
+
+/// Everything reachable from one coordinate within each of the distances
+#[derive(Debug, Clone)]
+pub struct IsochroneQuery {
+    pub lat: f64,
+    pub lon: f64,
+    pub bands: Vec<f64>, // meters along the road
+    pub resolution_m: f64, // cell size of the polygon, roads are buffered by about one cell
+    pub max_snap_distance: Option<f64>,
+    pub budget: Budget,
+}
+
+/// Where a requested coordinate ended up on the graph
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SnapInfo {
+    #[serde(skip_serializing)]
+    pub node_index: usize,
+    pub node_id: Option<u64>, // None when snapped into the middle of an edge
+    pub way_id: Option<u64>,
+    pub lat: f64,
+    pub lon: f64,
+    pub distance: f64, // meters between the requested coordinate and the snapped node
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PathsResponse {
+    pub start: SnapInfo,
+    pub goal: SnapInfo,
+    pub via: Vec<SnapInfo>, // in the order the paths visit them
+    pub stopped: Option<StopReason>, // set when the search ran out of budget, the paths are what it found until then
+    pub seed: Option<u64>, // what a randomized search used, sending it again gives the same paths
+    pub paths: Vec<Path>,
+}
+
 
 
 #[derive(Debug, Clone, Copy)]
@@ -43,6 +292,20 @@ pub struct Neighbor {
 }
 
 
+/// A coordinate projected onto the closest edge of the graph
+#[derive(Debug, Clone, Copy)]
+pub struct EdgeSnap {
+    pub from: usize, // node index of the edge start
+    pub to: usize, // node index of the edge end
+    pub way_id: u64,
+    pub fraction: f64, // position on the edge, 0.0 is `from` and 1.0 is `to`
+    pub lat: f64, // projected point
+    pub lon: f64,
+    pub distance: f64, // meters between the coordinate and the projected point
+    pub edge_length: f64,
+}
+
+
 #[derive(Debug, Clone,Copy, Serialize)]
 pub struct Node {
     id: u64,
@@ -76,12 +339,20 @@ pub struct Way {
     pub nodes: Vec<Node>,
     
     #[serde(skip_serializing)]
-    pub node_refs: Vec<u64> // vec containing the OSM node ids
+    pub node_refs: Vec<u64>, // vec containing the OSM node ids
+
+    #[serde(skip_serializing)]
+    pub tags: HashMap<String, String>,
 }
 
 impl Way {
     pub fn new(id: u64, node_refs: Vec<u64>, nodes: Vec<Node>) -> Self {
-        Way { id, node_refs, nodes }
+        Way { id, node_refs, nodes, tags: HashMap::new() }
+    }
+
+    pub fn with_tags(mut self, tags: HashMap<String, String>) -> Self {
+        self.tags = tags;
+        self
     }
 
     pub fn id(&self) -> u64 {
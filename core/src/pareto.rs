@@ -0,0 +1,255 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::diversity::DiversityFilter;
+use crate::graph::Graph;
+use crate::model::{Criteria, PathQuery, PathsResponse, RouteError, SearchOptions, SearchOutcome};
+use crate::query::QueryGraph;
+
+// how unpleasant a meter of road is per highway tag, 0 is as good as it gets
+const DISCOMFORT: &[(&str, f64)] = &[
+    ("cycleway", 0.0),
+    ("footway", 0.0),
+    ("path", 0.0),
+    ("pedestrian", 0.0),
+    ("living_street", 0.05),
+    ("track", 0.1),
+    ("residential", 0.2),
+    ("service", 0.2),
+    ("unclassified", 0.3),
+    ("road", 0.4),
+    ("tertiary", 0.5),
+    ("tertiary_link", 0.5),
+    ("secondary", 0.7),
+    ("secondary_link", 0.7),
+    ("primary", 0.85),
+    ("primary_link", 0.85),
+    ("trunk", 1.0),
+    ("trunk_link", 1.0),
+];
+// roads without a known highway tag
+const DEFAULT_DISCOMFORT: f64 = 0.5;
+
+// length, elevation gain and discomfort (meters times DISCOMFORT), all minimized
+type Cost = [f64; 3];
+
+fn dominates(a: &Cost, b: &Cost) -> bool {
+    a.iter().zip(b).all(|(x, y)| x <= y)
+}
+
+struct Label {
+    node: usize,
+    prev: Option<usize>, // index in labels
+    cost: Cost,
+    alive: bool, // false once a later label dominates it
+}
+
+struct Queued {
+    cost: Cost,
+    label: usize,
+}
+
+impl Ord for Queued {
+    // lexicographic, flipped for a min-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.iter().zip(&self.cost).map(|(a, b)| a.total_cmp(b)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl Graph {
+    /// The Pareto front of routes between start and goal trading off
+    /// length, elevation gain and comfort, at most `target_distance + tol`
+    /// long. Of a larger front `amount` routes spread over its length are
+    /// returned. With via-points every leg gets its own front.
+    pub fn get_paths_pareto(&self, req: &PathQuery) -> Result<PathsResponse, RouteError> {
+        self.get_paths_with(req, |query, start, goal, target_distance, tol, options| {
+            query.pareto(start, goal, req.amount, target_distance + tol, options)
+        })
+    }
+}
+
+impl QueryGraph<'_> {
+    fn discomfort(&self, way_id: u64) -> f64 {
+        let highway = self.graph().tag(way_id, "highway");
+        DISCOMFORT.iter().find(|(tag, _)| Some(*tag) == highway).map_or(DEFAULT_DISCOMFORT, |(_, d)| *d)
+    }
+
+    /// Elevation gain and comfort of a node sequence
+    pub fn criteria(&self, path: &[usize]) -> Criteria {
+        let (mut length, mut discomfort) = (0.0, 0.0);
+        for w in path.windows(2) {
+            let Some(edge) = self.neighbors(w[0]).filter(|n| n.node_index == w[1]).min_by(|a, b| a.edge_data.length_m.total_cmp(&b.edge_data.length_m)) else {
+                continue;
+            };
+            length += edge.edge_data.length_m;
+            discomfort += edge.edge_data.length_m * self.discomfort(edge.edge_data.way_id);
+        }
+        let comfort = if length > 0.0 { 1.0 - discomfort / length } else { 1.0 };
+        Criteria { elevation_gain_m: self.ascent(path), comfort }
+    }
+
+    /// Multi-criteria label setting (Martins). Every node keeps the labels
+    /// no other label at it beats on length, elevation gain and discomfort,
+    /// labels are settled in lexicographic order. Labels longer than
+    /// `max_length` to the goal or beaten by a route already at the goal are
+    /// dropped. Returns up to `k` routes of the front spread over its length,
+    /// shortest first, or what it has when `options.budget` runs out.
+    pub fn pareto(&self, start: usize, goal: usize, k: usize, max_length: f64, options: &SearchOptions) -> SearchOutcome {
+        let (d_goal, _) = self.reverse_tree(goal, max_length);
+        if d_goal[start] > max_length || k == 0 {
+            return SearchOutcome::default();
+        }
+        let heights = self.heights();
+        let mut meter = options.budget.meter();
+
+        let mut labels = vec![Label { node: start, prev: None, cost: [0.0; 3], alive: true }];
+        let mut at_node: Vec<Vec<usize>> = vec![Vec::new(); self.node_count()];
+        at_node[start].push(0);
+        let mut heap = BinaryHeap::from([Queued { cost: [0.0; 3], label: 0 }]);
+        let mut front: Vec<usize> = Vec::new();
+
+        while let Some(Queued { label: current, .. }) = heap.pop() {
+            let bytes = labels.len() * size_of::<Label>() + heap.len() * size_of::<Queued>();
+            if meter.exhausted(labels.len(), bytes) {
+                break;
+            }
+            if !labels[current].alive {
+                continue;
+            }
+            let (node, cost) = (labels[current].node, labels[current].cost);
+            if node == goal {
+                front.push(current);
+                continue;
+            }
+
+            for neighbor in self.neighbors(node) {
+                let next = neighbor.node_index;
+                let length = neighbor.edge_data.length_m;
+                let gain = heights.as_ref().map_or(0.0, |h| (h[next] - h[node]).max(0.0));
+                let new_cost = [cost[0] + length, cost[1] + gain, cost[2] + length * self.discomfort(neighbor.edge_data.way_id)];
+                if new_cost[0] + d_goal[next] > max_length {
+                    continue;
+                }
+                // the rest of the way is at least this long and costs nothing else
+                let optimistic = [new_cost[0] + d_goal[next], new_cost[1], new_cost[2]];
+                if front.iter().any(|&l| dominates(&labels[l].cost, &optimistic)) {
+                    continue;
+                }
+                if at_node[next].iter().any(|&l| dominates(&labels[l].cost, &new_cost)) {
+                    continue;
+                }
+                let beaten: Vec<usize> = at_node[next].iter().copied().filter(|&l| dominates(&new_cost, &labels[l].cost)).collect();
+                for l in beaten {
+                    labels[l].alive = false;
+                }
+                at_node[next].retain(|&l| labels[l].alive);
+
+                labels.push(Label { node: next, prev: Some(current), cost: new_cost, alive: true });
+                at_node[next].push(labels.len() - 1);
+                heap.push(Queued { cost: new_cost, label: labels.len() - 1 });
+            }
+        }
+
+        let mut diversity = DiversityFilter::new(options.max_overlap);
+        let mut routes: Vec<(Vec<usize>, f64)> = Vec::new();
+        for &l in &front {
+            let mut path = Vec::new();
+            let mut cur = Some(l);
+            while let Some(i) = cur {
+                path.push(labels[i].node);
+                cur = labels[i].prev;
+            }
+            path.reverse();
+            if diversity.accept(self, &path) {
+                routes.push((path, labels[l].cost[0]));
+            }
+        }
+        SearchOutcome { paths: spread(routes, k), stopped: meter.stopped() }
+    }
+}
+
+// `k` of the routes, sorted by length, evenly spaced so both ends of the
+// front are always in
+fn spread(mut routes: Vec<(Vec<usize>, f64)>, k: usize) -> Vec<(Vec<usize>, f64)> {
+    routes.sort_by(|a, b| a.1.total_cmp(&b.1));
+    if routes.len() <= k {
+        return routes;
+    }
+    if k == 1 {
+        routes.truncate(1);
+        return routes;
+    }
+    let last = routes.len() - 1;
+    let picked: Vec<usize> = (0..k).map(|i| (i * last + (k - 1) / 2) / (k - 1)).collect();
+    routes.into_iter().enumerate().filter(|(i, _)| picked.contains(i)).map(|(_, route)| route).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::model::{EdgeData, Neighbor, Node};
+
+    /// Three ways from 0 to 4, all nodes in one place:
+    ///   0 -100- 1 -100- 4   short, primary road over a 30 m hill at 1
+    ///   0 -150- 2 -150- 4   cycleway, flat
+    ///   0 -120- 3 -120- 4   residential over a 10 m hill at 3
+    ///   0 -200- 5 -200- 4   primary and hilly, worse than the first in every way
+    fn build_graph() -> Graph {
+        let nodes = (0..6).map(|i| Node::new(i, 0.0, 0.0)).collect();
+        let mut adj = vec![Vec::new(); 6];
+        let mut way_tags = HashMap::new();
+        for (way_id, via, length_m, highway) in [(1, 1, 100.0, "primary"), (2, 2, 150.0, "cycleway"), (3, 3, 120.0, "residential"), (4, 5, 200.0, "primary")] {
+            way_tags.insert(way_id, HashMap::from([("highway".to_string(), highway.to_string())]));
+            let edge_data = EdgeData { way_id, length_m };
+            for (u, v) in [(0, via), (via, 4)] {
+                adj[u].push(Neighbor { osm_id: v as u64, node_index: v, edge_data });
+                adj[v].push(Neighbor { osm_id: u as u64, node_index: u, edge_data });
+            }
+        }
+        Graph::new(nodes, adj).with_way_tags(way_tags).with_elevation(vec![0.0, 30.0, 0.0, 10.0, 0.0, 40.0])
+    }
+
+    #[test]
+    fn front_has_every_trade_off_and_nothing_dominated() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        let res = query.pareto(0, 4, 10, 1000.0, &SearchOptions::default());
+        let paths: Vec<Vec<usize>> = res.paths.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(paths, vec![vec![0, 1, 4], vec![0, 3, 4], vec![0, 2, 4]]);
+
+        let criteria: Vec<Criteria> = paths.iter().map(|path| query.criteria(path)).collect();
+        assert_eq!(criteria[0].elevation_gain_m, 30.0);
+        assert_eq!(criteria[1].elevation_gain_m, 10.0);
+        assert_eq!(criteria[2], Criteria { elevation_gain_m: 0.0, comfort: 1.0 });
+        assert!((criteria[0].comfort - 0.15).abs() < 1e-9);
+
+        // the cycleway is too long, the hilly primary road not worth it
+        let res = query.pareto(0, 4, 10, 250.0, &SearchOptions::default());
+        assert_eq!(res.paths.len(), 2);
+    }
+
+    #[test]
+    fn spread_keeps_both_ends() {
+        let routes: Vec<(Vec<usize>, f64)> = (0..7).map(|i| (vec![i], i as f64)).collect();
+        let lengths = |routes: Vec<(Vec<usize>, f64)>| routes.into_iter().map(|(_, length)| length).collect::<Vec<_>>();
+        assert_eq!(lengths(spread(routes.clone(), 3)), vec![0.0, 3.0, 6.0]);
+        assert_eq!(lengths(spread(routes.clone(), 2)), vec![0.0, 6.0]);
+        assert_eq!(lengths(spread(routes, 1)), vec![0.0]);
+    }
+}
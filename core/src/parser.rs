@@ -16,12 +16,12 @@ pub fn get_nodes_on_ways(nodes: HashMap<u64,Node>, ways: &[Way]) -> Vec<Way> {
         let mut way_clone = way.clone();
         for node_ref in &way.node_refs() {
             if let Some(node) = nodes.get(node_ref) {
-                way_clone.nodes.push(node.clone());
+                way_clone.nodes.push(*node);
             }
         }
         way_return.push(way_clone);
     }
-    return way_return
+    way_return
 }
 
 
@@ -124,6 +124,7 @@ pub fn parse_ways_with_tags<R: BufRead>(tag_filters: &[&str], reader: R) -> Resu
         match xml.read_event_into(&mut buf) {
             Ok(Event::Start(ref way)) if way.name().0 == b"way" => {
                 let mut node_refs: Vec<u64> = Vec::new();
+                let mut tags: HashMap<String, String> = HashMap::new();
                 let mut way_id: u64 = 0;
                 for attr in way.attributes() {
                     let attr = attr?;
@@ -146,17 +147,17 @@ pub fn parse_ways_with_tags<R: BufRead>(tag_filters: &[&str], reader: R) -> Resu
                         }
                         Ok(Event::Empty(ref tag)) if tag.name().0 == b"tag" => {
                             let mut key: Option<String> = None;
+                            let mut value = String::new();
                             for attr in tag.attributes() {
                                 let attr = attr?;
-                                if attr.key.0 == b"k" {
-                                    key = Some(str::from_utf8(&attr.value).expect("key is not a string").to_string());
+                                match attr.key.0 {
+                                    b"k" => key = Some(str::from_utf8(&attr.value).expect("key is not a string").to_string()),
+                                    b"v" => value = str::from_utf8(&attr.value).expect("value is not a string").to_string(),
+                                    _ => {}
                                 }
                             }
                             if let Some(k) = key {
-                                if tag_filters.contains(&k.as_str()) {
-                                    ways.push(model::Way::new(way_id, node_refs.clone(), Vec::new()));
-                                    break;
-                                }
+                                tags.insert(k, value);
                             }
                         }
                         Ok(Event::End(ref e2)) if e2.name().0 == b"way" => break, // end of this <way>
@@ -166,6 +167,10 @@ pub fn parse_ways_with_tags<R: BufRead>(tag_filters: &[&str], reader: R) -> Resu
                     }
                     buf.clear();
                 }
+                // keep the way if any of its tag keys is one we look for
+                if tags.keys().any(|k| tag_filters.contains(&k.as_str())) {
+                    ways.push(model::Way::new(way_id, node_refs, Vec::new()).with_tags(tags));
+                }
             },
             Ok(Event::Eof) => break,
             Ok(_) => (),
@@ -276,5 +281,24 @@ mod tests {
         assert_eq!(ways[1].node_refs, vec![3]);
         assert_eq!(ways[2].node_refs, vec![5]);
     }
-}
 
+    #[test]
+    fn test_parse_ways_keeps_tag_values() {
+        let xml = r#"
+            <osm>
+                <way id="7">
+                    <nd ref="1"/>
+                    <nd ref="2"/>
+                    <tag k="highway" v="residential"/>
+                    <tag k="name" v="Main St"/>
+                </way>
+            </osm>
+        "#;
+        let reader = BufReader::new(xml.as_bytes());
+        let ways = parse_ways_with_tags(&["highway"], reader).unwrap();
+        assert_eq!(ways.len(), 1);
+        assert_eq!(ways[0].id, 7);
+        assert_eq!(ways[0].tags.get("highway").map(String::as_str), Some("residential"));
+        assert_eq!(ways[0].tags.get("name").map(String::as_str), Some("Main St"));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+
+use crate::avoid::Mask;
+use crate::builder::haversine_distance;
+use crate::graph::{BasicState, Graph};
+use crate::model::{EdgeData, EdgeSnap, Neighbor, Node, SnapInfo};
+
+// snaps closer than this fraction to an edge end are mapped onto the real node
+const SNAP_END_EPSILON: f64 = 1e-6;
+
+/// A per-query view on top of a shared `Graph`.
+///
+/// The base graph is never modified, instead virtual nodes are appended after
+/// the real ones (index >= `graph.nodes().len()`) and wired into the graph by
+/// extra adjacency entries that only live as long as the query.
+pub struct QueryGraph<'a> {
+    graph: &'a Graph,
+    virtual_nodes: Vec<Node>,
+    virtual_adj: Vec<Vec<Neighbor>>,
+    virtual_edges: Vec<(usize, usize)>, // real edge each virtual node lies on
+    extra_adj: HashMap<usize, Vec<Neighbor>>,
+    split: HashSet<(usize, usize, u64)>, // real edges with virtual nodes on them, lower index first, and their way
+    pub(crate) mask: Option<Mask>, // roads closed for this query, see `avoiding`
+}
+
+impl<'a> QueryGraph<'a> {
+    pub fn new(graph: &'a Graph) -> Self {
+        QueryGraph {
+            graph,
+            virtual_nodes: Vec::new(),
+            virtual_adj: Vec::new(),
+            virtual_edges: Vec::new(),
+            extra_adj: HashMap::new(),
+            split: HashSet::new(),
+            mask: None,
+        }
+    }
+
+    pub fn graph(&self) -> &'a Graph {
+        self.graph
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.graph.nodes().len() + self.virtual_nodes.len()
+    }
+
+    pub fn node(&self, idx: usize) -> &Node {
+        let base = self.graph.nodes().len();
+        if idx < base {
+            &self.graph.nodes()[idx]
+        } else {
+            &self.virtual_nodes[idx - base]
+        }
+    }
+
+    pub fn is_virtual(&self, idx: usize) -> bool {
+        idx >= self.graph.nodes().len()
+    }
+
+    /// Edges leaving `idx`. A real edge with virtual nodes on it is left
+    /// out, its pieces between the virtual nodes take its place, so no search
+    /// can run past a snapped point on the original edge.
+    pub fn neighbors(&self, idx: usize) -> impl Iterator<Item = &Neighbor> {
+        let base = self.graph.nodes().len();
+        let (real, virt): (&[Neighbor], &[Neighbor]) = if idx < base {
+            let extra = self.extra_adj.get(&idx).map(Vec::as_slice).unwrap_or(&[]);
+            (&self.graph.adj()[idx], extra)
+        } else {
+            (&[], &self.virtual_adj[idx - base])
+        };
+        real.iter()
+            .filter(move |n| self.split.is_empty() || !self.split.contains(&(idx.min(n.node_index), idx.max(n.node_index), n.edge_data.way_id)))
+            .chain(virt.iter())
+            .filter(move |n| self.mask.as_ref().is_none_or(|mask| mask.allows(self, idx, n)))
+    }
+
+    /// Whether a real edge between `u` and `v` got virtual nodes, `neighbors`
+    /// hides it then
+    pub fn is_split_edge(&self, u: usize, v: usize) -> bool {
+        self.split.iter().any(|&(a, b, _)| (a, b) == (u.min(v), u.max(v)))
+    }
+
+    /// The real edge a virtual node was put on, `None` for real nodes
+    pub fn split_edge_of(&self, v: usize) -> Option<(usize, usize)> {
+        let base = self.graph.nodes().len();
+        v.checked_sub(base).map(|i| self.virtual_edges[i])
+    }
+
+    /// Length of the shortest edge between two adjacent nodes
+    pub fn edge_length(&self, u: usize, v: usize) -> Option<f64> {
+        self.neighbors(u)
+            .filter(|n| n.node_index == v)
+            .map(|n| n.edge_data.length_m)
+            .min_by(f64::total_cmp)
+    }
+
+    /// Shortest distances from `v` to the nodes reachable without passing
+    /// through a real node, plus the parent of each on the way back to `v`.
+    /// For a real `v` that is just `v` itself, for a virtual node it is the
+    /// rest of its split edge up to and including the real nodes at its ends.
+    pub fn virtual_reach(&self, v: usize) -> (HashMap<usize, f64>, HashMap<usize, usize>) {
+        let mut dist = HashMap::from([(v, 0.0)]);
+        let mut parent = HashMap::new();
+        let mut heap = BinaryHeap::from([BasicState { node: v, dist: 0.0 }]);
+        while let Some(BasicState { node: u, dist: du }) = heap.pop() {
+            if du > dist[&u] || !self.is_virtual(u) {
+                continue;
+            }
+            for edge in self.neighbors(u) {
+                let w = edge.node_index;
+                let dw = du + edge.edge_data.length_m;
+                if dist.get(&w).is_none_or(|&d| dw < d) {
+                    dist.insert(w, dw);
+                    parent.insert(w, u);
+                    heap.push(BasicState { node: w, dist: dw });
+                }
+            }
+        }
+        (dist, parent)
+    }
+
+    /// Snaps every coordinate onto the closest edge and returns where each of
+    /// them ended up, in the same order as `points`.
+    /// Falls back to the nearest real node when the graph has no edges.
+    pub fn snap(&mut self, points: &[(f64, f64)]) -> Vec<SnapInfo> {
+        let snaps: Option<Vec<EdgeSnap>> = points
+            .iter()
+            .map(|&(lat, lon)| {
+                // never onto a closed road
+                self.graph.snap_to_edge_where(lat, lon, |u, n| self.mask.as_ref().is_none_or(|mask| mask.allows(self, u, n)))
+            })
+            .collect();
+        match snaps {
+            Some(snaps) => {
+                let indices = self.split_edges(&snaps);
+                indices
+                    .into_iter()
+                    .zip(snaps)
+                    .map(|(idx, snap)| self.snap_info(idx, Some(snap.way_id), snap.distance))
+                    .collect()
+            }
+            None => points
+                .iter()
+                .map(|&(lat, lon)| {
+                    let idx = self.graph.map_lat_lon_to_node(lat, lon);
+                    let node = self.node(idx);
+                    self.snap_info(idx, None, haversine_distance(lat, lon, node.lat(), node.lon()))
+                })
+                .collect(),
+        }
+    }
+
+    fn snap_info(&self, idx: usize, way_id: Option<u64>, distance: f64) -> SnapInfo {
+        let node = self.node(idx);
+        SnapInfo {
+            node_index: idx,
+            node_id: if self.is_virtual(idx) { None } else { Some(node.id()) },
+            way_id,
+            lat: node.lat(),
+            lon: node.lon(),
+            distance,
+        }
+    }
+
+    /// Inserts a virtual node at every snap and returns the node index to use
+    /// for each of them, in the same order as `snaps`.
+    ///
+    /// Snaps on the same edge are chained along the edge (from - v1 - v2 - to)
+    /// so every partial edge has the right length. Snaps at (almost) the end
+    /// of an edge reuse the real node, identical snaps share one virtual node.
+    pub fn split_edges(&mut self, snaps: &[EdgeSnap]) -> Vec<usize> {
+        let mut indices = vec![0; snaps.len()];
+        let mut per_edge: BTreeMap<(usize, usize, u64), Vec<usize>> = BTreeMap::new();
+
+        for (i, snap) in snaps.iter().enumerate() {
+            if snap.fraction <= SNAP_END_EPSILON {
+                indices[i] = snap.from;
+            } else if snap.fraction >= 1.0 - SNAP_END_EPSILON {
+                indices[i] = snap.to;
+            } else {
+                per_edge.entry((snap.from, snap.to, snap.way_id)).or_default().push(i);
+            }
+        }
+
+        for ((from, to, way_id), mut members) in per_edge {
+            members.sort_by(|a, b| snaps[*a].fraction.total_cmp(&snaps[*b].fraction));
+            let edge_length = snaps[members[0]].edge_length;
+            self.split.insert((from.min(to), from.max(to), way_id));
+
+            let mut prev = from;
+            let mut prev_fraction = 0.0;
+            for i in members {
+                let snap = &snaps[i];
+                if prev != from && snap.fraction - prev_fraction <= SNAP_END_EPSILON {
+                    // same spot as the previous snap, share its virtual node
+                    indices[i] = prev;
+                    continue;
+                }
+                let idx = self.add_virtual_node(Node::new(0, snap.lat, snap.lon), (from, to));
+                self.link(prev, idx, way_id, (snap.fraction - prev_fraction) * edge_length);
+                indices[i] = idx;
+                prev = idx;
+                prev_fraction = snap.fraction;
+            }
+            self.link(prev, to, way_id, (1.0 - prev_fraction) * edge_length);
+        }
+
+        indices
+    }
+
+    fn add_virtual_node(&mut self, node: Node, on: (usize, usize)) -> usize {
+        self.virtual_nodes.push(node);
+        self.virtual_adj.push(Vec::new());
+        self.virtual_edges.push(on);
+        self.node_count() - 1
+    }
+
+    fn link(&mut self, u: usize, v: usize, way_id: u64, length_m: f64) {
+        let edge_data = EdgeData { way_id, length_m };
+        let to_v = Neighbor { osm_id: self.node(v).id(), node_index: v, edge_data };
+        let to_u = Neighbor { osm_id: self.node(u).id(), node_index: u, edge_data };
+        self.adjacency_mut(u).push(to_v);
+        self.adjacency_mut(v).push(to_u);
+    }
+
+    fn adjacency_mut(&mut self, idx: usize) -> &mut Vec<Neighbor> {
+        let base = self.graph.nodes().len();
+        if idx < base {
+            self.extra_adj.entry(idx).or_default()
+        } else {
+            &mut self.virtual_adj[idx - base]
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Straight road 0 --(100)-- 1 along the equator
+    fn build_line() -> Graph {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.001)];
+        let edge = EdgeData { way_id: 7, length_m: 100.0 };
+        let adj = vec![
+            vec![Neighbor { osm_id: 2, node_index: 1, edge_data: edge }],
+            vec![Neighbor { osm_id: 1, node_index: 0, edge_data: edge }],
+        ];
+        Graph::new(nodes, adj)
+    }
+
+    fn snap_at(fraction: f64) -> EdgeSnap {
+        EdgeSnap { from: 0, to: 1, way_id: 7, fraction, lat: 0.0, lon: 0.001 * fraction, distance: 0.0, edge_length: 100.0 }
+    }
+
+    #[test]
+    fn split_inserts_virtual_node_with_partial_lengths() {
+        let graph = build_line();
+        let mut query = QueryGraph::new(&graph);
+        let idx = query.split_edges(&[snap_at(0.25)]);
+        assert_eq!(idx, vec![2]);
+        assert!(query.is_virtual(2));
+
+        let lengths: Vec<(usize, f64)> = query.neighbors(2).map(|n| (n.node_index, n.edge_data.length_m)).collect();
+        assert_eq!(lengths, vec![(0, 25.0), (1, 75.0)]);
+        // the real nodes reach each other over the virtual node only
+        let around: Vec<usize> = query.neighbors(0).chain(query.neighbors(1)).map(|n| n.node_index).collect();
+        assert_eq!(around, vec![2, 2]);
+        // the shared graph is untouched
+        assert_eq!(graph.adj()[0].len(), 1);
+        assert!(query.is_split_edge(1, 0));
+    }
+
+    #[test]
+    fn snaps_on_same_edge_are_chained() {
+        let graph = build_line();
+        let mut query = QueryGraph::new(&graph);
+        let idx = query.split_edges(&[snap_at(0.8), snap_at(0.2)]);
+        assert_eq!(idx, vec![3, 2]);
+
+        let between: Vec<(usize, f64)> = query.neighbors(2).map(|n| (n.node_index, n.edge_data.length_m)).collect();
+        assert_eq!(between.len(), 2);
+        assert_eq!(between[0].0, 0);
+        assert!((between[0].1 - 20.0).abs() < 1e-9);
+        assert_eq!(between[1].0, 3);
+        assert!((between[1].1 - 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn identical_and_end_snaps_reuse_nodes() {
+        let graph = build_line();
+        let mut query = QueryGraph::new(&graph);
+        let idx = query.split_edges(&[snap_at(0.5), snap_at(0.5), snap_at(0.0), snap_at(1.0)]);
+        assert_eq!(idx, vec![2, 2, 0, 1]);
+        assert_eq!(query.node_count(), 3);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::budget::Budget;
+use crate::graph::{BasicState, Graph, check_snap_distance};
+use crate::model::{Path, PathsResponse, RouteError, SearchOutcome};
+use crate::query::QueryGraph;
+
+impl Graph {
+    /// Shortest path between two node indices, `None` if `goal` can't be reached
+    pub fn shortest_path(&self, start: usize, goal: usize) -> Option<Path> {
+        let query = QueryGraph::new(self);
+        let (path, distance) = query.shortest_path(start, goal)?;
+        Some(query.convert_to_path(&path, distance))
+    }
+
+    /// Snaps both coordinates onto the graph and returns the shortest path
+    /// between them, `paths` is empty when they are not connected.
+    pub fn get_shortest_path(&self, start_lat: f64, start_lon: f64, goal_lat: f64, goal_lon: f64, max_snap_distance: Option<f64>) -> Result<PathsResponse, RouteError> {
+        // one A*, nothing a budget would stop
+        self.get_k_shortest_paths(start_lat, start_lon, goal_lat, goal_lon, 1, max_snap_distance, &Budget::default())
+    }
+
+    /// The `k` shortest loopless paths between two node indices, shortest first
+    pub fn k_shortest_paths(&self, start: usize, goal: usize, k: usize) -> Vec<Path> {
+        let query = QueryGraph::new(self);
+        query
+            .k_shortest_paths(start, goal, k, &Budget::default())
+            .paths
+            .into_iter()
+            .map(|(path, distance)| query.convert_to_path(&path, distance))
+            .collect()
+    }
+
+    /// Like `get_shortest_path` but with up to `amount` alternatives, or the
+    /// ones found before `budget` ran out
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_k_shortest_paths(&self, start_lat: f64, start_lon: f64, goal_lat: f64, goal_lon: f64, amount: usize, max_snap_distance: Option<f64>, budget: &Budget) -> Result<PathsResponse, RouteError> {
+        let mut query = QueryGraph::new(self);
+        let snapped = query.snap(&[(start_lat, start_lon), (goal_lat, goal_lon)]);
+        let (start, goal) = (snapped[0], snapped[1]);
+        check_snap_distance("start", &start, max_snap_distance)?;
+        check_snap_distance("goal", &goal, max_snap_distance)?;
+
+        let outcome = query.k_shortest_paths(start.node_index, goal.node_index, amount, budget);
+        let paths = query.convert_to_paths(&outcome.paths);
+        Ok(PathsResponse { start, goal, via: Vec::new(), stopped: outcome.stopped, seed: None, paths })
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Contraction hierarchy query if the graph has one, A* otherwise.
+    /// The hierarchy knows nothing about closed roads, so queries that
+    /// avoid some always take A*.
+    pub fn shortest_path(&self, start: usize, goal: usize) -> Option<(Vec<usize>, f64)> {
+        match self.graph().contraction_hierarchy().filter(|_| self.mask.is_none()) {
+            Some(ch) => self.ch_shortest_path(ch, start, goal),
+            None => self.astar(start, goal),
+        }
+    }
+
+    /// A* from `start` to `goal`. The heuristic is the haversine distance to
+    /// the goal, or the landmark bound when the graph has landmarks and that
+    /// is larger. Neither overestimates, so the result is a shortest path.
+    pub fn astar(&self, start: usize, goal: usize) -> Option<(Vec<usize>, f64)> {
+        self.astar_filtered(start, goal, |_, _| true)
+    }
+
+    /// A* that only walks the edges `allowed(u, v)` accepts. Taking edges
+    /// away never makes a path shorter, so the heuristic stays admissible.
+    pub fn astar_filtered<F: Fn(usize, usize) -> bool>(&self, start: usize, goal: usize, allowed: F) -> Option<(Vec<usize>, f64)> {
+        let bound = self.lower_bound(goal);
+        let h = |idx: usize| bound.estimate(idx);
+
+        let mut dist: HashMap<usize, f64> = HashMap::new();
+        let mut parent: HashMap<usize, usize> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        dist.insert(start, 0.0);
+        heap.push(BasicState { node: start, dist: h(start) });
+
+        while let Some(BasicState { node: u, dist: f }) = heap.pop() {
+            let du = dist[&u];
+            if u == goal {
+                let mut path = vec![goal];
+                let mut cur = goal;
+                while let Some(&prev) = parent.get(&cur) {
+                    path.push(prev);
+                    cur = prev;
+                }
+                path.reverse();
+                return Some((path, du));
+            }
+            // stale heap entry, u was reached cheaper since
+            if f > du + h(u) {
+                continue;
+            }
+            for edge in self.neighbors(u) {
+                let v = edge.node_index;
+                if !allowed(u, v) {
+                    continue;
+                }
+                let dv = du + edge.edge_data.length_m;
+                if dist.get(&v).is_none_or(|&d| dv < d) {
+                    dist.insert(v, dv);
+                    parent.insert(v, u);
+                    heap.push(BasicState { node: v, dist: dv + h(v) });
+                }
+            }
+        }
+        None
+    }
+
+    /// Yen's algorithm: the `k` shortest loopless paths from `start` to
+    /// `goal`, shortest first.
+    ///
+    /// Every next path branches off one of the paths found so far at a spur
+    /// node. It reuses the root up to the spur node and finds the rest with
+    /// A*, avoiding the root nodes and every edge that an already found path
+    /// with the same root takes next. Split edges are skipped so no path
+    /// drives past a snapped start or goal and comes back. Every spur search
+    /// counts as a state of `budget`, when it runs out the paths found so far
+    /// are returned.
+    pub fn k_shortest_paths(&self, start: usize, goal: usize, k: usize, budget: &Budget) -> SearchOutcome {
+        let mut found: Vec<Vec<usize>> = Vec::new();
+        if k == 0 {
+            return SearchOutcome::default();
+        }
+        match self.shortest_path(start, goal) {
+            Some((path, _)) => found.push(path),
+            None => return SearchOutcome::default(),
+        }
+        let mut meter = budget.coarse_meter();
+        let mut spurs = 0;
+
+        let mut candidates: Vec<(f64, Vec<usize>)> = Vec::new();
+        let mut seen: HashSet<Vec<usize>> = HashSet::from([found[0].clone()]);
+        'search: while found.len() < k {
+            let last = found.last().unwrap();
+            for i in 0..last.len() - 1 {
+                spurs += 1;
+                let nodes: usize = candidates.iter().map(|(_, path)| path.len()).chain(found.iter().map(Vec::len)).sum();
+                let bytes = nodes * size_of::<usize>();
+                if meter.exhausted(spurs, bytes) {
+                    break 'search;
+                }
+                let (spur, root) = (last[i], &last[..=i]);
+                let banned_nodes: HashSet<usize> = root[..i].iter().copied().collect();
+                let banned_edges: HashSet<(usize, usize)> = found
+                    .iter()
+                    .filter(|path| path.len() > i + 1 && &path[..=i] == root)
+                    .map(|path| (path[i], path[i + 1]))
+                    .collect();
+
+                let allowed = |u: usize, v: usize| {
+                    !banned_nodes.contains(&v) && !banned_edges.contains(&(u, v))
+                };
+                if let Some((spur_path, _)) = self.astar_filtered(spur, goal, allowed) {
+                    let mut path = root.to_vec();
+                    path.extend_from_slice(&spur_path[1..]);
+                    if seen.insert(path.clone()) {
+                        candidates.push((self.path_length(&path), path));
+                    }
+                }
+            }
+
+            // shortest candidate next, ties broken by the node sequence to stay deterministic
+            let Some(best) = (0..candidates.len()).min_by(|&a, &b| {
+                candidates[a].0.total_cmp(&candidates[b].0).then_with(|| candidates[a].1.cmp(&candidates[b].1))
+            }) else {
+                break;
+            };
+            found.push(candidates.swap_remove(best).1);
+        }
+
+        let paths = found
+            .into_iter()
+            .map(|path| {
+                let distance = self.path_length(&path);
+                (path, distance)
+            })
+            .collect();
+        SearchOutcome { paths, stopped: meter.stopped() }
+    }
+
+    // summed segment by segment from the start
+    fn path_length(&self, path: &[usize]) -> f64 {
+        path.windows(2).fold(0.0, |acc, w| acc + self.edge_length(w[0], w[1]).unwrap())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::GraphBuilder;
+    use crate::ch::ContractionHierarchy;
+    use crate::builder::haversine_distance;
+    use crate::model::{EdgeData, Neighbor, Node, StopReason, Way};
+    use crate::test_util::Grid;
+
+    /// Two routes from 1 to 4, the northern one is shorter:
+    ///
+    ///      2 ----- 3
+    ///     /         \
+    ///    1           4
+    ///     \         /
+    ///      5 ----- 6      (further south)
+    fn build_graph() -> Graph {
+        let n = |id: u64, lat: f64, lon: f64| Node::new(id, lat, lon);
+        let mut builder = GraphBuilder::new();
+        builder.add_way(&Way::new(1, vec![1, 2, 3, 4], vec![n(1, 0.0, 0.0), n(2, 0.001, 0.001), n(3, 0.001, 0.002), n(4, 0.0, 0.003)]));
+        builder.add_way(&Way::new(2, vec![1, 5, 6, 4], vec![n(1, 0.0, 0.0), n(5, -0.003, 0.001), n(6, -0.003, 0.002), n(4, 0.0, 0.003)]));
+        builder.build()
+    }
+
+    fn ids(path: &Path) -> Vec<u64> {
+        path.nodes.iter().map(|n| n.id()).collect()
+    }
+
+    #[test]
+    fn finds_the_shorter_route() {
+        let graph = build_graph();
+        let path = graph.shortest_path(0, 3).unwrap();
+        assert_eq!(ids(&path), vec![1, 2, 3, 4]);
+
+        let expected: f64 = [(0.0, 0.0, 0.001, 0.001), (0.001, 0.001, 0.001, 0.002), (0.001, 0.002, 0.0, 0.003)]
+            .iter()
+            .map(|&(a, b, c, d)| haversine_distance(a, b, c, d))
+            .sum();
+        assert!((path.distance - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn matches_dijkstra_distances() {
+        let graph = build_graph();
+        let query = QueryGraph::new(&graph);
+        for start in 0..graph.nodes().len() {
+            let (dist, _) = query.dijkstra(start, None);
+            for (goal, expected) in dist.iter().enumerate() {
+                let (_, d) = query.astar(start, goal).unwrap();
+                assert!((d - expected).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn landmarks_give_the_same_distances() {
+        let graph = build_graph().with_landmarks(2);
+        let query = QueryGraph::new(&graph);
+        for start in 0..graph.nodes().len() {
+            let (dist, _) = query.dijkstra(start, None);
+            for (goal, expected) in dist.iter().enumerate() {
+                let (_, d) = query.astar(start, goal).unwrap();
+                assert!((d - expected).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn uses_the_contraction_hierarchy() {
+        let graph = build_graph();
+        let ch = ContractionHierarchy::build(&graph);
+        let graph = graph.with_contraction_hierarchy(ch);
+        let path = graph.shortest_path(0, 3).unwrap();
+        assert_eq!(ids(&path), vec![1, 2, 3, 4]);
+        let res = graph.get_shortest_path(0.0011, 0.0015, -0.0031, 0.0012, Some(50.0)).unwrap();
+        assert_eq!(ids(&res.paths[0]), vec![0, 2, 1, 5, 0]);
+    }
+
+    #[test]
+    fn same_start_and_goal() {
+        let graph = build_graph();
+        let path = graph.shortest_path(2, 2).unwrap();
+        assert_eq!(path.nodes.len(), 1);
+        assert_eq!(path.distance, 0.0);
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let nodes = vec![Node::new(1, 0.0, 0.0), Node::new(2, 0.0, 0.1), Node::new(3, 0.0, 0.2)];
+        let edge = EdgeData { way_id: 1, length_m: 1.0 };
+        let adj = vec![
+            vec![Neighbor { osm_id: 2, node_index: 1, edge_data: edge }],
+            vec![Neighbor { osm_id: 1, node_index: 0, edge_data: edge }],
+            vec![],
+        ];
+        let graph = Graph::new(nodes, adj);
+        assert!(graph.shortest_path(0, 2).is_none());
+    }
+
+    #[test]
+    fn snapped_shortest_path_ends_on_the_markers() {
+        let graph = build_graph();
+        let res = graph.get_shortest_path(0.0011, 0.0015, -0.0031, 0.0012, Some(50.0)).unwrap();
+        assert_eq!(res.paths.len(), 1);
+        let path = &res.paths[0];
+        // from the middle of 2-3 back over 1 to the middle of 5-6
+        assert_eq!(ids(path), vec![0, 2, 1, 5, 0]);
+        assert!((path.nodes[0].lon() - 0.0015).abs() < 1e-9);
+        assert!((path.nodes[4].lon() - 0.0012).abs() < 1e-9);
+    }
+
+    /// 3 × 3 grid with distinct edge lengths
+    fn build_grid() -> Graph {
+        let mut length = 100.0;
+        Grid::new(3, 3).build(|_, _, _| {
+            length += 7.0;
+            Some(EdgeData { way_id: 1, length_m: length })
+        })
+    }
+
+    // lengths of all simple paths from start to goal, sorted
+    fn all_simple_paths(graph: &Graph, start: usize, goal: usize) -> Vec<f64> {
+        fn walk(graph: &Graph, u: usize, goal: usize, length: f64, visited: &mut Vec<usize>, out: &mut Vec<f64>) {
+            if u == goal {
+                out.push(length);
+                return;
+            }
+            for n in &graph.adj()[u] {
+                if !visited.contains(&n.node_index) {
+                    visited.push(n.node_index);
+                    walk(graph, n.node_index, goal, length + n.edge_data.length_m, visited, out);
+                    visited.pop();
+                }
+            }
+        }
+        let mut out = Vec::new();
+        walk(graph, start, goal, 0.0, &mut vec![start], &mut out);
+        out.sort_by(f64::total_cmp);
+        out
+    }
+
+    #[test]
+    fn k_shortest_match_brute_force() {
+        let graph = build_grid();
+        for (start, goal) in [(0, 8), (1, 7), (4, 2)] {
+            let expected = all_simple_paths(&graph, start, goal);
+            let paths = graph.k_shortest_paths(start, goal, 10);
+            assert_eq!(paths.len(), 10.min(expected.len()));
+            for (path, length) in paths.iter().zip(&expected) {
+                assert!((path.distance - length).abs() < 1e-6);
+                // loopless
+                let mut ids = ids(path);
+                ids.sort();
+                ids.dedup();
+                assert_eq!(ids.len(), path.nodes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn k_shortest_stop_when_the_budget_runs_out() {
+        let graph = build_grid();
+        let budget = Budget { max_states: Some(3), ..Default::default() };
+        let outcome = QueryGraph::new(&graph).k_shortest_paths(0, 8, 10, &budget);
+        assert_eq!(outcome.stopped, Some(StopReason::StateLimit));
+        assert!(!outcome.paths.is_empty() && outcome.paths.len() < 10);
+        // still shortest first
+        assert!(outcome.paths.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+
+    #[test]
+    fn k_shortest_returns_what_exists() {
+        let graph = build_graph();
+        let paths = graph.k_shortest_paths(0, 3, 5);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(ids(&paths[0]), vec![1, 2, 3, 4]);
+        assert_eq!(ids(&paths[1]), vec![1, 5, 6, 4]);
+        assert!(paths[0].distance < paths[1].distance);
+        assert!(graph.k_shortest_paths(0, 3, 0).is_empty());
+    }
+
+    #[test]
+    fn snapped_k_shortest_paths() {
+        let graph = build_graph();
+        let res = graph.get_k_shortest_paths(0.0011, 0.0015, -0.0031, 0.0012, 3, Some(50.0), &Budget::default()).unwrap();
+        // over node 1 or around over node 4
+        assert_eq!(res.paths.len(), 2);
+        assert_eq!(ids(&res.paths[0]), vec![0, 2, 1, 5, 0]);
+        assert_eq!(ids(&res.paths[1]), vec![0, 3, 4, 6, 0]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::graph::Graph;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+}
+
+/// Summary of what ended up in a `Graph`
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphStats {
+    pub nodes: usize,
+    pub directed_edges: usize, // entries in the adjacency list
+    pub undirected_edges: usize, // road segments, each counted once
+    pub length_per_highway_m: BTreeMap<String, f64>, // ways without a highway tag are "unknown"
+    pub total_length_m: f64,
+    pub degree_distribution: BTreeMap<usize, usize>, // degree -> number of nodes
+    pub bounding_box: Option<BoundingBox>,
+    pub components: usize,
+    pub largest_component_nodes: usize,
+    pub isolated_nodes: usize,
+}
+
+impl Graph {
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let first = self.nodes.first()?;
+        let mut bbox = BoundingBox { min_lat: first.lat(), min_lon: first.lon(), max_lat: first.lat(), max_lon: first.lon() };
+        for node in &self.nodes {
+            bbox.min_lat = bbox.min_lat.min(node.lat());
+            bbox.min_lon = bbox.min_lon.min(node.lon());
+            bbox.max_lat = bbox.max_lat.max(node.lat());
+            bbox.max_lon = bbox.max_lon.max(node.lon());
+        }
+        Some(bbox)
+    }
+
+    /// Connected component id of every node, edges are treated as undirected
+    pub fn components(&self) -> Vec<usize> {
+        let n = self.nodes.len();
+        let mut reverse: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (u, neighbors) in self.adj.iter().enumerate() {
+            for neighbor in neighbors {
+                reverse[neighbor.node_index].push(u);
+            }
+        }
+
+        let mut component = vec![usize::MAX; n];
+        let mut count = 0;
+        let mut stack = Vec::new();
+        for root in 0..n {
+            if component[root] != usize::MAX {
+                continue;
+            }
+            component[root] = count;
+            stack.push(root);
+            while let Some(u) = stack.pop() {
+                let forward = self.adj[u].iter().map(|neighbor| neighbor.node_index);
+                for v in forward.chain(reverse[u].iter().copied()) {
+                    if component[v] == usize::MAX {
+                        component[v] = count;
+                        stack.push(v);
+                    }
+                }
+            }
+            count += 1;
+        }
+        component
+    }
+
+    pub fn stats(&self) -> GraphStats {
+        let mut degree_distribution: BTreeMap<usize, usize> = BTreeMap::new();
+        for neighbors in &self.adj {
+            *degree_distribution.entry(neighbors.len()).or_default() += 1;
+        }
+
+        let segments = self.segments();
+        let mut length_per_highway_m: BTreeMap<String, f64> = BTreeMap::new();
+        for (_, _, edge) in &segments {
+            let highway = self.tag(edge.edge_data.way_id, "highway").unwrap_or("unknown");
+            *length_per_highway_m.entry(highway.to_string()).or_default() += edge.edge_data.length_m;
+        }
+
+        let component = self.components();
+        let mut component_sizes: Vec<usize> = vec![0; component.iter().map(|c| c + 1).max().unwrap_or(0)];
+        for c in component {
+            component_sizes[c] += 1;
+        }
+
+        GraphStats {
+            nodes: self.nodes.len(),
+            directed_edges: self.adj.iter().map(Vec::len).sum(),
+            undirected_edges: segments.len(),
+            total_length_m: length_per_highway_m.values().sum(),
+            length_per_highway_m,
+            isolated_nodes: degree_distribution.get(&0).copied().unwrap_or(0),
+            degree_distribution,
+            bounding_box: self.bounding_box(),
+            components: component_sizes.len(),
+            largest_component_nodes: component_sizes.iter().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::builder::GraphBuilder;
+    use crate::model::{Node, Way};
+
+    fn tags(highway: &str) -> HashMap<String, String> {
+        HashMap::from([("highway".to_string(), highway.to_string())])
+    }
+
+    /// Two roads sharing node 2 plus a separate track:
+    ///
+    ///   1 --(residential)-- 2 --(primary)-- 3        4 --(track)-- 5
+    fn build_graph() -> Graph {
+        let n = |id: u64, lon: f64| Node::new(id, 0.0, lon);
+        let mut builder = GraphBuilder::new();
+        builder.add_way(&Way::new(10, vec![1, 2], vec![n(1, 0.0), n(2, 0.01)]).with_tags(tags("residential")));
+        builder.add_way(&Way::new(11, vec![2, 3], vec![n(2, 0.01), n(3, 0.02)]).with_tags(tags("primary")));
+        builder.add_way(&Way::new(12, vec![4, 5], vec![n(4, 0.05), n(5, 0.06)]));
+        builder.build()
+    }
+
+    #[test]
+    fn counts_nodes_and_edges() {
+        let stats = build_graph().stats();
+        assert_eq!(stats.nodes, 5);
+        assert_eq!(stats.directed_edges, 6);
+        assert_eq!(stats.undirected_edges, 3);
+        assert_eq!(stats.degree_distribution, BTreeMap::from([(1, 4), (2, 1)]));
+        assert_eq!(stats.isolated_nodes, 0);
+    }
+
+    #[test]
+    fn sums_length_per_highway_class() {
+        let stats = build_graph().stats();
+        let classes: Vec<&str> = stats.length_per_highway_m.keys().map(String::as_str).collect();
+        assert_eq!(classes, vec!["primary", "residential", "unknown"]);
+        // every segment spans 0.01° of longitude on the equator, about 1112 m
+        for length in stats.length_per_highway_m.values() {
+            assert!((length - 1111.95).abs() < 1.0);
+        }
+        assert!((stats.total_length_m - 3.0 * 1111.95).abs() < 3.0);
+    }
+
+    #[test]
+    fn finds_components_and_bbox() {
+        let stats = build_graph().stats();
+        assert_eq!(stats.components, 2);
+        assert_eq!(stats.largest_component_nodes, 3);
+        assert_eq!(
+            stats.bounding_box,
+            Some(BoundingBox { min_lat: 0.0, min_lon: 0.0, max_lat: 0.0, max_lon: 0.06 })
+        );
+    }
+
+    #[test]
+    fn empty_graph() {
+        let stats = Graph::new(Vec::new(), Vec::new()).stats();
+        assert_eq!(stats.nodes, 0);
+        assert_eq!(stats.components, 0);
+        assert!(stats.bounding_box.is_none());
+    }
+}
@@ -0,0 +1,62 @@
+use crate::builder::haversine_distance;
+use crate::graph::Graph;
+use crate::model::{EdgeData, Neighbor, Node};
+
+/// rows × cols grid near the equator, node `row * cols + col` with roads to
+/// its horizontal and vertical neighbours
+pub(crate) struct Grid {
+    rows: usize,
+    cols: usize,
+    spacing: f64, // degrees between neighbours
+    first_id: u64, // OSM id of node 0, the others count up from it
+}
+
+impl Grid {
+    pub(crate) fn new(rows: usize, cols: usize) -> Self {
+        Grid { rows, cols, spacing: 0.001, first_id: 0 }
+    }
+
+    pub(crate) fn with_spacing(mut self, spacing: f64) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub(crate) fn with_first_id(mut self, first_id: u64) -> Self {
+        self.first_id = first_id;
+        self
+    }
+
+    /// Nodes and adjacency, for tests that add more before building.
+    /// `edge` gets both ends of a road and their haversine distance, None
+    /// leaves the road out. Roads are visited by node, right before down.
+    pub(crate) fn parts(&self, mut edge: impl FnMut(usize, usize, f64) -> Option<EdgeData>) -> (Vec<Node>, Vec<Vec<Neighbor>>) {
+        let (rows, cols) = (self.rows, self.cols);
+        let nodes: Vec<Node> = (0..rows * cols)
+            .map(|i| Node::new(self.first_id + i as u64, (i / cols) as f64 * self.spacing, (i % cols) as f64 * self.spacing))
+            .collect();
+        let mut adj = vec![Vec::new(); rows * cols];
+        for u in 0..rows * cols {
+            for v in [u + 1, u + cols] {
+                if v >= rows * cols || (v == u + 1 && v % cols == 0) {
+                    continue;
+                }
+                let length_m = haversine_distance(nodes[u].lat(), nodes[u].lon(), nodes[v].lat(), nodes[v].lon());
+                if let Some(edge_data) = edge(u, v, length_m) {
+                    adj[u].push(Neighbor { osm_id: nodes[v].id(), node_index: v, edge_data });
+                    adj[v].push(Neighbor { osm_id: nodes[u].id(), node_index: u, edge_data });
+                }
+            }
+        }
+        (nodes, adj)
+    }
+
+    pub(crate) fn build(&self, edge: impl FnMut(usize, usize, f64) -> Option<EdgeData>) -> Graph {
+        let (nodes, adj) = self.parts(edge);
+        Graph::new(nodes, adj)
+    }
+}
+
+/// N×N grid 0.001° (about 111 m) apart, haversine lengths, all on way 1
+pub(crate) fn build_grid(n: usize) -> Graph {
+    Grid::new(n, n).build(|_, _, length_m| Some(EdgeData { way_id: 1, length_m }))
+}
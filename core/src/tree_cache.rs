@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::graph::Graph;
+use crate::query::QueryGraph;
+
+// cutoffs are rounded up to a multiple of this, so requests of about the same
+// length to one goal share a tree
+const CUTOFF_STEP_M: f64 = 1000.0;
+
+/// Reverse Dijkstra tree towards one goal on the plain graph: the road
+/// distance of every node to the goal and the next node on the way there,
+/// exact for every node up to `cutoff` away
+#[derive(Debug)]
+pub struct ReverseTree {
+    pub dist: Vec<f64>,
+    pub parent: Vec<Option<usize>>,
+    pub cutoff: f64,
+}
+
+impl ReverseTree {
+    fn bytes(&self) -> usize {
+        self.dist.len() * size_of::<f64>() + self.parent.len() * size_of::<Option<usize>>()
+    }
+}
+
+/// Reverse trees of recently used goals, shared by every request on one
+/// graph. The least recently used trees are dropped once there are more than
+/// `max_entries` of them or they take more than `max_bytes` together.
+pub struct TreeCache {
+    max_entries: usize,
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    trees: HashMap<usize, (Arc<ReverseTree>, u64)>, // goal -> tree and when it was last used
+    clock: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+/// How well the cache is doing since the server started
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub hit_rate: f64, // 0 before the first lookup
+}
+
+impl TreeCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        TreeCache { max_entries, max_bytes, entries: Mutex::new(Entries::default()) }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        let lookups = entries.hits + entries.misses;
+        CacheStats {
+            entries: entries.trees.len(),
+            bytes: entries.bytes,
+            hits: entries.hits,
+            misses: entries.misses,
+            evictions: entries.evictions,
+            hit_rate: if lookups == 0 { 0.0 } else { entries.hits as f64 / lookups as f64 },
+        }
+    }
+
+    /// The tree towards `goal` if one reaching at least `cutoff` is cached,
+    /// otherwise it is computed and kept
+    pub fn tree(&self, graph: &Graph, goal: usize, cutoff: f64) -> Arc<ReverseTree> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let now = entries.clock;
+            if let Some((tree, last_used)) = entries.trees.get_mut(&goal)
+                && tree.cutoff >= cutoff
+            {
+                *last_used = now;
+                let tree = tree.clone();
+                entries.hits += 1;
+                return tree;
+            }
+            entries.misses += 1;
+        }
+
+        // computed without holding the lock, other requests go on meanwhile
+        let cutoff = (cutoff / CUTOFF_STEP_M).ceil().max(1.0) * CUTOFF_STEP_M;
+        let (dist, parent) = QueryGraph::new(graph).dijkstra(goal, Some(cutoff));
+        let tree = Arc::new(ReverseTree { dist, parent, cutoff });
+        self.insert(goal, tree.clone());
+        tree
+    }
+
+    fn insert(&self, goal: usize, tree: Arc<ReverseTree>) {
+        if tree.bytes() > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let now = entries.clock;
+        match entries.trees.get(&goal) {
+            // another request got further meanwhile
+            Some((cached, _)) if cached.cutoff >= tree.cutoff => return,
+            Some((cached, _)) => entries.bytes -= cached.bytes(),
+            None => {}
+        }
+        entries.bytes += tree.bytes();
+        entries.trees.insert(goal, (tree, now));
+
+        while entries.trees.len() > self.max_entries || entries.bytes > self.max_bytes {
+            let oldest = entries.trees.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(&goal, _)| goal).unwrap();
+            let (evicted, _) = entries.trees.remove(&oldest).unwrap();
+            entries.bytes -= evicted.bytes();
+            entries.evictions += 1;
+        }
+    }
+}
+
+impl Graph {
+    /// Keeps reverse Dijkstra trees of recent goals for the path searches.
+    /// The trees refer to node indices, so do this after any reordering.
+    pub fn with_tree_cache(mut self, cache: TreeCache) -> Self {
+        self.tree_cache = Some(cache);
+        self
+    }
+
+    pub fn tree_cache(&self) -> Option<&TreeCache> {
+        self.tree_cache.as_ref()
+    }
+}
+
+impl QueryGraph<'_> {
+    /// Same as `dijkstra(goal, Some(cutoff))`, but served from the graph's
+    /// tree cache when there is one. Cached trees only know the real nodes,
+    /// virtual nodes get the distance through the ends of their split edge.
+    /// Queries with closed roads or a virtual goal always compute their own.
+    pub(crate) fn reverse_tree(&self, goal: usize, cutoff: f64) -> (Vec<f64>, Vec<Option<usize>>) {
+        let Some(cache) = self.graph().tree_cache() else {
+            return self.dijkstra(goal, Some(cutoff));
+        };
+        if self.mask.is_some() || self.is_virtual(goal) {
+            return self.dijkstra(goal, Some(cutoff));
+        }
+
+        let tree = cache.tree(self.graph(), goal, cutoff);
+        let mut dist = tree.dist.clone();
+        let mut parent = tree.parent.clone();
+        dist.resize(self.node_count(), f64::INFINITY);
+        parent.resize(self.node_count(), None);
+        for v in self.graph().nodes().len()..self.node_count() {
+            let (reach, back) = self.virtual_reach(v);
+            let best = reach
+                .iter()
+                .filter(|(end, _)| !self.is_virtual(**end))
+                .map(|(&end, d)| (end, d + dist[end]))
+                .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            if let Some((end, d)) = best {
+                // first step from v towards that end
+                let mut next = end;
+                while back[&next] != v {
+                    next = back[&next];
+                }
+                dist[v] = d;
+                parent[v] = Some(next);
+            }
+        }
+        // real nodes the tree leads over a split edge go to the virtual node
+        // next to them instead, the query hides the edge itself
+        for v in self.graph().nodes().len()..self.node_count() {
+            for u in self.neighbors(v).map(|n| n.node_index).filter(|&u| !self.is_virtual(u)) {
+                if let Some(p) = parent[u]
+                    && self.edge_length(u, p).is_none()
+                    && self.split_edge_of(v).is_some_and(|(a, b)| (a, b) == (u, p) || (b, a) == (u, p))
+                {
+                    parent[u] = Some(v);
+                }
+            }
+        }
+        (dist, parent)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::build_grid;
+
+    #[test]
+    fn cached_trees_match_a_fresh_dijkstra() {
+        let graph = build_grid(5).with_tree_cache(TreeCache::new(4, 1 << 20));
+        let mut query = QueryGraph::new(&graph);
+        // two snaps on one edge and one elsewhere
+        query.snap(&[(0.0, 0.0002), (0.0, 0.0007), (0.0025, 0.003)]);
+        assert_eq!(query.node_count(), 28);
+
+        for _ in 0..2 {
+            let (cached, parent) = query.reverse_tree(24, 900.0);
+            let (fresh, _) = query.dijkstra(24, Some(900.0));
+            for v in 0..query.node_count() {
+                if fresh[v] <= 900.0 {
+                    assert!((cached[v] - fresh[v]).abs() < 1e-6);
+                }
+                // every parent is one edge closer to the goal
+                if let Some(p) = parent[v] {
+                    let length = query.edge_length(v, p).unwrap();
+                    assert!((cached[v] - cached[p] - length).abs() < 1e-6);
+                }
+            }
+        }
+        let stats = graph.tree_cache().unwrap().stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (1, 1, 1));
+        assert_eq!(stats.hit_rate, 0.5);
+    }
+
+    #[test]
+    fn least_recently_used_trees_go_first() {
+        let graph = build_grid(4);
+        let cache = TreeCache::new(2, 1 << 20);
+        cache.tree(&graph, 0, 500.0);
+        cache.tree(&graph, 1, 500.0);
+        // a shorter cutoff is served by the tree already there
+        cache.tree(&graph, 0, 200.0);
+        cache.tree(&graph, 2, 500.0);
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses, stats.evictions), (2, 1, 3, 1));
+        // 1 was used least recently
+        cache.tree(&graph, 0, 500.0);
+        cache.tree(&graph, 1, 500.0);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (2, 4));
+
+        // a longer cutoff replaces the tree
+        cache.tree(&graph, 1, 1500.0);
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.tree(&graph, 1, 2000.0).cutoff >= 2000.0);
+
+        // trees that do not fit are not kept
+        let tiny = TreeCache::new(2, 100);
+        tiny.tree(&graph, 0, 500.0);
+        assert_eq!(tiny.stats().entries, 0);
+        assert_eq!(tiny.stats().bytes, 0);
+    }
+}
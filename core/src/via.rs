@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+
+use crate::diversity::DiversityFilter;
+use crate::model::{AscentRange, SearchOptions, SearchOutcome, ViaOrder};
+use crate::query::QueryGraph;
+
+// up to this many via-points every order is tried, above that the order is
+// built nearest neighbor first and then improved with 2-opt
+const BRUTE_FORCE_MAX_VIA: usize = 8;
+
+/// Order of the via-points 1..=n that makes start (0) -> via-points -> goal
+/// (n + 1) shortest, `dist[i][j]` being the distance from point i to point j.
+/// Returned as indices into the via-points, so 0..n.
+pub fn shortest_visiting_order(dist: &[Vec<f64>]) -> Vec<usize> {
+    let n = dist.len() - 2;
+    let order = if n <= BRUTE_FORCE_MAX_VIA { brute_force(dist) } else { two_opt(dist, nearest_neighbor(dist)) };
+    order.into_iter().map(|p| p - 1).collect()
+}
+
+// length of start -> order -> goal, order holding point indices
+fn route_length(dist: &[Vec<f64>], order: &[usize]) -> f64 {
+    let goal = dist.len() - 1;
+    let mut length = 0.0;
+    let mut at = 0;
+    for &p in order.iter().chain(std::iter::once(&goal)) {
+        length += dist[at][p];
+        at = p;
+    }
+    length
+}
+
+fn brute_force(dist: &[Vec<f64>]) -> Vec<usize> {
+    fn extend(dist: &[Vec<f64>], order: &mut Vec<usize>, length: f64, best: &mut (f64, Vec<usize>)) {
+        let n = dist.len() - 2;
+        let at = order.last().copied().unwrap_or(0);
+        if order.len() == n {
+            let total = length + dist[at][n + 1];
+            if total < best.0 || best.1.is_empty() {
+                *best = (total, order.clone());
+            }
+            return;
+        }
+        for p in 1..=n {
+            let next = length + dist[at][p];
+            // cannot beat the best one anymore
+            if order.contains(&p) || next >= best.0 {
+                continue;
+            }
+            order.push(p);
+            extend(dist, order, next, best);
+            order.pop();
+        }
+    }
+
+    let mut best = (f64::INFINITY, Vec::new());
+    extend(dist, &mut Vec::new(), 0.0, &mut best);
+    if best.1.is_empty() {
+        // nothing finite, every order is as bad as any other
+        best.1 = (1..dist.len() - 1).collect();
+    }
+    best.1
+}
+
+fn nearest_neighbor(dist: &[Vec<f64>]) -> Vec<usize> {
+    let mut left: HashSet<usize> = (1..dist.len() - 1).collect();
+    let mut order = Vec::with_capacity(left.len());
+    let mut at = 0;
+    while let Some(&next) = left.iter().min_by(|&&a, &&b| dist[at][a].total_cmp(&dist[at][b]).then(a.cmp(&b))) {
+        left.remove(&next);
+        order.push(next);
+        at = next;
+    }
+    order
+}
+
+// reverses stretches of the order as long as that makes the route shorter
+fn two_opt(dist: &[Vec<f64>], mut order: Vec<usize>) -> Vec<usize> {
+    let mut length = route_length(dist, &order);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 0..order.len() {
+            for j in i + 1..order.len() {
+                order[i..=j].reverse();
+                let candidate = route_length(dist, &order);
+                if candidate < length - 1e-9 {
+                    length = candidate;
+                    improved = true;
+                } else {
+                    order[i..=j].reverse();
+                }
+            }
+        }
+    }
+    order
+}
+
+impl QueryGraph<'_> {
+    /// Order in which to visit `via` between `start` and `goal`, as indices
+    /// into `via`. Unordered via-points are sorted by road distance so the
+    /// whole route is as short as possible.
+    pub fn via_order(&self, start: usize, via: &[usize], goal: usize, order: ViaOrder) -> Vec<usize> {
+        if order == ViaOrder::Ordered || via.len() < 2 {
+            return (0..via.len()).collect();
+        }
+        let mut points = vec![start];
+        points.extend_from_slice(via);
+        points.push(goal);
+        let dist: Vec<Vec<f64>> = points
+            .iter()
+            .map(|&p| {
+                let (from_p, _) = self.dijkstra(p, None);
+                points.iter().map(|&q| from_p[q]).collect()
+            })
+            .collect();
+        shortest_visiting_order(&dist)
+    }
+
+    /// Runs `search(query, from, to, target_distance, tol, options)` along
+    /// every leg between consecutive `stops` and chains the legs into whole
+    /// routes.
+    ///
+    /// The target distance and tolerance are split over the legs in
+    /// proportion to their shortest paths, so a route made of legs that are
+    /// each within their tolerance is within `tol` of `target_distance` as a
+    /// whole, legs of no length at all share it evenly. The i-th route takes
+    /// the i-th path of every leg, legs with fewer paths start over at their
+    /// first one. Routes overlapping more than `options.max_overlap` with an
+    /// earlier one are dropped, legs reused that way would share whole
+    /// stretches. A leg that runs out of budget still adds the paths it found
+    /// and the outcome says why it stopped, only a leg without any path leaves
+    /// no routes at all. An ascent range can't be split like that, every leg may climb anything up
+    /// to its upper end and the caller checks the whole route.
+    pub(crate) fn search_via<F>(&self, stops: &[usize], target_distance: f64, tol: f64, amount: usize, options: &SearchOptions, search: F) -> SearchOutcome
+    where
+        F: Fn(&QueryGraph, usize, usize, f64, f64, &SearchOptions) -> SearchOutcome,
+    {
+        let mut stops = stops.to_vec();
+        stops.dedup();
+        if stops.len() < 2 {
+            return SearchOutcome::default();
+        }
+        if stops.len() == 2 {
+            return search(self, stops[0], stops[1], target_distance, tol, options);
+        }
+
+        let mut shortest = Vec::with_capacity(stops.len() - 1);
+        for leg in stops.windows(2) {
+            match self.shortest_path(leg[0], leg[1]) {
+                Some((_, length)) => shortest.push(length),
+                None => return SearchOutcome::default(),
+            }
+        }
+        let total: f64 = shortest.iter().sum();
+        let legs_count = shortest.len() as f64;
+        let leg_options = SearchOptions { ascent: options.ascent.map(|range| AscentRange { min_m: 0.0, ..range }), ..options.clone() };
+        let mut legs = Vec::with_capacity(shortest.len());
+        let mut stopped = None;
+        for (leg, length) in stops.windows(2).zip(&shortest) {
+            let share = if total > 0.0 { length / total } else { 1.0 / legs_count };
+            let found = search(self, leg[0], leg[1], target_distance * share, tol * share, &leg_options);
+            stopped = stopped.or(found.stopped);
+            if found.paths.is_empty() {
+                // a route needs every leg
+                return SearchOutcome { paths: Vec::new(), stopped };
+            }
+            legs.push(found.paths);
+        }
+
+        let count = legs.iter().map(Vec::len).max().unwrap_or(0).min(amount);
+        let mut seen = HashSet::new();
+        let mut diversity = DiversityFilter::new(options.max_overlap);
+        let mut routes = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut path = vec![stops[0]];
+            let mut length = 0.0;
+            for found in &legs {
+                let (leg_path, leg_length) = &found[i % found.len()];
+                path.extend_from_slice(&leg_path[1..]);
+                length += leg_length;
+            }
+            if seen.insert(path.clone()) && diversity.accept(self, &path) {
+                routes.push((path, length));
+            }
+        }
+        SearchOutcome { paths: routes, stopped }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+    use crate::model::{EdgeData, Neighbor, Node, PathQuery, StopReason};
+    use crate::test_util::build_grid;
+
+    /// points on a line, their distance is the difference of their positions
+    fn line_distances(positions: &[f64]) -> Vec<Vec<f64>> {
+        positions.iter().map(|a| positions.iter().map(|b| (a - b).abs()).collect()).collect()
+    }
+
+    #[test]
+    fn brute_force_finds_the_order_along_the_way() {
+        // start at 0, goal at 10, via-points scattered in between
+        let dist = line_distances(&[0.0, 7.0, 2.0, 9.0, 4.0, 10.0]);
+        assert_eq!(shortest_visiting_order(&dist), vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn heuristic_sorts_points_on_a_line() {
+        let mut positions = vec![0.0];
+        positions.extend((1..=12).map(|i| ((i * 7) % 13) as f64));
+        positions.push(13.0);
+        let dist = line_distances(&positions);
+        assert!(positions.len() - 2 > BRUTE_FORCE_MAX_VIA);
+
+        let order: Vec<usize> = shortest_visiting_order(&dist).iter().map(|i| i + 1).collect();
+        assert_eq!(route_length(&dist, &order), 13.0);
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (1..=12).collect::<Vec<_>>());
+    }
+
+    fn query(via: Vec<(f64, f64)>, via_order: ViaOrder, target_distance: f64) -> PathQuery {
+        PathQuery {
+            start_lat: 0.0,
+            start_lon: 0.0,
+            goal_lat: 0.0,
+            goal_lon: 0.004,
+            amount: 3,
+            target_distance,
+            tol: 60.0,
+            max_snap_distance: None,
+            max_overlap: None,
+            via,
+            via_order,
+            avoid: Default::default(),
+            repeats: Default::default(),
+            budget: Default::default(),
+            seed: None,
+            ascent: None,
+            novelty: None,
+        }
+    }
+
+    #[test]
+    fn reused_legs_respect_max_overlap() {
+        let graph = build_grid(5);
+        let query = QueryGraph::new(&graph);
+        // one way along the bottom row to 4, three ways from there to 24
+        let search = |_: &QueryGraph, from: usize, _: usize, _: f64, _: f64, _: &SearchOptions| {
+            let paths = if from == 0 {
+                vec![vec![0, 1, 2, 3, 4]]
+            } else {
+                vec![vec![4, 9, 14, 19, 24], vec![4, 3, 8, 13, 18, 23, 24], vec![4, 9, 8, 13, 14, 19, 24]]
+            };
+            SearchOutcome { paths: paths.into_iter().map(|path| (path, 0.0)).collect(), stopped: None }
+        };
+        let all = query.search_via(&[0, 4, 24], 1000.0, 100.0, 3, &SearchOptions::default(), search);
+        assert_eq!(all.paths.len(), 3);
+        // they all share the whole first leg
+        let options = SearchOptions { max_overlap: Some(0.3), ..Default::default() };
+        let diverse = query.search_via(&[0, 4, 24], 1000.0, 100.0, 3, &options, search);
+        assert_eq!(diverse.paths.len(), 1);
+    }
+
+    #[test]
+    fn legs_out_of_budget_keep_what_they_found() {
+        let graph = build_grid(5);
+        let query = QueryGraph::new(&graph);
+        let search = |_: &QueryGraph, from: usize, to: usize, _: f64, _: f64, _: &SearchOptions| {
+            let stopped = (from == 0).then_some(StopReason::StateLimit);
+            SearchOutcome { paths: vec![(vec![from, to], 0.0)], stopped }
+        };
+        let res = query.search_via(&[0, 1, 2], 1000.0, 100.0, 3, &SearchOptions::default(), search);
+        assert_eq!(res.paths, vec![(vec![0, 1, 2], 0.0)]);
+        assert_eq!(res.stopped, Some(StopReason::StateLimit));
+    }
+
+    #[test]
+    fn legs_without_length_share_the_target() {
+        let edge_data = EdgeData { way_id: 1, length_m: 0.0 };
+        let adj = vec![
+            vec![Neighbor { osm_id: 1, node_index: 1, edge_data }],
+            vec![Neighbor { osm_id: 0, node_index: 0, edge_data }, Neighbor { osm_id: 2, node_index: 2, edge_data }],
+            vec![Neighbor { osm_id: 1, node_index: 1, edge_data }],
+        ];
+        let graph = Graph::new((0..3).map(|i| Node::new(i, 0.0, 0.0)).collect(), adj);
+        let targets = std::cell::RefCell::new(Vec::new());
+        QueryGraph::new(&graph).search_via(&[0, 1, 2], 100.0, 10.0, 1, &SearchOptions::default(), |_, from, to, target, tol, _| {
+            targets.borrow_mut().push((target, tol));
+            SearchOutcome { paths: vec![(vec![from, to], 0.0)], stopped: None }
+        });
+        assert_eq!(targets.into_inner(), vec![(50.0, 5.0), (50.0, 5.0)]);
+    }
+
+    fn visits_in_order(path: &[crate::model::Node], via: &[(f64, f64)]) -> bool {
+        let mut next = 0;
+        for node in path {
+            if next < via.len() && (node.lat(), node.lon()) == via[next] {
+                next += 1;
+            }
+        }
+        next == via.len()
+    }
+
+    #[test]
+    fn ordered_via_points_are_visited_in_sequence() {
+        let graph = build_grid(5);
+        // diagonally across, along the top row and diagonally back: 8 + 4 + 8 blocks
+        let via = vec![(0.004, 0.004), (0.004, 0.0)];
+        let res = graph.get_paths_bfs(&query(via.clone(), ViaOrder::Ordered, 20.0 * 111.2)).unwrap();
+        assert!(!res.paths.is_empty());
+        assert_eq!(res.via.len(), 2);
+        for path in &res.paths {
+            assert!(visits_in_order(&path.nodes, &via));
+            assert!((path.distance - 20.0 * 111.2).abs() <= 60.0);
+        }
+    }
+
+    #[test]
+    fn unordered_via_points_take_the_short_way() {
+        let graph = build_grid(5);
+        // given in the wrong order, corner (4, 0) should come before (4, 4): 12 blocks instead of 20
+        let via = vec![(0.004, 0.004), (0.004, 0.0)];
+        let res = graph.get_paths_bfs(&query(via, ViaOrder::Unordered, 12.0 * 111.2)).unwrap();
+        assert_eq!((res.via[0].lat, res.via[0].lon), (0.004, 0.0));
+        assert_eq!((res.via[1].lat, res.via[1].lon), (0.004, 0.004));
+        assert!(!res.paths.is_empty());
+        for path in &res.paths {
+            assert!(visits_in_order(&path.nodes, &[(0.004, 0.0), (0.004, 0.004)]));
+        }
+
+        // too short for the via-points in any order
+        let res = graph.get_paths_bfs(&query(vec![(0.004, 0.004)], ViaOrder::Unordered, 500.0)).unwrap();
+        assert!(res.paths.is_empty());
+    }
+}
@@ -41,6 +41,14 @@ export function MapControl({ nodesOn, setNodesOn, waysOn, setWaysOn, paths, visi
                                         <div className="text-sm text-gray-600">
                                             Distance: {path.distance.toFixed(1)} m
                                         </div>
+                                        <div className="text-sm text-gray-600">
+                                            Climbing: {path.criteria.elevation_gain_m.toFixed(0)} m, comfort: {(path.criteria.comfort * 100).toFixed(0)} %
+                                        </div>
+                                        {paths.length > 1 && (
+                                            <div className="text-sm text-gray-600">
+                                                Overlap: {(path.overlap * 100).toFixed(0)} %
+                                            </div>
+                                        )}
                                     </div>
                                 </div>
                             );
@@ -5,6 +5,9 @@ import 'maplibre-gl/dist/maplibre-gl.css';
 
 import type { Path, Way } from '../models/map';
 
+// start, goal and up to 8 via-points in between
+const MAX_MARKERS = 10;
+
 
 interface MapViewProps {
     ways: Way[];
@@ -77,7 +80,7 @@ export function MapView({ ways, markers, onMarkersChange, paths, colorMapPaths,c
 
     const handleMapLeftClick = (e: maplibregl.MapLayerMouseEvent & { originalEvent: MouseEvent}) => {
         if (e.originalEvent.button !== 0) return;  
-        if (markers.length >= 2) return; 
+        if (markers.length >= MAX_MARKERS) return; 
         onMarkersChange([
             ...markers,
             { lat: e.lngLat.lat, lon: e.lngLat.lng }
@@ -207,12 +210,12 @@ export function MapView({ ways, markers, onMarkersChange, paths, colorMapPaths,c
                     <div className="flex flex-col items-center">
                         {/* colored dot */}
                         <div
-                            className={`w-4 h-4 rounded-full border-2 border-white ${i === 0 ? 'bg-green-500' : 'bg-red-500'}`}
+                            className={`w-4 h-4 rounded-full border-2 border-white ${i === 0 ? 'bg-green-500' : i === markers.length - 1 ? 'bg-red-500' : 'bg-yellow-500'}`}
                         />
                         {/* label */}
                         <span className="mt-1 px-1 text-xs font-semibold text-white 
                             bg-black bg-opacity-50 rounded">
-                            {i === 0 ? 'Start' : 'Goal'}
+                            {i === 0 ? 'Start' : i === markers.length - 1 ? 'Goal' : `Via ${i}`}
                         </span>
                     </div>
                 </Marker>
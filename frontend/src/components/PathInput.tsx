@@ -1,5 +1,6 @@
 import { useState } from 'react';
-import {fetchPathsDfs, fetchPathsSpecialDijkstra, fetchPathsBfs} from '../util/map.ts';
+import {fetchPathsAnnealing, fetchPathsBeam, fetchPathsPareto, fetchKShortestPaths, fetchShortestPath, fetchLoops, fetchPathsDfs, fetchPathsSpecialDijkstra, fetchPathsBfs} from '../util/map.ts';
+import type { PathsResponse } from '../models/map';
 
 
 
@@ -16,25 +17,63 @@ interface PathInputProps {
 
 export function PathInput({ markers, distance, setDistance, amountPaths, setAmountPaths, setPaths }: PathInputProps) {
     const [pathAlgorithm, setPathAlgorithm] = useState<string>('dfs');
+    const [anyViaOrder, setAnyViaOrder] = useState<boolean>(false);
+    const showPaths = (res?: PathsResponse) => setPaths(res?.paths ?? []);
     function handleCalculatePath() {
+        if (pathAlgorithm.startsWith('loop_')) {
+            if (markers.length < 1) {
+                alert('Please select a start marker on the map.');
+                return;
+            }
+            const shape = pathAlgorithm.slice('loop_'.length);
+            fetchLoops(markers[0], distance, amountPaths, shape)
+                .then(showPaths)
+                .catch(console.error);
+            return;
+        }
         if (markers.length < 2) {
             // TODO: nice error handling
             alert('Please select two markers on the map.');
             return;
         }
+        // markers between the first and the last one are via-points
+        const start = markers[0];
+        const goal = markers[markers.length - 1];
+        const via = markers.slice(1, -1);
+        const viaOrder = anyViaOrder ? 'unordered' : 'ordered';
         if (pathAlgorithm === 'special_dijkstra') { 
-            fetchPathsSpecialDijkstra(markers[0], markers[1], distance, amountPaths)
-                .then(setPaths)
+            fetchPathsSpecialDijkstra(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
                 .then(console.log)
                 .catch(console.error);
         } else if (pathAlgorithm === 'dfs') {
-            fetchPathsDfs(markers[0], markers[1], distance, amountPaths)
-                .then(setPaths)
+            fetchPathsDfs(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
                 .then(console.log)
                 .catch(console.error);
+        } else if (pathAlgorithm === 'annealing') {
+            fetchPathsAnnealing(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
+                .catch(console.error);
+        } else if (pathAlgorithm === 'beam') {
+            fetchPathsBeam(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
+                .catch(console.error);
+        } else if (pathAlgorithm === 'pareto') {
+            fetchPathsPareto(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
+                .catch(console.error);
+        } else if (pathAlgorithm === 'shortest_path') {
+            fetchShortestPath(start, goal)
+                .then(showPaths)
+                .catch(console.error);
+        } else if (pathAlgorithm === 'k_shortest_paths') {
+            fetchKShortestPaths(start, goal, amountPaths)
+                .then(showPaths)
+                .catch(console.error);
         } else if (pathAlgorithm === 'bfs') {
-            fetchPathsBfs(markers[0], markers[1], distance, amountPaths)
-                .then(setPaths)
+            fetchPathsBfs(start, goal, distance, amountPaths, via, viaOrder)
+                .then(showPaths)
                 .then(console.log)
                 .catch(console.error)
         }
@@ -73,8 +112,26 @@ export function PathInput({ markers, distance, setDistance, amountPaths, setAmou
                     <option value="dfs">DFS</option>
                     <option value="bfs">BFS</option>
                     <option value="special_dijkstra">Special Dijkstra</option>
+                    <option value="annealing">Annealing</option>
+                    <option value="beam">Beam search</option>
+                    <option value="pareto">Pareto (length, climbing, comfort)</option>
+                    <option value="shortest_path">Shortest path</option>
+                    <option value="k_shortest_paths">K shortest paths</option>
+                    <option value="loop_round">Loop (round)</option>
+                    <option value="loop_elongated">Loop (elongated)</option>
+                    <option value="loop_out_and_back">Out and back</option>
+                    <option value="loop_petals">Figure eight</option>
                 </select>
             </label>
+            <label className='block mt-2'>
+                <input
+                    className='mr-1'
+                    type="checkbox"
+                    checked={anyViaOrder}
+                    onChange={(e) => setAnyViaOrder(e.target.checked)}
+                />
+                Visit via-points in any order
+            </label>
 
             <button
                 className='mt-2 w-full p-2 bg-blue-500 text-white rounded-lg hover:bg-blue-600'
@@ -14,4 +14,35 @@ export interface Path {
     id: string;
     distance: number;
     nodes: Node[];
+    overlap: number;
+    criteria: Criteria;
 }
+
+export interface Criteria {
+    elevation_gain_m: number;
+    // 0 (all trunk road) to 1 (all cycleway or footpath)
+    comfort: number;
+}
+
+export interface SnapInfo {
+    node_id: number | null;
+    way_id: number | null;
+    lat: number;
+    lon: number;
+    distance: number;
+}
+
+export interface PathsResponse {
+    start: SnapInfo;
+    goal: SnapInfo;
+    via: SnapInfo[];
+    paths: Path[];
+    // set when the search ran out of budget and the paths are only part of what it would find
+    stopped: StopReason | null;
+    // seed of a randomized search, sending it along again gives the same paths
+    seed: number | null;
+}
+
+export type StopReason = 'state_limit' | 'deadline' | 'memory_limit' | 'cancelled';
+
+export type ViaOrder = 'ordered' | 'unordered';
@@ -1,4 +1,7 @@
-import type { Path, Way } from '../models/map';
+import type { Path, ViaOrder, Way } from '../models/map';
+
+// paths sharing more than this fraction of their length with an earlier one are dropped
+const MAX_PATH_OVERLAP = 0.8;
 
 const accepted_road_types = [
     "residential",
@@ -63,7 +66,7 @@ async function fetchWays() {
     }
 }
 
-async function fetchPathsBfs(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number) {
+async function fetchPathsBfs(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered') {
     try {
         const res = await fetch('http://localhost:8000/paths_bfs', {
             mode: 'cors',
@@ -78,6 +81,9 @@ async function fetchPathsBfs(start: { lat: number, lon: number }, goal: { lat: n
                 goal_lon: goal.lon,
                 target_distance: distance,
                 amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
             }),
         });
         if (!res.ok) {
@@ -91,7 +97,7 @@ async function fetchPathsBfs(start: { lat: number, lon: number }, goal: { lat: n
 }
 
 
-async function fetchPathsSpecialDijkstra(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number) {
+async function fetchPathsSpecialDijkstra(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered') {
     try {
         const res = await fetch('http://localhost:8000/paths_special_dijkstra', {
             mode: 'cors',
@@ -106,6 +112,9 @@ async function fetchPathsSpecialDijkstra(start: { lat: number, lon: number }, go
                 goal_lon: goal.lon,
                 target_distance: distance,
                 amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
             }),
         });
         if (!res.ok) {
@@ -117,7 +126,7 @@ async function fetchPathsSpecialDijkstra(start: { lat: number, lon: number }, go
     }
 }
 
-async function fetchPathsDfs(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number) {
+async function fetchPathsDfs(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered') {
     try {
         const res = await fetch('http://localhost:8000/paths_dfs', {
             mode: 'cors',
@@ -132,6 +141,98 @@ async function fetchPathsDfs(start: { lat: number, lon: number }, goal: { lat: n
                 goal_lon: goal.lon,
                 target_distance: distance,
                 amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading routes:", err);
+    }
+}
+
+async function fetchPathsAnnealing(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered') {
+    try {
+        const res = await fetch('http://localhost:8000/paths_annealing', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                start_lat: start.lat,
+                start_lon: start.lon,
+                goal_lat: goal.lat,
+                goal_lon: goal.lon,
+                target_distance: distance,
+                amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading routes:", err);
+    }
+}
+
+async function fetchPathsBeam(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered', beamWidth?: number) {
+    try {
+        const res = await fetch('http://localhost:8000/paths_beam', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                start_lat: start.lat,
+                start_lon: start.lon,
+                goal_lat: goal.lat,
+                goal_lon: goal.lon,
+                target_distance: distance,
+                amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
+                beam_width: beamWidth,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading routes:", err);
+    }
+}
+
+
+async function fetchPathsPareto(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, distance: number, amount: number, via: { lat: number, lon: number }[] = [], viaOrder: ViaOrder = 'ordered') {
+    try {
+        const res = await fetch('http://localhost:8000/paths_pareto', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                start_lat: start.lat,
+                start_lon: start.lon,
+                goal_lat: goal.lat,
+                goal_lon: goal.lon,
+                target_distance: distance,
+                amount: amount,
+                max_overlap: MAX_PATH_OVERLAP,
+                via: via,
+                via_order: viaOrder,
             }),
         });
         if (!res.ok) {
@@ -144,6 +245,82 @@ async function fetchPathsDfs(start: { lat: number, lon: number }, goal: { lat: n
 }
 
 
-export {fetchPathsDfs, fetchPathsBfs, fetchPathsSpecialDijkstra, fetchWays, assignColorsPaths, assignColorsWays};
+async function fetchLoops(start: { lat: number, lon: number }, distance: number, amount: number, shape: string) {
+    try {
+        const res = await fetch('http://localhost:8000/loops', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                lat: start.lat,
+                lon: start.lon,
+                target_distance: distance,
+                amount: amount,
+                shape: shape,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading loops:", err);
+    }
+}
+
+
+async function fetchShortestPath(start: { lat: number, lon: number }, goal: { lat: number, lon: number }) {
+    try {
+        const res = await fetch('http://localhost:8000/shortest_path', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                start_lat: start.lat,
+                start_lon: start.lon,
+                goal_lat: goal.lat,
+                goal_lon: goal.lon,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading shortest path:", err);
+    }
+}
+
+async function fetchKShortestPaths(start: { lat: number, lon: number }, goal: { lat: number, lon: number }, amount: number) {
+    try {
+        const res = await fetch('http://localhost:8000/k_shortest_paths', {
+            mode: 'cors',
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                start_lat: start.lat,
+                start_lon: start.lon,
+                goal_lat: goal.lat,
+                goal_lon: goal.lon,
+                amount: amount,
+            }),
+        });
+        if (!res.ok) {
+            throw new Error("Server error ${res.status}: ${res.statusText}");
+        }
+        return res.json();
+    } catch (err) {
+        console.error("Error loading k shortest paths:", err);
+    }
+}
+
+
+export {fetchPathsAnnealing, fetchPathsBeam, fetchPathsPareto, fetchKShortestPaths, fetchShortestPath, fetchLoops, fetchPathsDfs, fetchPathsBfs, fetchPathsSpecialDijkstra, fetchWays, assignColorsPaths, assignColorsWays};
 
 